@@ -0,0 +1,33 @@
+fn main() {
+  #[cfg(feature = "grpc")]
+  generate_grpc_service();
+}
+
+/// Generates the server of the `Orchestrator` service of `proto/orchestrator.proto` for the
+/// messages declared in `src/grpc_api.rs`, without needing `protoc` to build.
+#[cfg(feature = "grpc")]
+fn generate_grpc_service() {
+  use tonic_build::manual::{Builder, Method, Service};
+
+  let method = |name: &str, route_name: &str, input_type: &str, output_type: &str| {
+    Method::builder()
+      .name(name)
+      .route_name(route_name)
+      .input_type(format!("crate::grpc_api::{}", input_type))
+      .output_type(format!("crate::grpc_api::{}", output_type))
+      .codec_path("tonic::codec::ProstCodec")
+  };
+
+  let service = Service::builder()
+    .name("Orchestrator")
+    .package("process_orchestrator.v1")
+    .method(method("status", "Status", "StatusRequest", "StatusResponse").build())
+    .method(method("control", "Control", "ControlRequest", "ControlResponse").build())
+    .method(method("logs", "Logs", "LogsRequest", "LogsResponse").build())
+    .method(method("stream_events", "StreamEvents", "StreamEventsRequest", "StreamMessage").server_streaming().build())
+    .build();
+
+  Builder::new().build_client(false).compile(&[service]);
+
+  println!("cargo:rerun-if-changed=build.rs");
+}
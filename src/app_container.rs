@@ -0,0 +1,130 @@
+use crate::process_token::create_well_known_sid;
+
+use serde::{Deserialize, Serialize};
+use std::ffi::OsStr;
+use std::os::windows::ffi::OsStrExt;
+use std::ptr::null_mut;
+use winapi::shared::minwindef::DWORD;
+use winapi::shared::winerror::{ERROR_ALREADY_EXISTS, FACILITY_WIN32, HRESULT_FROM_WIN32, SUCCEEDED};
+use winapi::shared::ntdef::HRESULT;
+use winapi::um::securitybaseapi::FreeSid;
+use winapi::um::userenv::{CreateAppContainerProfile, DeriveAppContainerSidFromAppContainerName};
+use winapi::um::winnt::{
+  PSID, SECURITY_CAPABILITIES, SECURITY_MAX_SID_SIZE, SE_GROUP_ENABLED, SID_AND_ATTRIBUTES, WELL_KNOWN_SID_TYPE,
+  WinCapabilityDocumentsLibrarySid, WinCapabilityEnterpriseAuthenticationSid, WinCapabilityInternetClientServerSid,
+  WinCapabilityInternetClientSid, WinCapabilityMusicLibrarySid, WinCapabilityPicturesLibrarySid,
+  WinCapabilityPrivateNetworkClientServerSid, WinCapabilityRemovableStorageSid, WinCapabilitySharedUserCertificatesSid,
+  WinCapabilityVideosLibrarySid,
+};
+
+/// What a process in an AppContainer is allowed beyond its own container, as declared by UWP apps.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum AppContainerCapability {
+  /// Outbound connections to the internet.
+  InternetClient,
+  /// Inbound and outbound connections to the internet.
+  InternetClientServer,
+  /// Inbound and outbound connections on home and work networks, e.g. to `localhost` peers.
+  PrivateNetworkClientServer,
+  PicturesLibrary,
+  VideosLibrary,
+  MusicLibrary,
+  DocumentsLibrary,
+  SharedUserCertificates,
+  EnterpriseAuthentication,
+  RemovableStorage,
+}
+
+impl AppContainerCapability {
+  fn get_sid_type(&self) -> WELL_KNOWN_SID_TYPE {
+    match self {
+      AppContainerCapability::InternetClient => WinCapabilityInternetClientSid,
+      AppContainerCapability::InternetClientServer => WinCapabilityInternetClientServerSid,
+      AppContainerCapability::PrivateNetworkClientServer => WinCapabilityPrivateNetworkClientServerSid,
+      AppContainerCapability::PicturesLibrary => WinCapabilityPicturesLibrarySid,
+      AppContainerCapability::VideosLibrary => WinCapabilityVideosLibrarySid,
+      AppContainerCapability::MusicLibrary => WinCapabilityMusicLibrarySid,
+      AppContainerCapability::DocumentsLibrary => WinCapabilityDocumentsLibrarySid,
+      AppContainerCapability::SharedUserCertificates => WinCapabilitySharedUserCertificatesSid,
+      AppContainerCapability::EnterpriseAuthentication => WinCapabilityEnterpriseAuthenticationSid,
+      AppContainerCapability::RemovableStorage => WinCapabilityRemovableStorageSid,
+    }
+  }
+}
+
+/// AppContainer a process is sandboxed in, with the capabilities it is granted.
+pub struct AppContainer {
+  sid: PSID,
+  /// Referenced by the capabilities.
+  _capability_sids: Vec<[u8; SECURITY_MAX_SID_SIZE]>,
+  capabilities: Vec<SID_AND_ATTRIBUTES>,
+}
+
+impl AppContainer {
+  /// Creates the profile of the AppContainer the first time it is used, or uses the one created
+  /// before.
+  pub fn create(name: &str, capabilities: &[AppContainerCapability]) -> std::io::Result<Self> {
+    let mut capability_sids = capabilities
+      .iter()
+      .map(|x| create_well_known_sid(x.get_sid_type()))
+      .collect::<std::io::Result<Vec<[u8; SECURITY_MAX_SID_SIZE]>>>()?;
+
+    let mut capabilities = capability_sids
+      .iter_mut()
+      .map(|x| SID_AND_ATTRIBUTES { Sid: x.as_mut_ptr() as PSID, Attributes: SE_GROUP_ENABLED })
+      .collect::<Vec<SID_AND_ATTRIBUTES>>();
+
+    let name_wide = to_wide(name);
+    let description_wide = to_wide(&format!("Process Orchestrator sandbox for {}", name));
+    let mut sid: PSID = null_mut();
+
+    unsafe {
+      let mut result = CreateAppContainerProfile(
+        name_wide.as_ptr(),
+        name_wide.as_ptr(),
+        description_wide.as_ptr(),
+        capabilities.as_mut_ptr(),
+        capabilities.len() as DWORD,
+        &mut sid);
+
+      if result == HRESULT_FROM_WIN32(ERROR_ALREADY_EXISTS) {
+        result = DeriveAppContainerSidFromAppContainerName(name_wide.as_ptr(), &mut sid);
+      }
+
+      if !SUCCEEDED(result) {
+        return Err(get_hresult_error(result));
+      }
+    }
+
+    Ok(Self { sid, _capability_sids: capability_sids, capabilities })
+  }
+
+  /// What to create the process with for it to run in the AppContainer, valid while this is.
+  pub fn get_security_capabilities(&mut self) -> SECURITY_CAPABILITIES {
+    SECURITY_CAPABILITIES {
+      AppContainerSid: self.sid,
+      Capabilities: self.capabilities.as_mut_ptr(),
+      CapabilityCount: self.capabilities.len() as DWORD,
+      Reserved: 0,
+    }
+  }
+}
+
+impl Drop for AppContainer {
+  fn drop(&mut self) {
+    unsafe { FreeSid(self.sid); }
+  }
+}
+
+fn get_hresult_error(result: HRESULT) -> std::io::Error {
+  if (result >> 16) & 0x1FFF == FACILITY_WIN32 {
+    return std::io::Error::from_raw_os_error(result & 0xFFFF);
+  }
+
+  std::io::Error::other(format!("HRESULT {:#010x}", result))
+}
+
+fn to_wide(value: &str) -> Vec<u16> {
+  OsStr::new(value).encode_wide().chain(std::iter::once(0)).collect()
+}
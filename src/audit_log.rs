@@ -0,0 +1,88 @@
+use serde::Serialize;
+use chrono::Utc;
+use std::error::Error;
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::path::PathBuf;
+use log::error;
+use winapi::shared::minwindef::DWORD;
+use winapi::um::winbase::GetUserNameW;
+
+/// Maximum length of a user name, from lmcons.h, plus its terminator.
+const USER_NAME_CAPACITY: usize = 257;
+
+/// A control action requested from outside the orchestrator, one JSON object per line of the
+/// audit file.
+#[derive(Debug, Serialize, Clone)]
+pub struct AuditRecord {
+  pub time: String,
+  /// `pipe`, `http`, `service_control` or `cli`.
+  pub channel: String,
+  /// Who requested it, as far as the channel can tell.
+  pub caller: String,
+  pub operation: String,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub target: Option<String>,
+  /// `ok`, or the error the action failed with.
+  pub result: String,
+}
+
+/// Appends the control actions requested through the control pipe, the management API, the service
+/// controls and the CLI to a file that is never truncated or rotated by the orchestrator.
+#[derive(Debug, Clone)]
+pub struct AuditLog {
+  file_path: PathBuf,
+}
+
+impl AuditLog {
+  pub fn new(service_name: Option<&str>) -> Self {
+    Self {
+      file_path: get_audit_file_path(service_name),
+    }
+  }
+
+  pub fn record(&self, channel: &str, caller: &str, operation: &str, target: Option<&str>, result: Result<(), &str>) {
+    let record = AuditRecord {
+      time: Utc::now().to_rfc3339(),
+      channel: channel.to_string(),
+      caller: caller.to_string(),
+      operation: operation.to_string(),
+      target: target.map(|x| x.to_string()),
+      result: result.err().unwrap_or("ok").to_string(),
+    };
+
+    if let Err(write_error) = self.append(&record) {
+      error!("AuditLog: Could not write to [{}]: {}", self.file_path.display(), write_error);
+    }
+  }
+
+  fn append(&self, record: &AuditRecord) -> Result<(), Box<dyn Error>> {
+    let mut file = OpenOptions::new().create(true).append(true).open(&self.file_path)?;
+    writeln!(file, "{}", serde_json::to_string(record)?)?;
+
+    Ok(())
+  }
+}
+
+/// The audit file is kept next to the executable, named after it (e.g. `process-orchestrator.audit.log`),
+/// or after the instance when it has a service name.
+pub fn get_audit_file_path(service_name: Option<&str>) -> PathBuf {
+  let executable_path = std::env::current_exe().unwrap();
+  let executable_stem = executable_path.file_stem().unwrap().to_str().unwrap();
+  let audit_file_stem = service_name.unwrap_or(executable_stem);
+  executable_path.with_file_name(format!("{}.audit.log", audit_file_stem))
+}
+
+/// Name of the user the current thread runs as, or impersonates.
+pub fn get_user_name() -> Option<String> {
+  let mut user_name = [0u16; USER_NAME_CAPACITY];
+  let mut user_name_length = USER_NAME_CAPACITY as DWORD;
+
+  let is_found = unsafe { GetUserNameW(user_name.as_mut_ptr(), &mut user_name_length) != 0 };
+  if !is_found {
+    return None;
+  }
+
+  // The length includes the terminator.
+  Some(String::from_utf16_lossy(&user_name[..(user_name_length as usize).saturating_sub(1)]))
+}
@@ -0,0 +1,273 @@
+use std::path::PathBuf;
+use structopt::StructOpt;
+
+#[derive(StructOpt, Debug, Clone)]
+#[structopt(
+  name = "process-orchestrator",
+  about = "Keeps processes up and running using desired-state-configuration")]
+pub struct CliOptions {
+  /// Config directories or glob patterns (e.g. `conf.d/**/*.yml`). Can be given several times;
+  /// when two locations define a process with the same name, the later location wins.
+  #[structopt(
+    short = "c",
+    long = "config-directory",
+    env = "PROCESS_ORCHESTRATOR_CONFIG_DIRECTORY",
+    number_of_values = 1,
+    use_delimiter = true,
+    value_delimiter = ";")]
+  pub config_locations: Vec<String>,
+
+  /// URL of a config bundle fetched in addition to the config locations, taking precedence over
+  /// them. It is polled for changes, which are applied to the running processes
+  #[structopt(long = "config-url", env = "PROCESS_ORCHESTRATOR_CONFIG_URL")]
+  pub config_url: Option<String>,
+
+  /// Seconds between polls of the `--config-url`
+  #[structopt(long = "config-poll-interval-secs", default_value = "60")]
+  pub config_poll_interval_secs: u64,
+
+  /// Environment whose overlay files (`<environment>.overlay.yml`) are applied to the configs
+  #[structopt(short = "e", long = "environment", env = "PROCESS_ORCHESTRATOR_ENVIRONMENT")]
+  pub environment: Option<String>,
+
+  /// Only manage processes with one of these tags
+  #[structopt(long = "only", number_of_values = 1)]
+  pub only_tags: Vec<String>,
+
+  /// Don't manage processes with any of these tags
+  #[structopt(long = "exclude", number_of_values = 1)]
+  pub exclude_tags: Vec<String>,
+
+  #[structopt(long = "verbose")]
+  pub verbose: bool,
+
+  /// Run in the foreground, stopping on Ctrl-C, without trying to connect to the service control
+  /// manager first
+  #[structopt(long = "console")]
+  pub console: bool,
+
+  /// Name of this orchestrator instance, so several can run side by side: the Windows service it
+  /// runs as, the name of its log and state files, and its default config directory (a
+  /// subdirectory of the executable directory)
+  #[structopt(long = "service-name", env = "PROCESS_ORCHESTRATOR_SERVICE_NAME")]
+  pub service_name: Option<String>,
+
+  #[structopt(subcommand)]
+  pub command: Option<CliCommand>,
+
+  /// Directory the orchestrator was started from, which relative config locations given on its
+  /// command line are resolved against. The service is started from the system directory, so its
+  /// own are resolved against the executable directory instead.
+  #[structopt(skip)]
+  pub working_directory: Option<PathBuf>,
+}
+
+#[derive(StructOpt, Debug, Clone)]
+pub enum CliCommand {
+  /// Encrypts a value with DPAPI, for use as `!encrypted <value>` in a config file
+  Encrypt {
+    value: String,
+
+    /// Allow any account on this machine to decrypt the value, rather than only the current one
+    #[structopt(long = "machine")]
+    machine: bool,
+  },
+
+  /// Checks every config file and reports the problems found, exiting non-zero if there are any
+  Validate,
+
+  /// Lists the process configs as they are loaded, after defaults, templates, overlays and
+  /// variable substitution
+  List {
+    /// Print the configs as JSON, with every effective value
+    #[structopt(long = "json")]
+    json: bool,
+  },
+
+  /// Writes a commented sample config showing every supported field
+  #[structopt(name = "generate-config", alias = "init")]
+  GenerateConfig {
+    /// Name of the process in the sample
+    #[structopt(long = "name", default_value = "my_process")]
+    name: String,
+
+    /// File to write the sample to, relative to the executable directory. Prints it when omitted
+    #[structopt(short = "o", long = "output", parse(from_os_str))]
+    output: Option<PathBuf>,
+  },
+
+  /// Enables a process disabled at runtime, or every process selected by `--only` / `--exclude`;
+  /// the state persists across restarts
+  Enable {
+    name: Option<String>,
+  },
+
+  /// Stops a process, or every process selected by `--only` / `--exclude`, and keeps it from
+  /// starting until it is enabled again; the state persists across restarts
+  Disable {
+    name: Option<String>,
+  },
+
+  /// Lists the processes managed by the running orchestrator, with their PID, state, uptime,
+  /// memory and restarts
+  Status {
+    /// Print the processes as JSON
+    #[structopt(long = "json")]
+    json: bool,
+
+    /// Redraw the table in place every given number of seconds (2 when not given), until Ctrl+C
+    #[structopt(long = "watch")]
+    watch: Option<Option<u64>>,
+
+    /// Print the end of the output of the process instead, as kept with `output_buffer_kbs`
+    #[structopt(long = "logs")]
+    logs: Option<String>,
+  },
+
+  /// Starts a process stopped with `stop` in the running orchestrator
+  Start {
+    name: String,
+  },
+
+  /// Stops a process in the running orchestrator, keeping it stopped until `start` or until the
+  /// orchestrator restarts
+  Stop {
+    name: String,
+  },
+
+  /// Restarts a process in the running orchestrator
+  Restart {
+    name: String,
+  },
+
+  /// Prints the end of the log file of a process in the running orchestrator and follows it as it
+  /// is written, rotated or moved to a new file when the process restarts
+  Tail {
+    name: String,
+
+    /// Instance of a process running several replicas
+    #[structopt(long = "instance", default_value = "0")]
+    instance: usize,
+
+    /// Number of lines printed before following
+    #[structopt(short = "n", long = "lines", default_value = "10")]
+    lines: usize,
+  },
+
+  /// Shows the processes of the running orchestrator live, sortable by CPU, memory, uptime or
+  /// restarts, with keys to start, stop and restart them
+  Top,
+
+  /// Has the running orchestrator reload its configs, restarting only the processes whose config
+  /// changed
+  Reload,
+
+  /// Installs the orchestrator as a Windows service, run with the config options given to this
+  /// command
+  #[structopt(name = "install-service")]
+  InstallService(InstallServiceOptions),
+
+  /// Stops and removes the Windows service
+  #[structopt(name = "uninstall-service")]
+  UninstallService {
+    /// Name of the service. Defaults to `service.name` in `orchestrator.yml`, or else the
+    /// executable name
+    #[structopt(long = "name")]
+    name: Option<String>,
+  },
+}
+
+#[derive(StructOpt, Debug, Clone)]
+pub struct InstallServiceOptions {
+  /// Name of the service. Defaults to `service.name` in `orchestrator.yml`, or else the
+  /// executable name
+  #[structopt(long = "name")]
+  pub name: Option<String>,
+
+  /// Name shown in the services console. Defaults to `service.display_name` in
+  /// `orchestrator.yml`, or else the service name
+  #[structopt(long = "display-name")]
+  pub display_name: Option<String>,
+
+  /// Description shown in the services console. Defaults to `service.description` in
+  /// `orchestrator.yml`
+  #[structopt(long = "description")]
+  pub description: Option<String>,
+
+  #[structopt(long = "start-type", default_value = "auto", possible_values = &["auto", "manual", "disabled"])]
+  pub start_type: String,
+
+  /// Account the service runs as (e.g. `.\svc-orchestrator` or `NT AUTHORITY\NetworkService`).
+  /// Defaults to LocalSystem
+  #[structopt(long = "account")]
+  pub account: Option<String>,
+
+  #[structopt(long = "password")]
+  pub password: Option<String>,
+
+  /// Start the service a short while after the other auto-start services, with `--start-type auto`
+  #[structopt(long = "delayed-auto-start")]
+  pub delayed_auto_start: bool,
+
+  /// Windows service the orchestrator depends on, started before it (e.g. `MSSQLSERVER`). Can be
+  /// given several times
+  #[structopt(long = "depends-on", number_of_values = 1)]
+  pub depends_on: Vec<String>,
+
+  /// Have the service control manager restart the orchestrator this many seconds after it crashes
+  #[structopt(long = "restart-on-failure-secs")]
+  pub restart_on_failure_secs: Option<u64>,
+
+  /// Command line run when the orchestrator keeps crashing (the third failure when it is also
+  /// restarted, every failure otherwise)
+  #[structopt(long = "failure-command")]
+  pub failure_command: Option<String>,
+
+  /// Seconds without a crash after which the failure count is reset
+  #[structopt(long = "failure-reset-period-secs")]
+  pub failure_reset_period_secs: Option<u64>,
+
+  /// Seconds Windows waits for the processes to stop gracefully when shutting down (its default
+  /// is 180)
+  #[structopt(long = "preshutdown-timeout-secs")]
+  pub preshutdown_timeout_secs: Option<u64>,
+}
+
+impl CliOptions {
+  /// Locations the process configs are loaded from, in order of increasing precedence. Relative
+  /// paths are resolved against the `working_directory`, or else the executable directory, which
+  /// is the current directory once the orchestrator has started. A named instance defaults to its
+  /// own subdirectory of the executable directory.
+  pub fn get_config_locations(&self) -> std::io::Result<Vec<PathBuf>> {
+    let current_directory = std::env::current_dir()?;
+
+    if self.config_locations.is_empty() {
+      return match &self.service_name {
+        Some(service_name) => Ok(vec![current_directory.join(service_name)]),
+        None => Ok(vec![current_directory]),
+      };
+    }
+
+    let base_directory = self.working_directory.as_ref().unwrap_or(&current_directory);
+
+    let config_locations = self.config_locations
+      .iter()
+      .map(|config_location| base_directory.join(config_location))
+      .collect();
+
+    Ok(config_locations)
+  }
+
+  pub fn has_tag_filters(&self) -> bool {
+    !self.only_tags.is_empty() || !self.exclude_tags.is_empty()
+  }
+
+  /// Whether a process with these tags is selected by the `--only` and `--exclude` filters.
+  pub fn is_selected(&self, tags: &[String]) -> bool {
+    if !self.only_tags.is_empty() && !tags.iter().any(|x| self.only_tags.contains(x)) {
+      return false;
+    }
+
+    !tags.iter().any(|x| self.exclude_tags.contains(x))
+  }
+}
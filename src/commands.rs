@@ -0,0 +1,624 @@
+use crate::audit_log::{AuditLog, get_user_name};
+use crate::cli_options::{CliOptions, CliCommand, InstallServiceOptions};
+use crate::config::{find_config_files, is_layer_file, load_config_file, load_config_layers, load_stateful_process_configs, validate_config};
+use crate::control_server::{ControlRequest, ProcessStatus, get_control_pipe_name, send_control_request};
+use crate::errors::OrchestratorError;
+use crate::remote_config::RemoteConfigSource;
+use crate::secrets::encrypt_value;
+use crate::state_store::{get_state_file_path, load_state, save_state};
+use crate::top::{enable_virtual_terminal_output, run_top};
+use crate::orchestrator_config::{load_orchestrator_config, ServiceRecoveryConfig};
+use crate::windows_service_host::{get_service_name, install_service, uninstall_service, ServiceInstallation, DEFAULT_SERVICE_DESCRIPTION};
+
+use std::collections::HashMap;
+use std::error::Error;
+use std::ffi::OsString;
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::PathBuf;
+use std::time::Duration;
+use chrono::Local;
+use windows_service::service::ServiceStartType;
+
+const SAMPLE_CONFIG: &str = include_str!("sample_config.yml");
+
+/// How far from the end of a log file `tail` looks for the lines it prints first.
+const TAIL_READ_BACK_BYTES: u64 = 1024 * 1024;
+
+/// How often `status --watch` redraws the table when no interval is given.
+const DEFAULT_STATUS_WATCH_INTERVAL_SECS: u64 = 2;
+
+pub fn run_command(cli_options: &CliOptions, command: &CliCommand) -> Result<(), Box<dyn Error>> {
+  match command {
+    CliCommand::Encrypt { value, machine } => run_encrypt(value, *machine),
+    CliCommand::Validate => run_validate(cli_options),
+    CliCommand::List { json } => run_list(cli_options, *json),
+    CliCommand::GenerateConfig { name, output } => run_generate_config(name, output.as_ref()),
+    CliCommand::Enable { name } => run_set_enabled(cli_options, name.as_deref(), true),
+    CliCommand::Disable { name } => run_set_enabled(cli_options, name.as_deref(), false),
+    CliCommand::InstallService(install_service_options) => run_install_service(cli_options, install_service_options),
+    CliCommand::UninstallService { name } => run_uninstall_service(cli_options, name.as_deref()),
+    CliCommand::Status { json, logs: Some(name), .. } => run_status_logs(cli_options, name, *json),
+    CliCommand::Status { json, watch: None, .. } => run_status(cli_options, *json),
+    CliCommand::Status { watch: Some(interval_secs), .. } => run_status_watch(cli_options, interval_secs.unwrap_or(DEFAULT_STATUS_WATCH_INTERVAL_SECS)),
+    CliCommand::Start { name } => run_control(cli_options, ControlRequest::Start { name: name.clone() }, name, "starting"),
+    CliCommand::Stop { name } => run_control(cli_options, ControlRequest::Stop { name: name.clone() }, name, "stopping"),
+    CliCommand::Restart { name } => run_control(cli_options, ControlRequest::Restart { name: name.clone() }, name, "restarting"),
+    CliCommand::Reload => run_reload(cli_options),
+    CliCommand::Tail { name, instance, lines } => run_tail(cli_options, name, *instance, *lines),
+    CliCommand::Top => run_top(&get_cli_control_pipe_name(cli_options)?),
+  }
+}
+
+fn run_install_service(cli_options: &CliOptions, install_service_options: &InstallServiceOptions) -> Result<(), Box<dyn Error>> {
+  let orchestrator_config = load_orchestrator_config()?;
+  let service_config = &orchestrator_config.service;
+
+  let default_name = get_service_name(None, &orchestrator_config);
+  let name = install_service_options.name.clone()
+    .or_else(|| cli_options.service_name.clone())
+    .unwrap_or_else(|| default_name.clone());
+
+  let display_name = install_service_options.display_name.as_ref()
+    .or(service_config.display_name.as_ref())
+    .unwrap_or(&name)
+    .clone();
+
+  let description = install_service_options.description.as_deref()
+    .or(service_config.description.as_deref())
+    .unwrap_or(DEFAULT_SERVICE_DESCRIPTION)
+    .to_string();
+
+  let start_type = match install_service_options.start_type.as_str() {
+    "manual" => ServiceStartType::OnDemand,
+    "disabled" => ServiceStartType::Disabled,
+    _ => ServiceStartType::AutoStart,
+  };
+
+  let mut dependencies = service_config.dependencies.clone().unwrap_or_default();
+  dependencies.extend(install_service_options.depends_on.iter().cloned());
+
+  let recovery_config = service_config.recovery.clone().unwrap_or_default();
+  let recovery = ServiceRecoveryConfig {
+    restart_delay_secs: install_service_options.restart_on_failure_secs.or(recovery_config.restart_delay_secs),
+    command: install_service_options.failure_command.clone().or(recovery_config.command),
+    reset_period_secs: install_service_options.failure_reset_period_secs.or(recovery_config.reset_period_secs),
+  };
+
+  let launch_arguments = get_service_launch_arguments(cli_options, (name != default_name).then_some(name.as_str()))?;
+
+  let service_installation = ServiceInstallation {
+    name,
+    display_name,
+    description,
+    start_type,
+    delayed_auto_start: install_service_options.delayed_auto_start || service_config.delayed_auto_start.unwrap_or(false),
+    dependencies,
+    account: install_service_options.account.clone(),
+    password: install_service_options.password.clone(),
+    launch_arguments,
+    recovery,
+    preshutdown_timeout_secs: install_service_options.preshutdown_timeout_secs.or(service_config.preshutdown_timeout_secs),
+  };
+
+  install_service(&service_installation)?;
+  println!("Service [{}] installed", &service_installation.name);
+
+  Ok(())
+}
+
+fn run_uninstall_service(cli_options: &CliOptions, name: Option<&str>) -> Result<(), Box<dyn Error>> {
+  let service_name = match name {
+    Some(name) => name.to_string(),
+    None => get_service_name(cli_options.service_name.as_deref(), &load_orchestrator_config()?),
+  };
+
+  uninstall_service(&service_name)?;
+  println!("Service [{}] uninstalled", service_name);
+
+  Ok(())
+}
+
+/// Arguments the service is started with, so it runs with the config options given when it was
+/// installed, and as a named instance when it is not installed under the default name.
+fn get_service_launch_arguments(cli_options: &CliOptions, service_name: Option<&str>) -> std::io::Result<Vec<OsString>> {
+  let mut launch_arguments = Vec::<OsString>::new();
+
+  if let Some(service_name) = service_name {
+    launch_arguments.push(OsString::from("--service-name"));
+    launch_arguments.push(OsString::from(service_name));
+  }
+
+  // Absolute, as the service is not started from the directory they were given relative to.
+  if !cli_options.config_locations.is_empty() {
+    for config_location in cli_options.get_config_locations()? {
+      launch_arguments.push(OsString::from("--config-directory"));
+      launch_arguments.push(config_location.into_os_string());
+    }
+  }
+
+  if let Some(config_url) = &cli_options.config_url {
+    launch_arguments.push(OsString::from("--config-url"));
+    launch_arguments.push(OsString::from(config_url));
+    launch_arguments.push(OsString::from("--config-poll-interval-secs"));
+    launch_arguments.push(OsString::from(cli_options.config_poll_interval_secs.to_string()));
+  }
+
+  if let Some(environment) = &cli_options.environment {
+    launch_arguments.push(OsString::from("--environment"));
+    launch_arguments.push(OsString::from(environment));
+  }
+
+  for only_tag in &cli_options.only_tags {
+    launch_arguments.push(OsString::from("--only"));
+    launch_arguments.push(OsString::from(only_tag));
+  }
+
+  for exclude_tag in &cli_options.exclude_tags {
+    launch_arguments.push(OsString::from("--exclude"));
+    launch_arguments.push(OsString::from(exclude_tag));
+  }
+
+  if cli_options.verbose {
+    launch_arguments.push(OsString::from("--verbose"));
+  }
+
+  Ok(launch_arguments)
+}
+
+/// Records the runtime enabled state of the selected processes. A running orchestrator notices the
+/// change to the state file and starts or stops them.
+fn run_set_enabled(cli_options: &CliOptions, name: Option<&str>, enabled: bool) -> Result<(), Box<dyn Error>> {
+  let process_names = select_process_names(cli_options, name)?;
+
+  let state_file_path = get_state_file_path(cli_options.service_name.as_deref());
+  let mut state = load_state(&state_file_path)?;
+
+  for process_name in &process_names {
+    state.enabled_overrides.insert(process_name.clone(), enabled);
+  }
+
+  let save_result = save_state(&state_file_path, &state);
+
+  let audit_log = AuditLog::new(cli_options.service_name.as_deref());
+  let caller = get_user_name().unwrap_or_else(|| String::from("unknown user"));
+  let save_error = save_result.as_ref().err().map(|x| x.to_string());
+
+  for process_name in &process_names {
+    let operation_name = if enabled { "enable" } else { "disable" };
+    audit_log.record("cli", &caller, operation_name, Some(process_name), save_error.as_deref().map_or(Ok(()), Err));
+  }
+
+  save_result?;
+
+  for process_name in &process_names {
+    println!("Process [{}] {}", process_name, if enabled { "enabled" } else { "disabled" });
+  }
+
+  Ok(())
+}
+
+fn run_status(cli_options: &CliOptions, json: bool) -> Result<(), Box<dyn Error>> {
+  let response = send_control_request(&get_cli_control_pipe_name(cli_options)?, &ControlRequest::Status)?;
+  let process_statuses = response.processes.unwrap_or_default();
+
+  if json {
+    println!("{}", serde_json::to_string_pretty(&process_statuses)?);
+    return Ok(());
+  }
+
+  print!("{}", format_status_table(&process_statuses));
+
+  Ok(())
+}
+
+/// Prints the output kept for each instance of the process, under a header per instance when it
+/// has replicas.
+fn run_status_logs(cli_options: &CliOptions, name: &str, json: bool) -> Result<(), Box<dyn Error>> {
+  let request = ControlRequest::Logs { name: name.to_string() };
+  let response = send_control_request(&get_cli_control_pipe_name(cli_options)?, &request)?;
+  let process_logs = response.logs.unwrap_or_default();
+
+  if json {
+    println!("{}", serde_json::to_string_pretty(&process_logs)?);
+    return Ok(());
+  }
+
+  for instance_logs in &process_logs {
+    if process_logs.len() > 1 {
+      println!("==> {}#{} <==", &instance_logs.name, instance_logs.instance_index);
+    }
+
+    for line in &instance_logs.lines {
+      println!("{}", line);
+    }
+  }
+
+  Ok(())
+}
+
+/// Redraws the status table in place until interrupted, showing the error instead while the
+/// orchestrator can't be reached, e.g. while the service restarts.
+fn run_status_watch(cli_options: &CliOptions, interval_secs: u64) -> Result<(), Box<dyn Error>> {
+  let pipe_name = get_cli_control_pipe_name(cli_options)?;
+  enable_virtual_terminal_output()?;
+  print!("\x1b[2J");
+
+  loop {
+    let status = match send_control_request(&pipe_name, &ControlRequest::Status) {
+      Ok(response) => format_status_table(&response.processes.unwrap_or_default()),
+      Err(status_error) => format!("{}\n", status_error),
+    };
+
+    let title = format!("Every {}s: status  {}", interval_secs, Local::now().format("%Y-%m-%d %H:%M:%S"));
+
+    // Overwrite the previous table and clear what is left of it, rather than clearing the screen
+    // first, so it doesn't flicker.
+    print!("\x1b[H{}\x1b[K\n\x1b[K\n{}\x1b[J", title, status.replace('\n', "\x1b[K\n"));
+    std::io::stdout().flush()?;
+
+    std::thread::sleep(Duration::from_secs(interval_secs.max(1)));
+  }
+}
+
+fn format_status_table(process_statuses: &[ProcessStatus]) -> String {
+  let rows = process_statuses
+    .iter()
+    .map(|x| vec![
+      x.name.clone(),
+      x.instance_index.to_string(),
+      x.id.clone().unwrap_or_default(),
+      x.pid.map(|x| x.to_string()).unwrap_or_default(),
+      x.state.clone(),
+      x.uptime_secs.map(format_duration).unwrap_or_default(),
+      x.memory_mbs.map(|x| format!("{:.1}MB", x)).unwrap_or_default(),
+      x.cpu_percent.map(|x| format!("{:.1}%", x)).unwrap_or_default(),
+      x.restarts.to_string(),
+    ])
+    .collect::<Vec<Vec<String>>>();
+
+  format_table(&["NAME", "INSTANCE", "ID", "PID", "STATE", "UPTIME", "MEMORY", "CPU", "RESTARTS"], &rows)
+}
+
+fn run_control(cli_options: &CliOptions, request: ControlRequest, name: &str, action: &str) -> Result<(), Box<dyn Error>> {
+  send_control_request(&get_cli_control_pipe_name(cli_options)?, &request)?;
+  println!("Process [{}] {}", name, action);
+
+  Ok(())
+}
+
+fn run_reload(cli_options: &CliOptions) -> Result<(), Box<dyn Error>> {
+  send_control_request(&get_cli_control_pipe_name(cli_options)?, &ControlRequest::Reload)?;
+  println!("Configs reloaded");
+
+  Ok(())
+}
+
+/// Follows the log file of a process, switching to the new file when it is rotated (truncated or
+/// replaced) or when the restarted process writes to another one. A process without a log file is
+/// followed through the output the orchestrator keeps of it.
+fn run_tail(cli_options: &CliOptions, name: &str, instance: usize, lines: usize) -> Result<(), Box<dyn Error>> {
+  let pipe_name = get_cli_control_pipe_name(cli_options)?;
+
+  let mut log_file_path = match get_process_log_file(&pipe_name, name, instance)? {
+    Some(log_file_path) => log_file_path,
+    None => return follow_output_buffer(&pipe_name, name, instance, lines),
+  };
+
+  let mut log_file = File::open(&log_file_path)?;
+  let mut log_file_created = log_file.metadata()?.created().ok();
+  let mut position = print_last_lines(&mut log_file, lines)?;
+
+  loop {
+    std::thread::sleep(Duration::from_secs(1));
+
+    // Keep following the last known file while the process is restarting.
+    if let Ok(Some(current_log_file_path)) = get_process_log_file(&pipe_name, name, instance) {
+      if current_log_file_path != log_file_path {
+        log_file_path = current_log_file_path;
+        log_file_created = None;
+      }
+    }
+
+    let metadata = match std::fs::metadata(&log_file_path) {
+      Ok(metadata) => metadata,
+      Err(_) => continue,
+    };
+
+    if metadata.created().ok() != log_file_created || metadata.len() < position {
+      log_file = File::open(&log_file_path)?;
+      log_file_created = metadata.created().ok();
+      position = 0;
+      eprintln!("==> {} <==", log_file_path.display());
+    }
+
+    if metadata.len() > position {
+      log_file.seek(SeekFrom::Start(position))?;
+      position += std::io::copy(&mut log_file, &mut std::io::stdout())?;
+    }
+  }
+}
+
+/// Follows the output kept of an instance with `output_buffer_kbs`, asking the orchestrator for it
+/// every second and printing the lines added since.
+fn follow_output_buffer(pipe_name: &str, name: &str, instance: usize, lines: usize) -> Result<(), Box<dyn Error>> {
+  let mut printed_lines = get_output_buffer_lines(pipe_name, name, instance)?
+    .ok_or_else(|| OrchestratorError::ProcessLogFileNotFound(name.to_string()))?;
+
+  for line in &printed_lines[printed_lines.len().saturating_sub(lines)..] {
+    println!("{}", line);
+  }
+
+  loop {
+    std::thread::sleep(Duration::from_secs(1));
+
+    // Keep waiting while the orchestrator restarts.
+    let current_lines = match get_output_buffer_lines(pipe_name, name, instance) {
+      Ok(Some(current_lines)) => current_lines,
+      _ => continue,
+    };
+
+    for line in &current_lines[get_new_line_index(&printed_lines, &current_lines)..] {
+      println!("{}", line);
+    }
+
+    printed_lines = current_lines;
+  }
+}
+
+/// Lines of the output kept of an instance, when its process sets `output_buffer_kbs`.
+fn get_output_buffer_lines(pipe_name: &str, name: &str, instance: usize) -> Result<Option<Vec<String>>, Box<dyn Error>> {
+  let response = send_control_request(pipe_name, &ControlRequest::Logs { name: name.to_string() })?;
+
+  Ok(response.logs
+    .unwrap_or_default()
+    .into_iter()
+    .find(|x| x.instance_index == instance)
+    .map(|x| x.lines))
+}
+
+/// Index of the first line of the output buffer not printed yet. The buffer drops its oldest lines
+/// as lines are added, so the end of the lines printed last is at its start, unless all of them
+/// were dropped.
+fn get_new_line_index(printed_lines: &[String], current_lines: &[String]) -> usize {
+  (0..=printed_lines.len().min(current_lines.len()))
+    .rev()
+    .find(|x| printed_lines[printed_lines.len() - x..] == current_lines[..*x])
+    .unwrap_or(0)
+}
+
+/// Log file the running instance of a process writes to, if any.
+fn get_process_log_file(pipe_name: &str, name: &str, instance: usize) -> Result<Option<PathBuf>, Box<dyn Error>> {
+  let response = send_control_request(pipe_name, &ControlRequest::Status)?;
+
+  let process_status = response.processes
+    .unwrap_or_default()
+    .into_iter()
+    .find(|x| x.name == name && x.instance_index == instance)
+    .ok_or_else(|| OrchestratorError::ProcessNotConfigured(name.to_string()))?;
+
+  Ok(process_status.log_file.map(PathBuf::from))
+}
+
+/// Prints the last lines of the file, returning the position its end is at.
+fn print_last_lines(file: &mut File, lines: usize) -> Result<u64, Box<dyn Error>> {
+  let length = file.metadata()?.len();
+  let start = length.saturating_sub(TAIL_READ_BACK_BYTES);
+
+  let mut contents = Vec::<u8>::new();
+  file.seek(SeekFrom::Start(start))?;
+  file.take(length - start).read_to_end(&mut contents)?;
+
+  let contents = String::from_utf8_lossy(&contents);
+  let all_lines = contents.lines().collect::<Vec<&str>>();
+
+  for line in &all_lines[all_lines.len().saturating_sub(lines)..] {
+    println!("{}", line);
+  }
+
+  Ok(length)
+}
+
+/// Control pipe of the orchestrator the CLI options refer to.
+fn get_cli_control_pipe_name(cli_options: &CliOptions) -> Result<String, Box<dyn Error>> {
+  let service_name = get_service_name(cli_options.service_name.as_deref(), &load_orchestrator_config()?);
+  Ok(get_control_pipe_name(&service_name))
+}
+
+fn print_table(headers: &[&str], rows: &[Vec<String>]) {
+  print!("{}", format_table(headers, rows));
+}
+
+fn format_table(headers: &[&str], rows: &[Vec<String>]) -> String {
+  let widths = headers
+    .iter()
+    .enumerate()
+    .map(|(index, header)| rows.iter().map(|x| x[index].len()).chain(std::iter::once(header.len())).max().unwrap_or_default())
+    .collect::<Vec<usize>>();
+
+  let format_row = |values: Vec<String>| values
+    .iter()
+    .zip(&widths)
+    .map(|(value, width)| format!("{:<width$}", value, width = width))
+    .collect::<Vec<String>>()
+    .join("  ")
+    .trim_end()
+    .to_string();
+
+  let mut table = format!("{}\n", format_row(headers.iter().map(|x| x.to_string()).collect()));
+  for row in rows {
+    table.push_str(&format!("{}\n", format_row(row.clone())));
+  }
+
+  table
+}
+
+/// Formats seconds with their two largest units, e.g. `3d 4h` or `5m 12s`.
+pub fn format_duration(duration_secs: f64) -> String {
+  let total_secs = duration_secs.max(0f64) as u64;
+  let units = [(total_secs / 86400, "d"), (total_secs / 3600 % 24, "h"), (total_secs / 60 % 60, "m"), (total_secs % 60, "s")];
+
+  let first_index = units.iter().position(|(value, _)| *value > 0).unwrap_or(units.len() - 1);
+
+  units[first_index..]
+    .iter()
+    .take(2)
+    .map(|(value, unit)| format!("{}{}", value, unit))
+    .collect::<Vec<String>>()
+    .join(" ")
+}
+
+/// Names of the processes a command operates on: the one given by name, or those selected by the
+/// `--only` / `--exclude` tag filters.
+fn select_process_names(cli_options: &CliOptions, name: Option<&str>) -> Result<Vec<String>, Box<dyn Error>> {
+  if let Some(name) = name {
+    return Ok(vec![name.to_string()]);
+  }
+
+  if !cli_options.has_tag_filters() {
+    return Err(Box::new(OrchestratorError::NoProcessesSelected()));
+  }
+
+  let configs = load_stateful_process_configs(&cli_options.get_config_locations()?, cli_options.environment.as_deref(), None)?;
+
+  let process_names = configs
+    .iter()
+    .filter(|x| cli_options.is_selected(x.tags.as_deref().unwrap_or_default()))
+    .map(|x| x.name.clone())
+    .collect::<Vec<String>>();
+
+  if process_names.is_empty() {
+    return Err(Box::new(OrchestratorError::NoProcessesSelected()));
+  }
+
+  Ok(process_names)
+}
+
+fn run_generate_config(name: &str, output: Option<&PathBuf>) -> Result<(), Box<dyn Error>> {
+  let sample_config = SAMPLE_CONFIG.replace("{name}", name);
+
+  match output {
+    Some(output_path) => {
+      if output_path.exists() {
+        return Err(Box::new(OrchestratorError::FileAlreadyExists(output_path.clone())));
+      }
+
+      std::fs::write(output_path, sample_config)?;
+      println!("Sample config written to [{}]", output_path.display());
+    }
+    None => print!("{}", sample_config),
+  }
+
+  Ok(())
+}
+
+/// Prints the effective configs, after includes, overlays and tag selection, as a table or JSON.
+fn run_list(cli_options: &CliOptions, json: bool) -> Result<(), Box<dyn Error>> {
+  let remote_config_contents = match &cli_options.config_url {
+    Some(config_url) => RemoteConfigSource::new(config_url.clone())?.fetch()?,
+    None => None,
+  };
+
+  let mut configs = load_stateful_process_configs(&cli_options.get_config_locations()?, cli_options.environment.as_deref(), remote_config_contents.as_deref())?;
+  configs.retain(|x| cli_options.is_selected(x.tags.as_deref().unwrap_or_default()));
+
+  if json {
+    println!("{}", serde_json::to_string_pretty(&configs)?);
+    return Ok(());
+  }
+
+  let state = load_state(&get_state_file_path(cli_options.service_name.as_deref()))?;
+
+  let rows = configs
+    .iter()
+    .map(|x| vec![
+      x.name.clone(),
+      state.is_enabled(&x.name, x.enabled).to_string(),
+      x.get_replicas().to_string(),
+      x.tags.as_deref().unwrap_or_default().join(","),
+      x.executable.clone(),
+    ])
+    .collect::<Vec<Vec<String>>>();
+
+  print_table(&["NAME", "ENABLED", "REPLICAS", "TAGS", "EXECUTABLE"], &rows);
+
+  Ok(())
+}
+
+/// Loads every config file, printing the problems found in each. Fails when there are any, so it
+/// can gate a deployment.
+fn run_validate(cli_options: &CliOptions) -> Result<(), Box<dyn Error>> {
+  let config_locations = cli_options.get_config_locations()?;
+  let config_file_paths = find_config_files(&config_locations)?;
+
+  let mut error_count = 0;
+
+  let layer_file_paths = config_file_paths.iter().filter(|x| is_layer_file(x)).cloned().collect::<Vec<PathBuf>>();
+  let config_layers = match load_config_layers(&layer_file_paths, cli_options.environment.as_deref()) {
+    Ok(config_layers) => config_layers,
+    Err(load_config_layers_error) => {
+      println!("error: {}", load_config_layers_error);
+      return Err(Box::new(OrchestratorError::ConfigValidationFailed(1)));
+    }
+  };
+
+  // Process names seen so far, with the location index and file that defined them.
+  let mut defined_names = HashMap::<String, (usize, PathBuf)>::new();
+
+  for (location_index, config_location) in config_locations.iter().enumerate() {
+    for config_file_path in find_config_files(std::slice::from_ref(config_location))? {
+      if is_layer_file(&config_file_path) {
+        continue;
+      }
+
+      println!("{}", config_file_path.display());
+
+      let configs = match load_config_file(&config_file_path, &config_layers) {
+        Ok(configs) => configs,
+        Err(load_config_file_error) => {
+          println!("  error: {}", load_config_file_error);
+          error_count += 1;
+          continue;
+        }
+      };
+
+      for config in configs {
+        let mut problems = validate_config(&config);
+
+        if let Some((defined_location_index, defined_file_path)) = defined_names.get(&config.name) {
+          if *defined_location_index == location_index {
+            problems.push(format!("name is already used in [{}]", defined_file_path.display()));
+          }
+          else {
+            println!("  info: [{}] overrides the config in [{}]", &config.name, defined_file_path.display());
+          }
+        }
+
+        defined_names.insert(config.name.clone(), (location_index, config_file_path.clone()));
+
+        if problems.is_empty() {
+          println!("  ok: [{}]", &config.name);
+        }
+
+        for problem in &problems {
+          println!("  error: [{}] {}", &config.name, problem);
+        }
+
+        error_count += problems.len();
+      }
+    }
+  }
+
+  if error_count > 0 {
+    return Err(Box::new(OrchestratorError::ConfigValidationFailed(error_count)));
+  }
+
+  println!("{} process config(s) valid", defined_names.len());
+  Ok(())
+}
+
+fn run_encrypt(value: &str, machine: bool) -> Result<(), Box<dyn Error>> {
+  let encrypted_value = encrypt_value(value, machine)?;
+  println!("{}", encrypted_value);
+  Ok(())
+}
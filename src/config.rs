@@ -1,43 +1,742 @@
-use crate::stateful_process::StatefulProcessConfig;
+use crate::stateful_process::{StatefulProcessConfig, StdinSource};
 use crate::errors::OrchestratorError;
+use crate::secrets::{CREDENTIAL_PREFIX, ENCRYPTED_PREFIX};
+use crate::orchestrator_config::ORCHESTRATOR_CONFIG_FILE_NAME;
+use crate::known_folders::{get_known_folder, get_home_directory};
+use crate::shell::Shell;
 
+use std::collections::HashMap;
 use std::error::Error;
-use std::path::PathBuf;
+use std::ffi::{OsStr, OsString};
+use std::os::windows::ffi::{OsStrExt, OsStringExt};
+use std::path::{Path, PathBuf};
+use std::ptr::{null, null_mut};
+use std::time::SystemTime;
+use log::{info, warn};
+use serde::Deserialize;
+use serde_yaml::Value;
+use winapi::shared::minwindef::{DWORD, MAX_PATH};
+use winapi::um::processenv::SearchPathW;
 
-pub fn load_stateful_process_configs() -> Result<Vec<StatefulProcessConfig>, Box<dyn Error>> {
-  let config_directory = std::env::current_dir()?;
+/// File name of the defaults merged into every process config found in the config locations.
+pub const DEFAULTS_FILE_NAME: &str = "defaults.yml";
 
+/// Suffix of the overlay files of an environment, e.g. `production.overlay.yml`.
+pub const OVERLAY_FILE_SUFFIX: &str = ".overlay.yml";
+
+/// Key of a process config naming the template config file it inherits from.
+const EXTENDS_KEY: &str = "extends";
+
+/// Key marking a config as a template, only used through `extends` and never run itself.
+const TEMPLATE_KEY: &str = "template";
+
+/// Watched paths with their last modified times, missing for a path that does not exist.
+pub type ModifiedTimes = Vec<(PathBuf, Option<SystemTime>)>;
+
+/// Values layered onto every process config: the defaults below it, and the overlay of the selected
+/// environment above it.
+#[derive(Debug, Clone)]
+pub struct ConfigLayers {
+  pub defaults: Value,
+  /// Per-process overrides of the environment overlay, keyed by process name.
+  pub overlay_processes: Value,
+}
+
+/// Loads the process configs from the config locations, and from the contents of a remote config
+/// bundle when one is given, which takes precedence over all the locations.
+pub fn load_stateful_process_configs(config_locations: &[PathBuf], environment: Option<&str>, remote_config_contents: Option<&str>) -> Result<Vec<StatefulProcessConfig>, Box<dyn Error>> {
   let mut results = Vec::<StatefulProcessConfig>::new();
 
-  let config_directory_entries = std::fs::read_dir(&config_directory)?;
+  let (layer_file_paths, config_file_paths): (Vec<PathBuf>, Vec<PathBuf>) = find_config_files(config_locations)?
+    .into_iter()
+    .partition(|x| is_layer_file(x));
 
-  for config_directory_entry in config_directory_entries {
-    let config_file = config_directory_entry?;
-    let config_file_name = config_file.file_name().into_string().unwrap();
-    let config_file_path = config_file.path();
+  let config_layers = load_config_layers(&layer_file_paths, environment)?;
 
-    if !config_file.metadata()?.is_file() {
-      continue;
+  for config_file_path in config_file_paths {
+    let config_file_document_result = load_config_file(&config_file_path, &config_layers);
+    if let Err(load_config_file_error) = config_file_document_result {
+      return Err(Box::new(OrchestratorError::ConfigLoadFailed(config_file_path, load_config_file_error)))
     }
 
-    if !config_file_name.ends_with(".yml") {
-      continue;
+    for config_file_document in config_file_document_result.unwrap() {
+      // Later locations take precedence over earlier ones.
+      if let Some(index) = results.iter().position(|x| x.name == config_file_document.name) {
+        info!("Config [{}]: Overridden by [{}]", &config_file_document.name, config_file_path.display());
+        results[index] = config_file_document;
+      }
+      else {
+        results.push(config_file_document);
+      }
     }
+  }
 
-    let config_file_document_result = load_config_file(&config_file_path);
-    if let Err(load_config_file_error) = config_file_document_result {
-      return Err(Box::new(OrchestratorError::ConfigLoadFailed(config_file_path, load_config_file_error)))
+  if let Some(remote_config_contents) = remote_config_contents {
+    for config_document in load_config_contents(remote_config_contents, &config_layers, None)? {
+      match results.iter().position(|x| x.name == config_document.name) {
+        Some(index) => results[index] = config_document,
+        None => results.push(config_document),
+      }
+    }
+  }
+
+  Ok(results)
+}
+
+/// Expands the config locations into the list of YAML files to load, in order of precedence.
+/// A location is either a directory (its `*.yml` files are loaded) or a glob pattern.
+pub fn find_config_files(config_locations: &[PathBuf]) -> Result<Vec<PathBuf>, Box<dyn Error>> {
+  let mut results = Vec::<PathBuf>::new();
+
+  for config_location in config_locations {
+    let config_location_string = config_location
+      .to_str()
+      .ok_or_else(|| OrchestratorError::ConfigLocationNotUnicode(config_location.clone()))?;
+
+    let mut config_file_paths = Vec::<PathBuf>::new();
+
+    if is_glob_pattern(config_location_string) {
+      for config_file_path in glob::glob(config_location_string)? {
+        config_file_paths.push(config_file_path?);
+      }
+    }
+    else {
+      for config_directory_entry in std::fs::read_dir(config_location)? {
+        config_file_paths.push(config_directory_entry?.path());
+      }
+    }
+
+    config_file_paths.sort();
+
+    let mut found_config_file = false;
+
+    for config_file_path in config_file_paths {
+      if !config_file_path.is_file() {
+        continue;
+      }
+
+      if config_file_path.extension().and_then(|x| x.to_str()) != Some("yml") {
+        continue;
+      }
+
+      if config_file_path.file_name().and_then(|x| x.to_str()) == Some(ORCHESTRATOR_CONFIG_FILE_NAME) {
+        continue;
+      }
+
+      if !results.contains(&config_file_path) {
+        results.push(config_file_path);
+      }
+
+      found_config_file = true;
     }
 
-    let config_file_document = config_file_document_result.unwrap();
-    results.push(config_file_document);
+    if !found_config_file {
+      warn!("Config location [{}]: No config files found", config_location.display());
+    }
   }
 
   Ok(results)
 }
 
-pub fn load_config_file(config_file_path: &PathBuf) -> Result<StatefulProcessConfig, Box<dyn Error>> {
+/// Returns the paths whose changes mean the configs need reloading, with their last modified
+/// times: the config files in the config locations, and the location directories themselves, which
+/// change when files are added or removed.
+pub fn get_config_files_modified(config_locations: &[PathBuf]) -> Result<ModifiedTimes, Box<dyn Error>> {
+  let mut watched_paths = find_config_files(config_locations)?;
+  watched_paths.extend(config_locations.iter().filter(|x| x.is_dir()).cloned());
+
+  let results = watched_paths
+    .into_iter()
+    .map(|x| {
+      let modified = std::fs::metadata(&x).and_then(|x| x.modified()).ok();
+      (x, modified)
+    })
+    .collect();
+
+  Ok(results)
+}
+
+/// Loads every process defined in a config file. A file can hold a single process, a
+/// `processes:` list, or several YAML documents (separated by `---`) of either form.
+pub fn load_config_file(config_file_path: &PathBuf, config_layers: &ConfigLayers) -> Result<Vec<StatefulProcessConfig>, Box<dyn Error>> {
   let config_file_contents = std::fs::read_to_string(config_file_path)?;
-  let config_file_document = serde_yaml::from_str::<StatefulProcessConfig>(config_file_contents.as_str())?;
-  Ok(config_file_document)
-}
\ No newline at end of file
+  load_config_contents(config_file_contents.as_str(), config_layers, config_file_path.parent())
+}
+
+/// Loads the processes defined in config contents. Relative paths in them are resolved against the
+/// `base_directory` (the directory of the config file), or left relative to the executable
+/// directory when there is none.
+pub fn load_config_contents(config_file_contents: &str, config_layers: &ConfigLayers, base_directory: Option<&Path>) -> Result<Vec<StatefulProcessConfig>, Box<dyn Error>> {
+  let mut results = Vec::<StatefulProcessConfig>::new();
+
+  for config_file_document in serde_yaml::Deserializer::from_str(config_file_contents) {
+    let config_file_value = Value::deserialize(config_file_document)?;
+
+    if config_file_value.is_null() {
+      continue;
+    }
+
+    let process_values = match config_file_value.get("processes") {
+      Some(processes_value) => serde_yaml::from_value::<Vec<Value>>(processes_value.clone())?,
+      None => vec![config_file_value],
+    };
+
+    for process_value in process_values {
+      if is_template(&process_value) {
+        continue;
+      }
+
+      let process_value = resolve_extends(process_value, base_directory, &mut Vec::<PathBuf>::new())?;
+      results.push(create_process_config(config_layers, process_value, base_directory)?);
+    }
+  }
+
+  Ok(results)
+}
+
+/// Merges a process config onto the template config file it `extends`, and that template onto the
+/// one it extends in turn. The template path is relative to the file that references it.
+fn resolve_extends(process_value: Value, base_directory: Option<&Path>, extended_file_paths: &mut Vec<PathBuf>) -> Result<Value, Box<dyn Error>> {
+  let template_file_path = match process_value.get(EXTENDS_KEY).and_then(|x| x.as_str()) {
+    Some(extends) => PathBuf::from(expand_path(extends)),
+    None => return Ok(process_value),
+  };
+
+  let template_file_path = match base_directory {
+    Some(base_directory) => base_directory.join(template_file_path),
+    None => template_file_path,
+  };
+
+  let canonical_template_file_path = template_file_path.canonicalize().unwrap_or_else(|_| template_file_path.clone());
+  if extended_file_paths.contains(&canonical_template_file_path) {
+    return Err(Box::new(OrchestratorError::ConfigExtendsCycle(template_file_path)));
+  }
+  extended_file_paths.push(canonical_template_file_path);
+
+  let template_value = match load_yaml_file(&template_file_path) {
+    Ok(template_value) => template_value,
+    Err(load_template_file_error) => return Err(Box::new(OrchestratorError::ConfigLoadFailed(template_file_path, load_template_file_error))),
+  };
+
+  let mut merged_value = resolve_extends(template_value, template_file_path.parent(), extended_file_paths)?;
+  merge_values(&mut merged_value, process_value);
+
+  Ok(merged_value)
+}
+
+fn is_template(process_value: &Value) -> bool {
+  process_value.get(TEMPLATE_KEY).and_then(|x| x.as_bool()).unwrap_or(false)
+}
+
+/// Merges the defaults files and the overlay files of the environment, later files taking
+/// precedence. An overlay file can hold `defaults:` and per-process overrides under `processes:`.
+pub fn load_config_layers(layer_file_paths: &[PathBuf], environment: Option<&str>) -> Result<ConfigLayers, Box<dyn Error>> {
+  let mut config_layers = ConfigLayers {
+    defaults: Value::Null,
+    overlay_processes: Value::Null,
+  };
+
+  for defaults_file_path in layer_file_paths.iter().filter(|x| is_defaults_file(x)) {
+    match load_yaml_file(defaults_file_path) {
+      Ok(defaults_value) => merge_values(&mut config_layers.defaults, defaults_value),
+      Err(load_defaults_file_error) => return Err(Box::new(OrchestratorError::ConfigLoadFailed(defaults_file_path.clone(), load_defaults_file_error))),
+    }
+  }
+
+  if let Some(environment) = environment {
+    let overlay_file_name = format!("{}{}", environment, OVERLAY_FILE_SUFFIX);
+    let overlay_file_paths = layer_file_paths
+      .iter()
+      .filter(|x| x.file_name().and_then(|x| x.to_str()) == Some(overlay_file_name.as_str()));
+
+    for overlay_file_path in overlay_file_paths {
+      let overlay_value = match load_yaml_file(overlay_file_path) {
+        Ok(overlay_value) => overlay_value,
+        Err(load_overlay_file_error) => return Err(Box::new(OrchestratorError::ConfigLoadFailed(overlay_file_path.clone(), load_overlay_file_error))),
+      };
+
+      if let Some(defaults_value) = overlay_value.get("defaults") {
+        merge_values(&mut config_layers.defaults, defaults_value.clone());
+      }
+
+      if let Some(processes_value) = overlay_value.get("processes") {
+        merge_values(&mut config_layers.overlay_processes, processes_value.clone());
+      }
+
+      info!("Config [{}]: Applied overlay for environment [{}]", overlay_file_path.display(), environment);
+    }
+  }
+
+  Ok(config_layers)
+}
+
+pub fn load_yaml_file(yaml_file_path: &PathBuf) -> Result<Value, Box<dyn Error>> {
+  let yaml_file_contents = std::fs::read_to_string(yaml_file_path)?;
+  let yaml_value = serde_yaml::from_str::<Value>(yaml_file_contents.as_str())?;
+  Ok(yaml_value)
+}
+
+/// Builds a process config from its YAML value, with any field it does not set taken from the
+/// defaults, and the environment overlay for the process applied on top.
+fn create_process_config(config_layers: &ConfigLayers, process_value: Value, base_directory: Option<&Path>) -> Result<StatefulProcessConfig, Box<dyn Error>> {
+  let overlay_value = process_value
+    .get("name")
+    .and_then(|x| x.as_str())
+    .and_then(|x| config_layers.overlay_processes.get(x))
+    .cloned();
+
+  let mut merged_value = config_layers.defaults.clone();
+  merge_values(&mut merged_value, process_value);
+
+  if let Some(overlay_value) = overlay_value {
+    merge_values(&mut merged_value, overlay_value);
+  }
+
+  if let Value::Mapping(merged_mapping) = &mut merged_value {
+    merged_mapping.remove(EXTENDS_KEY);
+    merged_mapping.remove(TEMPLATE_KEY);
+  }
+
+  untag_values(&mut merged_value);
+
+  let mut config = serde_yaml::from_value::<StatefulProcessConfig>(merged_value)?;
+  config.config_directory = base_directory.map(|x| x.to_string_lossy().to_string());
+  expand_config_environment_variables(&mut config);
+
+  if let Some(base_directory) = base_directory {
+    resolve_relative_paths(&mut config, base_directory);
+  }
+
+  Ok(config)
+}
+
+/// Makes the relative paths of a config relative to the directory of the config file defining it.
+/// A relative executable is looked for there, then in the working directory, and a bare executable
+/// name found in neither is left to be found on the `PATH`.
+fn resolve_relative_paths(config: &mut StatefulProcessConfig, base_directory: &Path) {
+  let stdin_path = match &mut config.stdin {
+    Some(StdinSource::File(path)) => Some(path),
+    _ => None,
+  };
+
+  let paths = vec![&mut config.working_directory, &mut config.log_file, &mut config.env_file]
+    .into_iter()
+    .flatten()
+    .chain(stdin_path)
+    .chain(config.restart_on_change.iter_mut().flatten());
+
+  for path in paths {
+    if Path::new(path.as_str()).is_relative() {
+      *path = base_directory.join(path.as_str()).to_string_lossy().to_string();
+    }
+  }
+
+  let executable_path = Path::new(&config.executable);
+  if executable_path.is_relative() && config.is_executable_a_path() {
+    let resolved_executable_path = std::iter::once(base_directory)
+      .chain(config.working_directory.as_deref().map(Path::new))
+      .map(|x| x.join(executable_path))
+      .find(|x| x.is_file());
+
+    match resolved_executable_path {
+      Some(resolved_executable_path) => config.executable = resolved_executable_path.to_string_lossy().to_string(),
+      None if executable_path.components().count() > 1 => config.executable = base_directory.join(executable_path).to_string_lossy().to_string(),
+      None => {}
+    }
+  }
+}
+
+/// Expands `${VAR}` and `%VAR%` references in the config values that commonly differ between
+/// machines, and `~` in its paths.
+fn expand_config_environment_variables(config: &mut StatefulProcessConfig) {
+  config.executable = expand_path(&config.executable);
+
+  if let Some(arguments) = &mut config.arguments {
+    for argument in arguments.iter_mut() {
+      *argument = expand_environment_variables(argument);
+    }
+  }
+
+  if let Some(raw_arguments) = &mut config.raw_arguments {
+    *raw_arguments = expand_environment_variables(raw_arguments);
+  }
+
+  if let Some(working_directory) = &mut config.working_directory {
+    *working_directory = expand_path(working_directory);
+  }
+
+  if let Some(log_file) = &mut config.log_file {
+    *log_file = expand_path(log_file);
+  }
+
+  if let Some(env_file) = &mut config.env_file {
+    *env_file = expand_path(env_file);
+  }
+
+  if let Some(restart_on_change) = &mut config.restart_on_change {
+    for path in restart_on_change.iter_mut() {
+      *path = expand_path(path);
+    }
+  }
+
+  if let Some(environment_variables) = &mut config.environment_variables {
+    for value in environment_variables.values_mut() {
+      *value = expand_environment_variables(value);
+    }
+  }
+}
+
+/// Longest name Windows accepts for an AppContainer profile.
+const MAX_APP_CONTAINER_NAME_LENGTH: usize = 64;
+
+/// Checks a loaded config for problems that would stop the process from starting, returning a
+/// description of each.
+pub fn validate_config(config: &StatefulProcessConfig) -> Vec<String> {
+  let mut problems = Vec::<String>::new();
+
+  if config.name.trim().is_empty() {
+    problems.push(String::from("name is empty"));
+  }
+
+  if config.executable.trim().is_empty() {
+    problems.push(String::from("executable is empty"));
+  }
+  else if config.is_executable_a_path() && find_executable(&config.executable).is_none() {
+    problems.push(format!("executable [{}] not found", &config.executable));
+  }
+
+  if let Some(shell) = config.get_shell() {
+    if find_executable(shell.get_interpreter()).is_none() {
+      problems.push(format!("shell [{}] not found", shell.get_interpreter()));
+    }
+
+    // The command line after `-Command` is joined back into the command, losing the quotes.
+    if (shell == Shell::Powershell || shell == Shell::Pwsh) && !config.is_executable_a_path() && config.arguments.is_some() {
+      problems.push(String::from("arguments are only passed to a PowerShell script, add them to the command instead"));
+    }
+  }
+
+  if let Some(working_directory) = &config.working_directory {
+    if !config.create_working_directory.unwrap_or(false) && !Path::new(working_directory).is_dir() {
+      problems.push(format!("working_directory [{}] does not exist", working_directory));
+    }
+  }
+
+  if let Some(StdinSource::File(path)) = &config.stdin {
+    if !Path::new(path).is_file() {
+      problems.push(format!("stdin file [{}] does not exist", path));
+    }
+  }
+
+  if let Some(env_file) = &config.env_file {
+    if !Path::new(env_file).is_file() {
+      problems.push(format!("env_file [{}] does not exist", env_file));
+    }
+  }
+
+  // A directory with placeholders is created when the process starts.
+  if let Some(log_file) = config.log_file.as_ref().filter(|x| !x.contains('{')) {
+    let log_directory = Path::new(log_file).parent().filter(|x| !x.as_os_str().is_empty());
+    if log_directory.map(|x| !x.is_dir()).unwrap_or(false) {
+      problems.push(format!("log_file directory [{}] does not exist", log_directory.unwrap().display()));
+    }
+  }
+
+  if config.log_max_size_mbs.map(|x| x <= 0f64).unwrap_or(false) {
+    problems.push(String::from("log_max_size_mbs must be greater than zero"));
+  }
+
+  if config.log_max_age_days.map(|x| x <= 0f64).unwrap_or(false) {
+    problems.push(String::from("log_max_age_days must be greater than zero"));
+  }
+
+  if config.log_max_size_mbs.is_none() && config.log_rotation.is_none() {
+    let retention_fields = [
+      ("log_max_files", config.log_max_files.is_some()),
+      ("log_max_age_days", config.log_max_age_days.is_some()),
+      ("log_compress", config.log_compress.is_some()),
+    ];
+
+    for (field_name, _) in retention_fields.iter().filter(|(_, is_set)| *is_set) {
+      problems.push(format!("{} needs log_max_size_mbs or log_rotation", field_name));
+    }
+  }
+
+  for event_log_rule in config.event_log_rules.iter().flatten() {
+    if let Err(pattern_error) = event_log_rule.get_line_pattern() {
+      problems.push(format!("event_log_rules pattern [{}] is not valid: {}", &event_log_rule.pattern, pattern_error));
+    }
+  }
+
+  if let Some(base_port) = config.base_port {
+    let last_port = base_port as usize + config.get_replicas().saturating_sub(1);
+    if last_port > u16::MAX as usize {
+      problems.push(format!("base_port [{}] with [{}] replicas goes beyond port {}", base_port, config.get_replicas(), u16::MAX));
+    }
+  }
+
+  if config.output_buffer_kbs == Some(0) {
+    problems.push(String::from("output_buffer_kbs must be greater than zero"));
+  }
+
+  if config.recycle_on_memory_mbs.map(|x| x <= 0f64).unwrap_or(false) {
+    problems.push(String::from("recycle_on_memory_mbs must be greater than zero"));
+  }
+
+  if config.recycle_on_duration_secs.map(|x| x <= 0f64).unwrap_or(false) {
+    problems.push(String::from("recycle_on_duration_secs must be greater than zero"));
+  }
+
+  for path in config.restart_on_change.iter().flatten() {
+    if !Path::new(path).exists() {
+      problems.push(format!("restart_on_change path [{}] does not exist", path));
+    }
+  }
+
+  if config.restart_on_change_debounce_secs.map(|x| x < 0f64).unwrap_or(false) {
+    problems.push(String::from("restart_on_change_debounce_secs must not be negative"));
+  }
+
+  if config.cpu_affinity.as_ref().map(|x| x.get_mask().is_none()).unwrap_or(false) {
+    problems.push(String::from("cpu_affinity must list processors from 0 to 63, or be a non-zero hexadecimal mask"));
+  }
+
+  if config.numa_node.is_some() && config.processor_group.is_some() {
+    problems.push(String::from("numa_node and processor_group can't both be set, as a NUMA node is in a single processor group"));
+  }
+
+  if let Some(working_set) = &config.working_set {
+    if working_set.min_mbs <= 0f64 || working_set.max_mbs < working_set.min_mbs {
+      problems.push(String::from("working_set min_mbs must be greater than 0, and max_mbs at least min_mbs"));
+    }
+  }
+
+  if config.cpu_limit_percent.map(|x| x <= 0f64 || x > 100f64).unwrap_or(false) {
+    problems.push(String::from("cpu_limit_percent must be greater than 0 and at most 100"));
+  }
+
+  if config.memory_limit_mbs.map(|x| x <= 0f64).unwrap_or(false) {
+    problems.push(String::from("memory_limit_mbs must be greater than 0"));
+  }
+
+  if let Some(app_container) = &config.app_container {
+    let name = app_container.get_name(&config.name);
+    if name.len() > MAX_APP_CONTAINER_NAME_LENGTH {
+      problems.push(format!("app_container name [{}] is longer than {} characters", name, MAX_APP_CONTAINER_NAME_LENGTH));
+    }
+  }
+
+  if let Some(run_as) = &config.run_as {
+    if run_as.username.trim().is_empty() {
+      problems.push(String::from("run_as username is empty"));
+    }
+
+    // A password is never kept in plain text in a config file.
+    if !run_as.password_ref.starts_with(ENCRYPTED_PREFIX) && !run_as.password_ref.starts_with(CREDENTIAL_PREFIX) {
+      problems.push(format!("run_as password_ref must be an {}value or a {}<target> reference", ENCRYPTED_PREFIX, CREDENTIAL_PREFIX));
+    }
+  }
+
+  problems
+}
+
+/// Finds the file an executable refers to, as an absolute path: a path, or a bare name looked up
+/// through the `PATH` environment variable (trying each of the `PATHEXT` extensions when it has
+/// none).
+pub fn find_executable(executable: &str) -> Option<PathBuf> {
+  let executable_path = Path::new(executable);
+
+  if executable_path.is_file() {
+    return std::env::current_dir().ok().map(|x| x.join(executable_path));
+  }
+
+  if executable_path.components().count() > 1 {
+    return None;
+  }
+
+  let extensions = match executable_path.extension() {
+    Some(_) => vec![String::new()],
+    None => std::env::var("PATHEXT")
+      .unwrap_or_else(|_| String::from(".COM;.EXE;.BAT;.CMD"))
+      .split(';')
+      .map(|x| x.to_string())
+      .collect(),
+  };
+
+  let path = std::env::var_os("PATH")?;
+
+  // Each extension in turn, so an `.exe` anywhere on the `PATH` is preferred to a `.bat`, as
+  // `where` does.
+  extensions
+    .iter()
+    .find_map(|x| search_path(&path, &format!("{}{}", executable, x)))
+}
+
+/// Looks for the file in the directories of the `PATH`, in order.
+fn search_path(path: &OsStr, file_name: &str) -> Option<PathBuf> {
+  let path_wide = path.encode_wide().chain(std::iter::once(0)).collect::<Vec<u16>>();
+  let file_name_wide = OsStr::new(file_name).encode_wide().chain(std::iter::once(0)).collect::<Vec<u16>>();
+  let mut found_path = vec![0u16; MAX_PATH];
+
+  loop {
+    let found_path_length = unsafe {
+      SearchPathW(path_wide.as_ptr(), file_name_wide.as_ptr(), null(), found_path.len() as DWORD, found_path.as_mut_ptr(), null_mut())
+    } as usize;
+
+    match found_path_length {
+      0 => return None,
+      // Too long for the buffer, which is given the length needed, null character included.
+      length if length > found_path.len() => found_path.resize(length, 0),
+      length => return Some(PathBuf::from(OsString::from_wide(&found_path[..length]))).filter(|x| x.is_file()),
+    }
+  }
+}
+
+/// Loads `KEY=VALUE` pairs from a dotenv-style file. Blank lines and `#` comments are skipped,
+/// an `export ` prefix is allowed, and values may be wrapped in single or double quotes.
+pub fn load_env_file(env_file_path: &Path) -> Result<HashMap<String, String>, Box<dyn Error>> {
+  let env_file_contents = std::fs::read_to_string(env_file_path)?;
+
+  let mut results = HashMap::<String, String>::new();
+
+  for (line_index, line) in env_file_contents.lines().enumerate() {
+    let line = line.trim();
+
+    if line.is_empty() || line.starts_with('#') {
+      continue;
+    }
+
+    let line = line.strip_prefix("export ").unwrap_or(line);
+
+    let (key, value) = match line.split_once('=') {
+      Some(pair) => pair,
+      None => return Err(Box::new(OrchestratorError::EnvFileLineInvalid(env_file_path.to_path_buf(), line_index + 1))),
+    };
+
+    let value = value.trim();
+    let is_quoted = value.len() >= 2
+      && ((value.starts_with('"') && value.ends_with('"')) || (value.starts_with('\'') && value.ends_with('\'')));
+
+    let value = if is_quoted { &value[1..value.len() - 1] } else { value };
+
+    results.insert(key.trim().to_string(), value.to_string());
+  }
+
+  Ok(results)
+}
+
+/// Replaces `${VAR}` and `%VAR%` with the value of the environment variable, falling back to the
+/// known folder for names such as `ProgramData` or `TEMP`. References to other variables that are
+/// not set are left untouched.
+pub fn expand_environment_variables(value: &str) -> String {
+  let mut result = String::with_capacity(value.len());
+  let mut remaining = value;
+
+  while let Some(start) = remaining.find(['$', '%']) {
+    result.push_str(&remaining[..start]);
+    remaining = &remaining[start..];
+
+    let reference = if let Some(rest) = remaining.strip_prefix("${") {
+      rest.find('}').map(|end| (&rest[..end], end + 3))
+    }
+    else if let Some(rest) = remaining.strip_prefix('%') {
+      rest.find('%').map(|end| (&rest[..end], end + 2))
+    }
+    else {
+      None
+    };
+
+    match reference {
+      Some((name, length)) if is_environment_variable_name(name) => {
+        match std::env::var(name).ok().or_else(|| get_known_folder(name).map(|x| x.to_string_lossy().to_string())) {
+          Some(variable_value) => result.push_str(variable_value.as_str()),
+          None => result.push_str(&remaining[..length]),
+        }
+        remaining = &remaining[length..];
+      }
+      _ => {
+        result.push_str(&remaining[..1]);
+        remaining = &remaining[1..];
+      }
+    }
+  }
+
+  result.push_str(remaining);
+  result
+}
+
+/// Expands environment variable references in a path, and a leading `~` to the home directory of
+/// the account the orchestrator runs as.
+pub fn expand_path(value: &str) -> String {
+  let value = expand_environment_variables(value);
+
+  let home_relative_path = match value.strip_prefix('~') {
+    Some(rest) if rest.is_empty() || rest.starts_with('/') || rest.starts_with('\\') => rest.trim_start_matches(['/', '\\']),
+    _ => return value,
+  };
+
+  match get_home_directory() {
+    Some(home_directory) => home_directory.join(home_relative_path).to_string_lossy().to_string(),
+    None => value,
+  }
+}
+
+fn is_environment_variable_name(name: &str) -> bool {
+  !name.is_empty() && name.chars().all(|c| c.is_alphanumeric() || "_().".contains(c))
+}
+
+/// Merges `overlay` into `base`. Mappings are merged key by key (so `environment_variables` are
+/// combined), any other value in the overlay replaces the one in the base.
+pub fn merge_values(base: &mut Value, overlay: Value) {
+  match (base, overlay) {
+    (Value::Mapping(base_mapping), Value::Mapping(overlay_mapping)) => {
+      for (key, overlay_value) in overlay_mapping {
+        match base_mapping.get_mut(&key) {
+          Some(base_value) => merge_values(base_value, overlay_value),
+          None => { base_mapping.insert(key, overlay_value); }
+        }
+      }
+    }
+    (base, overlay) => *base = overlay,
+  }
+}
+
+/// Removes YAML tags from the values. An `!encrypted` tag is kept as a prefix of the string, so the
+/// secret can be decrypted when the process is spawned.
+fn untag_values(value: &mut Value) {
+  match value {
+    Value::Tagged(tagged_value) => {
+      let is_encrypted = tagged_value.tag == "encrypted";
+      let mut inner_value = std::mem::replace(&mut tagged_value.value, Value::Null);
+      untag_values(&mut inner_value);
+
+      *value = match inner_value {
+        Value::String(encrypted_value) if is_encrypted => Value::String(format!("{}{}", ENCRYPTED_PREFIX, encrypted_value)),
+        inner_value => inner_value,
+      };
+    }
+    Value::Mapping(mapping) => mapping.values_mut().for_each(untag_values),
+    Value::Sequence(sequence) => sequence.iter_mut().for_each(untag_values),
+    _ => {}
+  }
+}
+
+pub fn is_defaults_file(config_file_path: &Path) -> bool {
+  config_file_path.file_name().and_then(|x| x.to_str()) == Some(DEFAULTS_FILE_NAME)
+}
+
+/// Whether the file holds values layered onto process configs (defaults or an environment
+/// overlay), rather than process configs themselves.
+pub fn is_layer_file(config_file_path: &Path) -> bool {
+  let is_overlay_file = config_file_path
+    .file_name()
+    .and_then(|x| x.to_str())
+    .map(|x| x.ends_with(OVERLAY_FILE_SUFFIX))
+    .unwrap_or(false);
+
+  is_overlay_file || is_defaults_file(config_file_path)
+}
+
+fn is_glob_pattern(config_location: &str) -> bool {
+  config_location.contains(['*', '?', '['])
+}
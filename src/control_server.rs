@@ -0,0 +1,289 @@
+use crate::audit_log::get_user_name;
+use crate::errors::OrchestratorError;
+use crate::event_pump::Event;
+
+use serde::{Deserialize, Serialize};
+use std::error::Error;
+use std::ffi::OsStr;
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::os::windows::ffi::OsStrExt;
+use std::os::windows::io::{AsRawHandle, FromRawHandle};
+use std::ptr::null_mut;
+use std::sync::mpsc::{Sender, channel};
+use std::time::Duration;
+use log::{error, trace};
+use winapi::shared::winerror::ERROR_PIPE_CONNECTED;
+use winapi::um::errhandlingapi::GetLastError;
+use winapi::um::handleapi::{CloseHandle, INVALID_HANDLE_VALUE};
+use winapi::um::winnt::HANDLE;
+use winapi::um::namedpipeapi::{ConnectNamedPipe, CreateNamedPipeW, ImpersonateNamedPipeClient};
+use winapi::um::securitybaseapi::RevertToSelf;
+use winapi::um::winbase::{GetNamedPipeClientProcessId, PIPE_ACCESS_DUPLEX, PIPE_READMODE_BYTE, PIPE_REJECT_REMOTE_CLIENTS, PIPE_TYPE_BYTE, PIPE_UNLIMITED_INSTANCES, PIPE_WAIT};
+
+const CONTROL_PIPE_BUFFER_SIZE: u32 = 64 * 1024;
+
+/// How long a control request waits for the event pump to answer it.
+const CONTROL_RESPONSE_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// A request sent to a running orchestrator over its control pipe, one JSON object per line, e.g.
+/// `{"command":"restart","name":"worker"}`.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(tag = "command", rename_all = "snake_case")]
+pub enum ControlRequest {
+  Status,
+  Start { name: String },
+  Stop { name: String },
+  Restart { name: String },
+  Reload,
+  Events,
+  Logs { name: String },
+}
+
+impl ControlRequest {
+  /// Name of the request as in its JSON, e.g. `restart`.
+  pub fn get_operation_name(&self) -> &'static str {
+    match self {
+      ControlRequest::Status => "status",
+      ControlRequest::Start { .. } => "start",
+      ControlRequest::Stop { .. } => "stop",
+      ControlRequest::Restart { .. } => "restart",
+      ControlRequest::Reload => "reload",
+      ControlRequest::Events => "events",
+      ControlRequest::Logs { .. } => "logs",
+    }
+  }
+
+  pub fn get_target(&self) -> Option<&str> {
+    match self {
+      ControlRequest::Start { name } | ControlRequest::Stop { name } | ControlRequest::Restart { name } | ControlRequest::Logs { name } => Some(name),
+      _ => None,
+    }
+  }
+
+  /// Whether the request only reads the state of the orchestrator, and so isn't audited.
+  pub fn is_read_only(&self) -> bool {
+    matches!(self, ControlRequest::Status | ControlRequest::Events | ControlRequest::Logs { .. })
+  }
+}
+
+/// Where a control request came from, as recorded in the audit log.
+#[derive(Debug, Clone)]
+pub struct ControlCaller {
+  /// `pipe`, `http` or `grpc`.
+  pub channel: &'static str,
+  pub identity: String,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize, Clone)]
+pub struct ControlResponse {
+  pub ok: bool,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub error: Option<String>,
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub error_kind: Option<ControlErrorKind>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub processes: Option<Vec<ProcessStatus>>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub events: Option<Vec<RecentEvent>>,
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub logs: Option<Vec<ProcessLogs>>,
+}
+
+/// Why a control request failed, for clients to tell failures apart without parsing the error.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum ControlErrorKind {
+  ConfigInvalid,
+  ProcessNotConfigured,
+  TimedOut,
+}
+
+/// State of one instance of a configured process.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ProcessStatus {
+  pub name: String,
+  pub instance_index: usize,
+  pub id: Option<String>,
+  pub pid: Option<u32>,
+  pub state: String,
+  pub uptime_secs: Option<f64>,
+  pub memory_mbs: Option<f64>,
+  pub cpu_percent: Option<f64>,
+  pub restarts: u64,
+  pub log_file: Option<String>,
+}
+
+/// End of the output of one instance of a process, as kept with `output_buffer_kbs`.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ProcessLogs {
+  pub name: String,
+  pub instance_index: usize,
+  pub lines: Vec<String>,
+}
+
+/// An event handled by the event pump, as kept for the `events` request.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct RecentEvent {
+  pub time: String,
+  pub event: String,
+}
+
+/// A message of the management API event stream.
+#[derive(Debug, Serialize, Clone)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum StreamMessage {
+  Event(RecentEvent),
+  Metrics { time: String, processes: Vec<ProcessStatus> },
+}
+
+impl ControlResponse {
+  pub fn success() -> Self {
+    Self { ok: true, ..Default::default() }
+  }
+
+  pub fn failure(error: String) -> Self {
+    Self { ok: false, error: Some(error), ..Default::default() }
+  }
+
+  pub fn failure_of_kind(error_kind: ControlErrorKind, error: String) -> Self {
+    Self { error_kind: Some(error_kind), ..Self::failure(error) }
+  }
+}
+
+/// Name of the pipe the orchestrator running as the service listens on.
+pub fn get_control_pipe_name(service_name: &str) -> String {
+  format!("\\\\.\\pipe\\process-orchestrator-{}", service_name)
+}
+
+/// Sends a request to the orchestrator listening on the control pipe, returning its response when
+/// it succeeded.
+pub fn send_control_request(pipe_name: &str, request: &ControlRequest) -> Result<ControlResponse, Box<dyn Error>> {
+  let pipe = match OpenOptions::new().read(true).write(true).open(pipe_name) {
+    Ok(pipe) => pipe,
+    Err(open_error) if open_error.kind() == std::io::ErrorKind::NotFound => {
+      return Err(Box::new(OrchestratorError::OrchestratorNotRunning(pipe_name.to_string())));
+    }
+    Err(open_error) => return Err(Box::new(open_error)),
+  };
+
+  let mut writer = pipe.try_clone()?;
+  writeln!(writer, "{}", serde_json::to_string(request)?)?;
+
+  let mut response_line = String::new();
+  BufReader::new(pipe).read_line(&mut response_line)?;
+
+  let response = serde_json::from_str::<ControlResponse>(&response_line)?;
+  if !response.ok {
+    return Err(Box::new(OrchestratorError::ControlRequestFailed(response.error.unwrap_or_default(), response.error_kind)));
+  }
+
+  Ok(response)
+}
+
+/// Listens on the control pipe, handing every request to the event pump and writing back its
+/// response. Each client is served on its own thread.
+pub fn start_control_server(pipe_name: String, sender: Sender<Event>) {
+  std::thread::spawn(move || {
+    let pipe_name_wide = OsStr::new(&pipe_name)
+      .encode_wide()
+      .chain(std::iter::once(0))
+      .collect::<Vec<u16>>();
+
+    loop {
+      let pipe_handle = unsafe {
+        CreateNamedPipeW(
+          pipe_name_wide.as_ptr(),
+          PIPE_ACCESS_DUPLEX,
+          PIPE_TYPE_BYTE | PIPE_READMODE_BYTE | PIPE_WAIT | PIPE_REJECT_REMOTE_CLIENTS,
+          PIPE_UNLIMITED_INSTANCES,
+          CONTROL_PIPE_BUFFER_SIZE,
+          CONTROL_PIPE_BUFFER_SIZE,
+          0,
+          null_mut())
+      };
+
+      if pipe_handle == INVALID_HANDLE_VALUE {
+        error!("Control [{}]: Could not create pipe: {}", &pipe_name, std::io::Error::last_os_error());
+        return;
+      }
+
+      let is_connected = unsafe {
+        ConnectNamedPipe(pipe_handle, null_mut()) != 0 || GetLastError() == ERROR_PIPE_CONNECTED
+      };
+
+      if !is_connected {
+        unsafe { CloseHandle(pipe_handle); }
+        continue;
+      }
+
+      let pipe = unsafe { File::from_raw_handle(pipe_handle as _) };
+      let connection_sender = sender.clone();
+
+      std::thread::spawn(move || {
+        if let Err(connection_error) = handle_connection(pipe, connection_sender) {
+          trace!("Control: Connection closed: {}", connection_error);
+        }
+      });
+    }
+  });
+}
+
+fn handle_connection(pipe: File, sender: Sender<Event>) -> std::io::Result<()> {
+  let pipe_handle = pipe.as_raw_handle();
+  let mut writer = pipe.try_clone()?;
+  let reader = BufReader::new(pipe);
+  let mut caller = None::<ControlCaller>;
+
+  for line in reader.lines() {
+    let line = line?;
+    if line.trim().is_empty() {
+      continue;
+    }
+
+    // The client can only be impersonated once something has been read from it.
+    let request_caller = caller
+      .get_or_insert_with(|| ControlCaller { channel: "pipe", identity: get_pipe_client_identity(pipe_handle as _) })
+      .clone();
+
+    let response = match serde_json::from_str::<ControlRequest>(&line) {
+      Ok(request) => send_to_event_pump(request, request_caller, &sender),
+      Err(parse_error) => ControlResponse::failure(format!("Invalid request: {}", parse_error)),
+    };
+
+    writeln!(writer, "{}", serde_json::to_string(&response)?)?;
+  }
+
+  Ok(())
+}
+
+/// The user the client runs as, and its PID, e.g. `alice (PID 4312)`.
+fn get_pipe_client_identity(pipe_handle: HANDLE) -> String {
+  let mut client_process_id = 0;
+  unsafe { GetNamedPipeClientProcessId(pipe_handle, &mut client_process_id); }
+
+  let user_name = unsafe {
+    if ImpersonateNamedPipeClient(pipe_handle) != 0 {
+      let user_name = get_user_name();
+      RevertToSelf();
+      user_name
+    }
+    else {
+      None
+    }
+  };
+
+  format!("{} (PID {})", user_name.as_deref().unwrap_or("unknown user"), client_process_id)
+}
+
+pub fn send_to_event_pump(request: ControlRequest, caller: ControlCaller, sender: &Sender<Event>) -> ControlResponse {
+  let (response_sender, response_receiver) = channel::<ControlResponse>();
+
+  if sender.send(Event::ControlRequestReceived(request, caller, response_sender)).is_err() {
+    return ControlResponse::failure(String::from("Orchestrator is stopping"));
+  }
+
+  response_receiver
+    .recv_timeout(CONTROL_RESPONSE_TIMEOUT)
+    .unwrap_or_else(|_| ControlResponse::failure_of_kind(ControlErrorKind::TimedOut, String::from("Orchestrator did not respond")))
+}
@@ -0,0 +1,130 @@
+use crate::orchestrator_config::{LogDiskGuardAction, LogDiskGuardConfig};
+
+use std::collections::HashSet;
+use std::ffi::OsStr;
+use std::os::windows::ffi::{OsStrExt, OsStringExt};
+use std::path::{Path, PathBuf};
+use std::ptr::null_mut;
+use std::time::{Duration, Instant};
+use winapi::shared::minwindef::DWORD;
+use winapi::um::fileapi::{GetDiskFreeSpaceExW, GetVolumePathNameW};
+use winapi::um::winnt::ULARGE_INTEGER;
+
+const DEFAULT_CHECK_INTERVAL_SECS: f64 = 30f64;
+
+/// Longest volume path, e.g. a volume mounted in a deep folder.
+const VOLUME_PATH_CAPACITY: usize = 1024;
+
+/// Change in the free space of a volume holding log files, since it was last checked.
+#[derive(Debug, Clone, PartialEq)]
+pub enum VolumeSpaceChange {
+  /// The volume is low on space, and was not before.
+  BecameLow { volume: PathBuf, free_mbs: f64 },
+  /// The volume is still low on space.
+  StillLow { volume: PathBuf, free_mbs: f64 },
+  /// The volume has enough space again.
+  Recovered { volume: PathBuf, free_mbs: f64 },
+}
+
+/// Checks the free space of the volumes the processes log to, every `check_interval_secs`.
+#[derive(Debug)]
+pub struct LogDiskGuard {
+  pub config: LogDiskGuardConfig,
+  checked_at: Option<Instant>,
+  low_volumes: HashSet<PathBuf>,
+}
+
+impl LogDiskGuard {
+  pub fn new(config: LogDiskGuardConfig) -> Self {
+    Self {
+      config,
+      checked_at: None,
+      low_volumes: HashSet::new(),
+    }
+  }
+
+  pub fn get_action(&self) -> LogDiskGuardAction {
+    self.config.action.unwrap_or(LogDiskGuardAction::PauseCapture)
+  }
+
+  pub fn is_check_due(&self) -> bool {
+    let check_interval = Duration::from_secs_f64(self.config.check_interval_secs.unwrap_or(DEFAULT_CHECK_INTERVAL_SECS));
+    self.checked_at.map(|x| x.elapsed() >= check_interval).unwrap_or(true)
+  }
+
+  /// How the free space of the volumes changed since they were last checked.
+  pub fn check(&mut self, volumes: &HashSet<PathBuf>) -> Vec<VolumeSpaceChange> {
+    self.checked_at = Some(Instant::now());
+
+    let mut changes = Vec::new();
+
+    // Volumes no process logs to any more are no longer low, as far as the guard is concerned.
+    self.low_volumes.retain(|x| volumes.contains(x));
+
+    for volume in volumes {
+      let free_mbs = match get_free_space_mbs(volume) {
+        Some(free_mbs) => free_mbs,
+        None => continue,
+      };
+
+      let is_low = free_mbs < self.config.min_free_mbs;
+      let was_low = self.low_volumes.contains(volume);
+
+      let change = match (was_low, is_low) {
+        (false, true) => VolumeSpaceChange::BecameLow { volume: volume.clone(), free_mbs },
+        (true, true) => VolumeSpaceChange::StillLow { volume: volume.clone(), free_mbs },
+        (true, false) => VolumeSpaceChange::Recovered { volume: volume.clone(), free_mbs },
+        (false, false) => continue,
+      };
+
+      if is_low {
+        self.low_volumes.insert(volume.clone());
+      }
+      else {
+        self.low_volumes.remove(volume);
+      }
+
+      changes.push(change);
+    }
+
+    changes
+  }
+}
+
+/// Root of the volume holding the file, e.g. `C:\`, resolving a relative path against the current
+/// directory.
+pub fn get_volume_path(file_path: &Path) -> Option<PathBuf> {
+  let file_path = std::env::current_dir().ok()?.join(file_path);
+  let file_path_wide = to_wide(file_path.as_os_str());
+  let mut volume_path = vec![0u16; VOLUME_PATH_CAPACITY];
+
+  let is_found = unsafe {
+    GetVolumePathNameW(file_path_wide.as_ptr(), volume_path.as_mut_ptr(), VOLUME_PATH_CAPACITY as DWORD) != 0
+  };
+
+  if !is_found {
+    return None;
+  }
+
+  let volume_path_length = volume_path.iter().position(|x| *x == 0).unwrap_or(volume_path.len());
+  Some(PathBuf::from(std::ffi::OsString::from_wide(&volume_path[..volume_path_length])))
+}
+
+/// Megabytes of the volume available to the account the orchestrator runs as.
+pub fn get_free_space_mbs(volume_path: &Path) -> Option<f64> {
+  let volume_path_wide = to_wide(volume_path.as_os_str());
+
+  unsafe {
+    let mut free_bytes_available = std::mem::zeroed::<ULARGE_INTEGER>();
+
+    if GetDiskFreeSpaceExW(volume_path_wide.as_ptr(), &mut free_bytes_available, null_mut(), null_mut()) == 0 {
+      return None;
+    }
+
+    Some(*free_bytes_available.QuadPart() as f64 / 1024f64 / 1024f64)
+  }
+}
+
+fn to_wide(value: &OsStr) -> Vec<u16> {
+  value.encode_wide().chain(std::iter::once(0)).collect()
+}
@@ -1,3 +1,5 @@
+use crate::control_server::ControlErrorKind;
+
 use std::path::PathBuf;
 use std::error::Error;
 use std::fmt::{Formatter, Display};
@@ -8,6 +10,35 @@ pub enum OrchestratorError {
   ServiceStartFailed(windows_service::Error),
   ServiceControllerNotPresent(),
   ProcessNotificationRegistrationFailed(),
+  EnvFileLineInvalid(PathBuf, usize),
+  CredentialNotFound(String),
+  ConfigValidationFailed(usize),
+  FileAlreadyExists(PathBuf),
+  NoProcessesSelected(),
+  ExecutableNotFound(PathBuf),
+  ConfigExtendsCycle(PathBuf),
+  AlreadyRunning(String),
+  OrchestratorNotRunning(String),
+  ControlRequestFailed(String, Option<ControlErrorKind>),
+  ProcessNotConfigured(String),
+  ProcessLogFileNotFound(String),
+  ManagementApiTokensRequired(String),
+  LogFileOpenFailed(String, std::io::Error),
+  RunAsLogonFailed(String, std::io::Error),
+  UserProfileLoadFailed(String, std::io::Error),
+  NoInteractiveSession(),
+  AppContainerCreateFailed(String, std::io::Error),
+  JobCreateFailed(std::io::Error),
+  JobAssignFailed(std::io::Error),
+  NumaNodeNotFound(u16),
+  ProcessorGroupNotFound(u16),
+  StdinOpenFailed(String, std::io::Error),
+  WorkingDirectoryNotFound(String),
+  WorkingDirectoryCreateFailed(String, std::io::Error),
+  CmdArgumentInvalid(String),
+  ConfigLocationNotUnicode(PathBuf),
+  GrpcApiNotLoopback(String),
+  DesktopAccessGrantFailed(String, std::io::Error),
 }
 
 impl Display for OrchestratorError {
@@ -17,9 +48,70 @@ impl Display for OrchestratorError {
       OrchestratorError::ServiceStartFailed(err) => write!(formatter, "Windows service failed to start: {:?}", err),
       OrchestratorError::ServiceControllerNotPresent() => write!(formatter, "Windows service controller not present"),
       OrchestratorError::ProcessNotificationRegistrationFailed() => write!(formatter, "Registration of the process notification handler has failed"),
+      OrchestratorError::EnvFileLineInvalid(file_path, line_number) => write!(formatter, "Env file [{}] line {} is not a KEY=VALUE pair", file_path.to_str().unwrap(), line_number),
+      OrchestratorError::CredentialNotFound(target) => write!(formatter, "Credential [{}] not found in the Windows Credential Manager", target),
+      OrchestratorError::ConfigValidationFailed(error_count) => write!(formatter, "Config validation failed with {} error(s)", error_count),
+      OrchestratorError::FileAlreadyExists(file_path) => write!(formatter, "File [{}] already exists", file_path.to_str().unwrap()),
+      OrchestratorError::NoProcessesSelected() => write!(formatter, "No processes selected, give a process name or a matching --only / --exclude tag filter"),
+      OrchestratorError::ExecutableNotFound(file_path) => write!(formatter, "Executable [{}] not found", file_path.to_str().unwrap()),
+      OrchestratorError::ConfigExtendsCycle(file_path) => write!(formatter, "Config [{}] is extended in a cycle", file_path.to_str().unwrap()),
+      OrchestratorError::AlreadyRunning(config_locations) => write!(formatter, "Another orchestrator is already running for config locations [{}]", config_locations),
+      OrchestratorError::OrchestratorNotRunning(pipe_name) => write!(formatter, "No orchestrator is listening on control pipe [{}]", pipe_name),
+      OrchestratorError::ControlRequestFailed(message, _) => write!(formatter, "Control request failed: {}", message),
+      OrchestratorError::ProcessNotConfigured(process_name) => write!(formatter, "Process [{}] is not configured", process_name),
+      OrchestratorError::ProcessLogFileNotFound(process_name) => write!(formatter, "Process [{}] is not running with a log_file, nor keeps its output with output_buffer_kbs", process_name),
+      OrchestratorError::ManagementApiTokensRequired(address) => write!(formatter, "Management API address [{}] is not a loopback address, so it needs tokens", address),
+      OrchestratorError::LogFileOpenFailed(log_file, err) => write!(formatter, "Log file [{}] could not be opened, nor its fallbacks: {}", log_file, err),
+      OrchestratorError::RunAsLogonFailed(account_name, err) => write!(formatter, "Could not log on as [{}]: {}", account_name, err),
+      OrchestratorError::UserProfileLoadFailed(account_name, err) => write!(formatter, "Could not load the profile of [{}]: {}", account_name, err),
+      OrchestratorError::NoInteractiveSession() => write!(formatter, "No user is logged on to the console"),
+      OrchestratorError::AppContainerCreateFailed(name, err) => write!(formatter, "Could not create AppContainer [{}]: {}", name, err),
+      OrchestratorError::JobCreateFailed(err) => write!(formatter, "Could not create the job object of the process: {}", err),
+      OrchestratorError::JobAssignFailed(err) => write!(formatter, "Could not put the process in its job object: {}", err),
+      OrchestratorError::NumaNodeNotFound(numa_node) => write!(formatter, "NUMA node [{}] has no processors on this machine", numa_node),
+      OrchestratorError::ProcessorGroupNotFound(processor_group) => write!(formatter, "Processor group [{}] does not exist on this machine", processor_group),
+      OrchestratorError::StdinOpenFailed(stdin, err) => write!(formatter, "Could not open stdin [{}]: {}", stdin, err),
+      OrchestratorError::WorkingDirectoryNotFound(working_directory) => write!(formatter, "Working directory [{}] does not exist", working_directory),
+      OrchestratorError::WorkingDirectoryCreateFailed(working_directory, err) => write!(formatter, "Could not create working directory [{}]: {}", working_directory, err),
+      OrchestratorError::CmdArgumentInvalid(argument) => write!(formatter, "Argument [{}] can't be passed through cmd, as a line break ends its command", argument.escape_debug()),
+      OrchestratorError::ConfigLocationNotUnicode(config_location) => write!(formatter, "Config location [{}] is not valid Unicode", config_location.display()),
+      OrchestratorError::GrpcApiNotLoopback(address) => write!(formatter, "gRPC API address [{}] is not a loopback address, which needs TLS the gRPC API does not support", address),
+      OrchestratorError::DesktopAccessGrantFailed(account_name, err) => write!(formatter, "Could not let [{}] use the desktop of the orchestrator: {}", account_name, err),
     }
   }
 }
 
 impl Error for OrchestratorError {
+}
+
+/// Exit code of a CLI command that failed in a way not covered by the other exit codes.
+pub const FAILED_EXIT_CODE: i32 = 1;
+/// Exit code of a CLI command that failed on a config that doesn't load or validate.
+pub const CONFIG_INVALID_EXIT_CODE: i32 = 2;
+/// Exit code of a CLI command that needs a running orchestrator when none is listening.
+pub const ORCHESTRATOR_NOT_RUNNING_EXIT_CODE: i32 = 3;
+/// Exit code of a CLI command given the name of a process that isn't configured.
+pub const PROCESS_NOT_FOUND_EXIT_CODE: i32 = 4;
+/// Exit code of a CLI command the running orchestrator did not answer in time.
+pub const TIMED_OUT_EXIT_CODE: i32 = 5;
+
+/// Exit code of the CLI for the error a command failed with, so scripts can branch on it.
+pub fn get_exit_code(error: &(dyn Error + 'static)) -> i32 {
+  let orchestrator_error = match error.downcast_ref::<OrchestratorError>() {
+    Some(orchestrator_error) => orchestrator_error,
+    None => return FAILED_EXIT_CODE,
+  };
+
+  match orchestrator_error {
+    OrchestratorError::ConfigLoadFailed(_, _)
+    | OrchestratorError::ConfigValidationFailed(_)
+    | OrchestratorError::EnvFileLineInvalid(_, _)
+    | OrchestratorError::ConfigExtendsCycle(_)
+    | OrchestratorError::ControlRequestFailed(_, Some(ControlErrorKind::ConfigInvalid)) => CONFIG_INVALID_EXIT_CODE,
+    OrchestratorError::OrchestratorNotRunning(_) => ORCHESTRATOR_NOT_RUNNING_EXIT_CODE,
+    OrchestratorError::ProcessNotConfigured(_)
+    | OrchestratorError::ControlRequestFailed(_, Some(ControlErrorKind::ProcessNotConfigured)) => PROCESS_NOT_FOUND_EXIT_CODE,
+    OrchestratorError::ControlRequestFailed(_, Some(ControlErrorKind::TimedOut)) => TIMED_OUT_EXIT_CODE,
+    _ => FAILED_EXIT_CODE,
+  }
 }
\ No newline at end of file
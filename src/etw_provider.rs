@@ -0,0 +1,96 @@
+use crate::stateful_process::StatefulProcess;
+
+use std::ffi::OsStr;
+use std::os::windows::ffi::OsStrExt;
+use std::ptr::null_mut;
+use log::trace;
+use winapi::shared::evntprov::{EventProviderEnabled, EventRegister, EventUnregister, EventWriteString, REGHANDLE};
+use winapi::shared::guiddef::GUID;
+use winapi::shared::winerror::ERROR_SUCCESS;
+use winapi::shared::ntdef::UCHAR;
+
+/// ETW provider id, `{d1dfc58e-587a-4047-b2bd-bac5a4b00158}`, to enable in xperf/WPR sessions.
+pub const ETW_PROVIDER_ID: GUID = GUID {
+  Data1: 0xd1dfc58e,
+  Data2: 0x587a,
+  Data3: 0x4047,
+  Data4: [0xb2, 0xbd, 0xba, 0xc5, 0xa4, 0xb0, 0x01, 0x58],
+};
+
+/// Keyword of the events handled by the event pump.
+pub const ETW_EVENT_KEYWORD: u64 = 0x1;
+
+/// Keyword of the per-process metrics read on each poll.
+pub const ETW_METRICS_KEYWORD: u64 = 0x2;
+
+const ETW_LEVEL_INFORMATION: UCHAR = 4;
+
+/// Writes string events to ETW, so traces show what the orchestrator decided alongside the rest of
+/// the system. Writing is skipped when no trace session has the provider enabled.
+pub struct EtwProvider {
+  registration_handle: Option<REGHANDLE>,
+}
+
+impl EtwProvider {
+  pub fn register() -> Self {
+    let mut registration_handle: REGHANDLE = 0;
+
+    let register_result = unsafe {
+      EventRegister(&ETW_PROVIDER_ID, None, null_mut(), &mut registration_handle)
+    };
+
+    if register_result != ERROR_SUCCESS {
+      trace!("EtwProvider: Could not register: {}", std::io::Error::from_raw_os_error(register_result as i32));
+      return Self { registration_handle: None };
+    }
+
+    Self {
+      registration_handle: Some(registration_handle),
+    }
+  }
+
+  pub fn is_enabled(&self, keyword: u64) -> bool {
+    match self.registration_handle {
+      Some(registration_handle) => unsafe { EventProviderEnabled(registration_handle, ETW_LEVEL_INFORMATION, keyword) != 0 },
+      None => false,
+    }
+  }
+
+  pub fn write(&self, keyword: u64, message: &str) {
+    if !self.is_enabled(keyword) {
+      return;
+    }
+
+    let message_wide = OsStr::new(message)
+      .encode_wide()
+      .chain(std::iter::once(0))
+      .collect::<Vec<u16>>();
+
+    unsafe {
+      EventWriteString(self.registration_handle.unwrap(), ETW_LEVEL_INFORMATION, keyword, message_wide.as_ptr());
+    }
+  }
+
+  pub fn write_process_metrics(&self, process: &StatefulProcess) {
+    if !self.is_enabled(ETW_METRICS_KEYWORD) {
+      return;
+    }
+
+    self.write(ETW_METRICS_KEYWORD, &format!(
+      "Process [{}]: pid={} memory_mbs={} duration_secs={}",
+      &process.id,
+      process.get_pid().map(|x| x.to_string()).unwrap_or_default(),
+      process.memory_usage_mbs.map(|x| format!("{:.1}", x)).unwrap_or_default(),
+      process.duration_secs.map(|x| format!("{:.0}", x)).unwrap_or_default()));
+  }
+}
+
+impl Drop for EtwProvider {
+  fn drop(&mut self) {
+    if let Some(registration_handle) = self.registration_handle {
+      unsafe {
+        EventUnregister(registration_handle);
+      }
+    }
+  }
+}
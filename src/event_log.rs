@@ -0,0 +1,166 @@
+use serde::{Deserialize, Serialize};
+use std::error::Error;
+use std::ffi::OsStr;
+use std::os::windows::ffi::OsStrExt;
+use std::ptr::null_mut;
+use log::trace;
+use winapi::shared::minwindef::{DWORD, HKEY, WORD};
+use winapi::shared::winerror::ERROR_SUCCESS;
+use winapi::um::winbase::{DeregisterEventSource, RegisterEventSourceW, ReportEventW};
+use winapi::um::winnt::{EVENTLOG_ERROR_TYPE, EVENTLOG_INFORMATION_TYPE, EVENTLOG_WARNING_TYPE, KEY_SET_VALUE, REG_DWORD, REG_EXPAND_SZ};
+use winapi::um::winreg::{RegCloseKey, RegCreateKeyExW, RegDeleteKeyW, RegSetValueExW, HKEY_LOCAL_MACHINE};
+
+pub const SERVICE_STARTED_EVENT_ID: DWORD = 1;
+pub const SERVICE_STOPPED_EVENT_ID: DWORD = 2;
+pub const PROCESS_STARTED_EVENT_ID: DWORD = 100;
+pub const PROCESS_STOPPED_EVENT_ID: DWORD = 101;
+pub const PROCESS_EXITED_EVENT_ID: DWORD = 102;
+pub const PROCESS_RECYCLED_EVENT_ID: DWORD = 103;
+pub const PROCESS_START_FAILED_EVENT_ID: DWORD = 104;
+pub const PROCESS_OUTPUT_EVENT_ID: DWORD = 105;
+pub const LOG_VOLUME_LOW_EVENT_ID: DWORD = 106;
+pub const LOG_VOLUME_RECOVERED_EVENT_ID: DWORD = 107;
+pub const LOG_FILE_FALLBACK_EVENT_ID: DWORD = 108;
+
+/// Registry key the Application log event sources are registered under.
+const EVENT_SOURCES_KEY: &str = "SYSTEM\\CurrentControlSet\\Services\\EventLog\\Application";
+
+/// Message file whose messages are all `%1`, so entries show the text they are written with.
+const EVENT_MESSAGE_FILE: &str = "%SystemRoot%\\Microsoft.NET\\Framework64\\v4.0.30319\\EventLogMessages.dll";
+
+/// Level of an event log entry, as configured.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum EventLogLevel {
+  Information,
+  Warning,
+  Error,
+}
+
+/// Writes orchestrator lifecycle entries to the Windows Application event log, under an event
+/// source named after the service. Failing to write an entry never fails the orchestrator.
+#[derive(Debug, Clone)]
+pub struct EventLog {
+  source_name: String,
+}
+
+impl EventLog {
+  pub fn new(source_name: &str) -> Self {
+    Self {
+      source_name: source_name.to_string(),
+    }
+  }
+
+  pub fn info(&self, event_id: DWORD, message: &str) {
+    self.report(EVENTLOG_INFORMATION_TYPE, event_id, message);
+  }
+
+  pub fn warning(&self, event_id: DWORD, message: &str) {
+    self.report(EVENTLOG_WARNING_TYPE, event_id, message);
+  }
+
+  pub fn error(&self, event_id: DWORD, message: &str) {
+    self.report(EVENTLOG_ERROR_TYPE, event_id, message);
+  }
+
+  pub fn report_level(&self, level: EventLogLevel, event_id: DWORD, message: &str) {
+    match level {
+      EventLogLevel::Information => self.info(event_id, message),
+      EventLogLevel::Warning => self.warning(event_id, message),
+      EventLogLevel::Error => self.error(event_id, message),
+    }
+  }
+
+  fn report(&self, event_type: WORD, event_id: DWORD, message: &str) {
+    let source_name_wide = to_wide(&self.source_name);
+    let message_wide = to_wide(message);
+    let mut strings = [message_wide.as_ptr()];
+
+    unsafe {
+      let event_source_handle = RegisterEventSourceW(null_mut(), source_name_wide.as_ptr());
+      if event_source_handle.is_null() {
+        trace!("EventLog: Could not register event source [{}]: {}", &self.source_name, std::io::Error::last_os_error());
+        return;
+      }
+
+      if ReportEventW(event_source_handle, event_type, 0, event_id, null_mut(), 1, 0, strings.as_mut_ptr(), null_mut()) == 0 {
+        trace!("EventLog: Could not write event {}: {}", event_id, std::io::Error::last_os_error());
+      }
+
+      DeregisterEventSource(event_source_handle);
+    }
+  }
+}
+
+/// Registers the event source in the Application log, so its entries are shown with their text.
+pub fn install_event_source(source_name: &str) -> Result<(), Box<dyn Error>> {
+  let event_source_key_wide = to_wide(&format!("{}\\{}", EVENT_SOURCES_KEY, source_name));
+
+  unsafe {
+    let mut event_source_key: HKEY = null_mut();
+
+    let create_result = RegCreateKeyExW(
+      HKEY_LOCAL_MACHINE,
+      event_source_key_wide.as_ptr(),
+      0,
+      null_mut(),
+      0,
+      KEY_SET_VALUE,
+      null_mut(),
+      &mut event_source_key,
+      null_mut());
+
+    if create_result != ERROR_SUCCESS as i32 {
+      return Err(Box::new(std::io::Error::from_raw_os_error(create_result)));
+    }
+
+    let event_message_file_wide = to_wide(EVENT_MESSAGE_FILE);
+    let types_supported = (EVENTLOG_ERROR_TYPE | EVENTLOG_WARNING_TYPE | EVENTLOG_INFORMATION_TYPE) as DWORD;
+
+    let mut set_result = RegSetValueExW(
+      event_source_key,
+      to_wide("EventMessageFile").as_ptr(),
+      0,
+      REG_EXPAND_SZ,
+      event_message_file_wide.as_ptr() as *const u8,
+      (event_message_file_wide.len() * 2) as DWORD);
+
+    if set_result == ERROR_SUCCESS as i32 {
+      set_result = RegSetValueExW(
+        event_source_key,
+        to_wide("TypesSupported").as_ptr(),
+        0,
+        REG_DWORD,
+        &types_supported as *const DWORD as *const u8,
+        std::mem::size_of::<DWORD>() as DWORD);
+    }
+
+    RegCloseKey(event_source_key);
+
+    if set_result != ERROR_SUCCESS as i32 {
+      return Err(Box::new(std::io::Error::from_raw_os_error(set_result)));
+    }
+  }
+
+  Ok(())
+}
+
+pub fn uninstall_event_source(source_name: &str) -> Result<(), Box<dyn Error>> {
+  let event_source_key_wide = to_wide(&format!("{}\\{}", EVENT_SOURCES_KEY, source_name));
+
+  unsafe {
+    let delete_result = RegDeleteKeyW(HKEY_LOCAL_MACHINE, event_source_key_wide.as_ptr());
+    if delete_result != ERROR_SUCCESS as i32 {
+      return Err(Box::new(std::io::Error::from_raw_os_error(delete_result)));
+    }
+  }
+
+  Ok(())
+}
+
+fn to_wide(value: &str) -> Vec<u16> {
+  OsStr::new(value)
+    .encode_wide()
+    .chain(std::iter::once(0))
+    .collect()
+}
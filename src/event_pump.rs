@@ -1,18 +1,75 @@
-use crate::config::load_stateful_process_configs;
-use crate::stateful_process::{StatefulProcessConfig, StatefulProcess};
+use crate::cli_options::CliOptions;
+use crate::errors::OrchestratorError;
+use crate::event_log::{EventLog, PROCESS_STARTED_EVENT_ID, PROCESS_STOPPED_EVENT_ID, PROCESS_EXITED_EVENT_ID, PROCESS_RECYCLED_EVENT_ID, PROCESS_START_FAILED_EVENT_ID, LOG_VOLUME_LOW_EVENT_ID, LOG_VOLUME_RECOVERED_EVENT_ID, LOG_FILE_FALLBACK_EVENT_ID};
+use crate::etw_provider::{EtwProvider, ETW_EVENT_KEYWORD};
+use crate::orchestrator_config::{GrpcApiConfig, LogDiskGuardAction, ManagementApiConfig, load_orchestrator_config};
+use crate::management_api::start_management_api;
+#[cfg(feature = "grpc")]
+use crate::grpc_api::start_grpc_api;
+use crate::statsd_sink::StatsdSink;
+use crate::otlp_exporter::OtlpExporter;
+use crate::performance_counters::PerformanceCounters;
+use crate::audit_log::AuditLog;
+use crate::log_capture::{OutputBuffer, OutputEvents, remove_rotated_files};
+use crate::log_shipper::LogShipper;
+use crate::log_retention::LogRetention;
+use crate::disk_guard::{LogDiskGuard, VolumeSpaceChange, get_volume_path};
+use crate::control_server::{ControlCaller, ControlErrorKind, ControlRequest, ControlResponse, ProcessLogs, ProcessStatus, RecentEvent, StreamMessage, get_control_pipe_name, start_control_server};
+use crate::windows_service_host::get_service_name;
+use crate::config::{ModifiedTimes, load_stateful_process_configs, get_config_files_modified};
+use crate::remote_config::RemoteConfigSource;
+use crate::stateful_process::{StatefulProcessConfig, StatefulProcess, SessionChangeEvent, SessionChangeAction};
+use crate::state_store::{OrchestratorState, get_state_file_path, load_state, save_state, get_state_file_modified};
 
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::sync::atomic::Ordering;
 use std::sync::mpsc::{Receiver, Sender, channel};
 use std::error::Error;
-use std::time::Duration;
-use log::{info, error, trace};
+use std::path::PathBuf;
+use std::time::{Duration, SystemTime};
+use chrono::Utc;
+use log::{info, warn, error, trace};
 
 pub type VoidResult = Result<(), Box<dyn Error>>;
 
+/// Number of handled events kept for the `events` control request.
+const RECENT_EVENTS_CAPACITY: usize = 200;
+
 pub struct EventPump {
   pub sender: Sender<Event>,
   receiver: Receiver<Event>,
+  cli_options: CliOptions,
   configs: Vec<StatefulProcessConfig>,
   processes: Vec<StatefulProcess>,
+  remote_config_contents: Option<String>,
+  state: OrchestratorState,
+  state_file_path: PathBuf,
+  state_file_modified: Option<SystemTime>,
+  config_files_modified: ModifiedTimes,
+  rolling_restart_process_ids: VecDeque<String>,
+  rolling_restart_instance: Option<(String, usize)>,
+  is_paused: bool,
+  is_suspended: bool,
+  event_log: EventLog,
+  etw_provider: EtwProvider,
+  statsd_sink: Option<StatsdSink>,
+  otlp_exporter: Option<OtlpExporter>,
+  log_shipper: Option<LogShipper>,
+  log_disk_guard: Option<LogDiskGuard>,
+  log_retention: Option<LogRetention>,
+  performance_counters: PerformanceCounters,
+  process_restart_counts: HashMap<(String, usize), u64>,
+  /// End of the output of each process instance with `output_buffer_kbs`, kept across restarts.
+  output_buffers: HashMap<(String, usize), OutputBuffer>,
+  /// Processes stopped on request, e.g. on a session change or over the control pipe, which are
+  /// not started again until requested.
+  stopped_process_names: HashSet<String>,
+  control_pipe_name: String,
+  audit_log: AuditLog,
+  recent_events: VecDeque<RecentEvent>,
+  management_api_config: Option<ManagementApiConfig>,
+  grpc_api_config: Option<GrpcApiConfig>,
+  event_stream_senders: Vec<Sender<StreamMessage>>,
   is_stop_requested: bool,
   is_stopped: bool,
 }
@@ -23,23 +80,92 @@ pub enum Event {
   OrchestratorTick(),
   OrchestratorRequestStop(),
   OrchestratorStopping(),
-  ProcessConfigLoaded(StatefulProcessConfig),
-  ProcessRequestStart(String),
+  ProcessConfigLoaded(Box<StatefulProcessConfig>),
+  RemoteConfigFetched(String),
+  ProcessRequestStart(String, usize),
   ProcessRequestPoll(String),
   ProcessRequestStop(String),
   ProcessStopped(String),
+  /// The log file of the process could not be opened, so it writes to a fallback instead.
+  ProcessLogFileFallback(String, String, String, String),
+  ProcessRequestEnable(String),
+  ProcessRequestDisable(String),
+  OrchestratorRequestReload(),
+  OrchestratorRequestRollingRestart(),
+  OrchestratorRequestStatusDump(),
+  /// Suspends the processes, then signals the sender.
+  OrchestratorRequestPause(Sender<()>),
+  /// Resumes the processes, then signals the sender.
+  OrchestratorRequestContinue(Sender<()>),
+  OrchestratorSuspending(),
+  OrchestratorResumed(),
+  OrchestratorSessionChanged(SessionChangeEvent),
+  ControlRequestReceived(ControlRequest, ControlCaller, Sender<ControlResponse>),
+  StreamSubscribed(Sender<StreamMessage>),
 }
 
 impl EventPump {
-  pub fn new() -> Self {
+  pub fn new(cli_options: CliOptions) -> Self {
     let (sender, receiver) = channel::<Event>();
     sender.send(Event::OrchestratorStarting()).unwrap();
 
+    let state_file_path = get_state_file_path(cli_options.service_name.as_deref());
+
+    let orchestrator_config = load_orchestrator_config().unwrap_or_default();
+    let service_name = get_service_name(cli_options.service_name.as_deref(), &orchestrator_config);
+    let event_log = EventLog::new(&service_name);
+    let audit_log = AuditLog::new(cli_options.service_name.as_deref());
+
+    let statsd_sink = orchestrator_config.statsd.as_ref().and_then(|statsd_config| {
+      StatsdSink::new(statsd_config)
+        .map_err(|statsd_error| error!("EventPump: Could not create StatsD sink: {}", statsd_error))
+        .ok()
+    });
+
+    let otlp_exporter = orchestrator_config.opentelemetry.as_ref().and_then(|opentelemetry_config| {
+      OtlpExporter::new(opentelemetry_config, &service_name)
+        .map_err(|otlp_error| error!("EventPump: Could not create OpenTelemetry exporter: {}", otlp_error))
+        .ok()
+    });
+
+    let log_shipper = orchestrator_config.log_shipping.as_ref().and_then(|log_shipping_config| {
+      LogShipper::new(log_shipping_config)
+        .map_err(|log_shipper_error| error!("EventPump: Could not create log shipper: {}", log_shipper_error))
+        .ok()
+    });
+
     Self {
       sender,
       receiver,
+      cli_options,
       configs: Vec::<StatefulProcessConfig>::new(),
       processes: Vec::<StatefulProcess>::new(),
+      remote_config_contents: None,
+      state: OrchestratorState::default(),
+      state_file_path,
+      state_file_modified: None,
+      config_files_modified: Vec::new(),
+      rolling_restart_process_ids: VecDeque::new(),
+      rolling_restart_instance: None,
+      is_paused: false,
+      is_suspended: false,
+      event_log,
+      etw_provider: EtwProvider::register(),
+      statsd_sink,
+      otlp_exporter,
+      log_shipper,
+      log_disk_guard: orchestrator_config.log_disk_guard.clone().map(LogDiskGuard::new),
+      log_retention: orchestrator_config.log_retention.clone().map(LogRetention::new),
+      performance_counters: PerformanceCounters::start(),
+      process_restart_counts: HashMap::new(),
+      output_buffers: HashMap::new(),
+      stopped_process_names: HashSet::new(),
+      control_pipe_name: get_control_pipe_name(&service_name),
+      audit_log,
+      recent_events: VecDeque::new(),
+      management_api_config: orchestrator_config.management_api.clone(),
+      grpc_api_config: orchestrator_config.grpc_api.clone(),
+      event_stream_senders: Vec::new(),
       is_stop_requested: false,
       is_stopped: false,
     }
@@ -60,6 +186,11 @@ impl EventPump {
       if let Ok(message) = result {
         let message_string = format!("{:?}", &message);
         trace!("EventPump: {}", message_string);
+        self.etw_provider.write(ETW_EVENT_KEYWORD, &message_string);
+
+        if is_reported_event(&message) {
+          self.report_event(&message_string);
+        }
 
         let message_result = self.process_message(message);
 
@@ -78,10 +209,24 @@ impl EventPump {
       Event::OrchestratorStopping() => self.on_orchestrator_stopping(),
       Event::OrchestratorTick() => self.on_orchestrator_tick(),
       Event::ProcessConfigLoaded(config) => self.on_process_config_loaded(config),
-      Event::ProcessRequestStart(name) => self.on_process_start(name),
+      Event::RemoteConfigFetched(contents) => self.on_remote_config_fetched(contents),
+      Event::ProcessRequestStart(name, instance_index) => self.on_process_start(name, instance_index),
       Event::ProcessRequestPoll(process_id) => self.on_request_process_poll(process_id),
       Event::ProcessRequestStop(process_id) => self.on_request_process_stop(process_id),
       Event::ProcessStopped(process_id) => self.on_process_stopped(process_id),
+      Event::ProcessLogFileFallback(process_id, log_file, fallback_log_file, open_error) => self.on_process_log_file_fallback(process_id, log_file, fallback_log_file, open_error),
+      Event::ProcessRequestEnable(process_name) => self.on_process_request_enable(process_name),
+      Event::ProcessRequestDisable(process_name) => self.on_process_request_disable(process_name),
+      Event::OrchestratorRequestReload() => self.on_orchestrator_request_reload(),
+      Event::ControlRequestReceived(request, caller, response_sender) => self.on_control_request_received(request, caller, response_sender),
+      Event::StreamSubscribed(stream_sender) => self.on_event_stream_subscribed(stream_sender),
+      Event::OrchestratorRequestRollingRestart() => self.on_orchestrator_request_rolling_restart(),
+      Event::OrchestratorRequestStatusDump() => self.on_orchestrator_request_status_dump(),
+      Event::OrchestratorRequestPause(paused_sender) => self.on_orchestrator_request_pause(paused_sender),
+      Event::OrchestratorRequestContinue(continued_sender) => self.on_orchestrator_request_continue(continued_sender),
+      Event::OrchestratorSuspending() => self.on_orchestrator_suspending(),
+      Event::OrchestratorResumed() => self.on_orchestrator_resumed(),
+      Event::OrchestratorSessionChanged(session_change_event) => self.on_orchestrator_session_changed(session_change_event),
       _ => panic!("Message not recognized [{:?}]", message),
     }
   }
@@ -89,11 +234,170 @@ impl EventPump {
   fn on_orchestrator_tick(&mut self) -> VoidResult {
     for process in &mut self.processes {
       process.poll()?;
+      self.etw_provider.write_process_metrics(process);
+
+      if let Some(statsd_sink) = &self.statsd_sink {
+        statsd_sink.send_process_metrics(process);
+      }
+
+      let restart_count = self.process_restart_counts.get(&(process.config.name.clone(), process.instance_index)).copied().unwrap_or(0);
+      self.performance_counters.update(process, restart_count);
     }
 
-    for process in &mut self.processes {
-      if process.is_recycle_required() {
-        self.sender.send(Event::ProcessRequestStop(process.id.clone())).unwrap();
+    if let Some(otlp_exporter) = &self.otlp_exporter {
+      otlp_exporter.export_process_metrics(&self.processes);
+    }
+
+    if !self.event_stream_senders.is_empty() {
+      let processes = self.get_process_statuses();
+      self.publish(&StreamMessage::Metrics { time: Utc::now().to_rfc3339(), processes });
+    }
+
+    if !self.is_paused {
+      for process in self.processes.iter().filter(|p| !p.is_stop_requested) {
+        if let Some(recycle_reason) = process.get_recycle_reason() {
+          self.request_recycle(&process.id, &recycle_reason);
+        }
+      }
+    }
+
+    self.check_log_disk_space();
+    self.clean_log_files();
+    self.poll_state_file()?;
+    self.poll_config_files()?;
+
+    Ok(())
+  }
+
+  /// Removes the log files past the `log_retention` limits, other than those the processes are
+  /// writing to.
+  fn clean_log_files(&mut self) {
+    let log_retention = match self.log_retention.as_mut() {
+      Some(log_retention) if log_retention.is_clean_due() => log_retention,
+      _ => return,
+    };
+
+    let open_log_files = self.processes
+      .iter()
+      .filter_map(|x| x.get_log_file())
+      .map(PathBuf::from)
+      .collect::<HashSet<PathBuf>>();
+
+    log_retention.clean(&self.configs, &open_log_files);
+  }
+
+  /// Applies the `log_disk_guard` action to the processes logging to a volume low on free space,
+  /// and undoes it once the volume has enough space again.
+  fn check_log_disk_space(&mut self) {
+    let log_disk_guard = match self.log_disk_guard.as_mut() {
+      Some(log_disk_guard) if log_disk_guard.is_check_due() => log_disk_guard,
+      _ => return,
+    };
+
+    let process_log_files = self.processes
+      .iter()
+      .filter_map(|process| {
+        let log_file = PathBuf::from(process.get_log_file()?);
+        let volume = get_volume_path(&log_file)?;
+        Some((process, log_file, volume))
+      })
+      .collect::<Vec<_>>();
+
+    let volumes = process_log_files.iter().map(|(_, _, volume)| volume.clone()).collect::<HashSet<PathBuf>>();
+    let volume_space_changes = log_disk_guard.check(&volumes);
+    let action = log_disk_guard.get_action();
+    let min_free_mbs = log_disk_guard.config.min_free_mbs;
+
+    let mut recycled_process_ids = Vec::<(String, String)>::new();
+
+    for volume_space_change in volume_space_changes {
+      let (volume, free_mbs, is_low) = match &volume_space_change {
+        VolumeSpaceChange::BecameLow { volume, free_mbs } => {
+          warn!("LogDiskGuard: Volume [{}] has {:.0}MB free, below {:.0}MB", volume.display(), free_mbs, min_free_mbs);
+          self.event_log.warning(LOG_VOLUME_LOW_EVENT_ID, &format!("Log volume [{}] has {:.0}MB free, below {:.0}MB", volume.display(), free_mbs, min_free_mbs));
+          (volume, *free_mbs, true)
+        }
+        VolumeSpaceChange::StillLow { volume, free_mbs } => (volume, *free_mbs, true),
+        VolumeSpaceChange::Recovered { volume, free_mbs } => {
+          info!("LogDiskGuard: Volume [{}] has {:.0}MB free again", volume.display(), free_mbs);
+          self.event_log.info(LOG_VOLUME_RECOVERED_EVENT_ID, &format!("Log volume [{}] has {:.0}MB free again", volume.display(), free_mbs));
+          (volume, *free_mbs, false)
+        }
+      };
+
+      let volume_log_files = process_log_files.iter().filter(|(_, _, x)| x == volume);
+
+      match action {
+        LogDiskGuardAction::PauseCapture => {
+          for (process, _, _) in volume_log_files {
+            process.is_log_write_paused.store(is_low, Ordering::Relaxed);
+          }
+        }
+        LogDiskGuardAction::RemoveRotated if is_low => {
+          for (process, log_file, _) in volume_log_files {
+            match remove_rotated_files(log_file) {
+              Ok(0) => {}
+              Ok(removed_count) => info!("Process [{}]: Removed {} rotated log files for space", &process.id, removed_count),
+              Err(remove_error) => error!("Process [{}]: Could not remove rotated log files: {}", &process.id, remove_error),
+            }
+          }
+        }
+        // Recycling again while the volume stays low would not free any more space.
+        LogDiskGuardAction::Recycle if matches!(volume_space_change, VolumeSpaceChange::BecameLow { .. }) => {
+          let largest_log_file = volume_log_files
+            .filter(|(process, _, _)| !process.is_stop_requested)
+            .max_by_key(|(_, log_file, _)| std::fs::metadata(log_file).map(|x| x.len()).unwrap_or(0));
+
+          if let Some((process, _, _)) = largest_log_file {
+            recycled_process_ids.push((process.id.clone(), format!("Log volume [{}] has {:.0}MB free", volume.display(), free_mbs)));
+          }
+        }
+        _ => {}
+      }
+    }
+
+    for (process_id, recycle_reason) in recycled_process_ids {
+      self.request_recycle(&process_id, &recycle_reason);
+    }
+  }
+
+  /// Reloads the configs when a config file was changed, added or removed since they were last
+  /// loaded. Only the processes whose config changed are recycled.
+  fn poll_config_files(&mut self) -> VoidResult {
+    let changed_path = self.config_files_modified
+      .iter()
+      .find(|(path, modified)| std::fs::metadata(path).and_then(|x| x.modified()).ok() != *modified)
+      .map(|(path, _)| path.clone());
+
+    if let Some(changed_path) = changed_path {
+      info!("Config [{}]: Changed", changed_path.display());
+      self.config_files_modified = get_config_files_modified(&self.cli_options.get_config_locations()?)?;
+      self.sender.send(Event::OrchestratorRequestReload()).unwrap();
+    }
+
+    Ok(())
+  }
+
+  /// Picks up processes enabled or disabled by another orchestrator invocation (the `enable` and
+  /// `disable` commands) since the state file was last read.
+  fn poll_state_file(&mut self) -> VoidResult {
+    let state_file_modified = get_state_file_modified(&self.state_file_path);
+    if state_file_modified == self.state_file_modified {
+      return Ok(())
+    }
+
+    let previous_state = std::mem::replace(&mut self.state, load_state(&self.state_file_path)?);
+    self.state_file_modified = state_file_modified;
+
+    for config in &self.configs {
+      let was_enabled = previous_state.is_enabled(&config.name, config.enabled);
+      let is_enabled = self.state.is_enabled(&config.name, config.enabled);
+
+      if is_enabled && !was_enabled {
+        self.sender.send(Event::ProcessRequestEnable(config.name.clone())).unwrap();
+      }
+      else if !is_enabled && was_enabled {
+        self.sender.send(Event::ProcessRequestDisable(config.name.clone())).unwrap();
       }
     }
 
@@ -107,13 +411,43 @@ impl EventPump {
     })?;
     trace!("EventPump: Registered CTRL-C handler");
 
-    let stateful_process_configs = load_stateful_process_configs()?;
-    info!("EventPump: Loaded {} config files", stateful_process_configs.len());
+    self.state = load_state(&self.state_file_path)?;
+    self.state_file_modified = get_state_file_modified(&self.state_file_path);
+
+    if let Some(config_url) = self.cli_options.config_url.clone() {
+      let mut remote_config_source = RemoteConfigSource::new(config_url)?;
+
+      // The network may not be up yet when the service starts with the machine, so the local
+      // configs are started from, and the polling reconciles the remote one once it is fetched.
+      match remote_config_source.fetch() {
+        Ok(remote_config_contents) => self.remote_config_contents = remote_config_contents,
+        Err(fetch_error) => error!("EventPump: Remote config fetch failed, starting from the local configs: {}", fetch_error),
+      }
+
+      self.start_remote_config_polling(remote_config_source);
+    }
+
+    self.config_files_modified = get_config_files_modified(&self.cli_options.get_config_locations()?)?;
+    let stateful_process_configs = self.load_configs()?;
+    self.reconcile_configs(stateful_process_configs);
 
-    self.configs = stateful_process_configs.clone();
+    start_control_server(self.control_pipe_name.clone(), self.sender.clone());
 
-    for stateful_process_config in stateful_process_configs {
-      self.sender.send(Event::ProcessConfigLoaded(stateful_process_config)).unwrap();
+    // The processes are managed without an API that can't listen, e.g. as its port is in use.
+    if let Some(management_api_config) = &self.management_api_config {
+      if let Err(start_error) = start_management_api(management_api_config, self.sender.clone()) {
+        error!("Management API: Could not start: {}", start_error);
+      }
+    }
+
+    if let Some(grpc_api_config) = &self.grpc_api_config {
+      #[cfg(feature = "grpc")]
+      if let Err(start_error) = start_grpc_api(grpc_api_config, self.sender.clone()) {
+        error!("gRPC API: Could not start: {}", start_error);
+      }
+
+      #[cfg(not(feature = "grpc"))]
+      warn!("gRPC API: Not started from [{}], as the orchestrator was built without the grpc feature", grpc_api_config.address.as_deref().unwrap_or_default());
     }
 
     let timer_sender = self.sender.clone();
@@ -127,8 +461,96 @@ impl EventPump {
     Ok(())
   }
 
-  fn on_process_config_loaded(&mut self, config: StatefulProcessConfig) -> VoidResult {
-    self.sender.send(Event::ProcessRequestStart(config.name)).unwrap();
+  fn load_configs(&self) -> Result<Vec<StatefulProcessConfig>, Box<dyn Error>> {
+    let config_locations = self.cli_options.get_config_locations()?;
+    let environment = self.cli_options.environment.as_deref();
+    let remote_config_contents = self.remote_config_contents.as_deref();
+
+    let mut stateful_process_configs = load_stateful_process_configs(&config_locations, environment, remote_config_contents)?;
+    info!("EventPump: Loaded {} configs", stateful_process_configs.len());
+
+    if self.cli_options.has_tag_filters() {
+      stateful_process_configs.retain(|x| self.cli_options.is_selected(x.tags.as_deref().unwrap_or_default()));
+      info!("EventPump: {} processes selected by tag", stateful_process_configs.len());
+    }
+
+    for stateful_process_config in &stateful_process_configs {
+      if let Err(err) = stateful_process_config.resolve_executable() {
+        error!("Config [{}]: {}", &stateful_process_config.name, err);
+      }
+    }
+
+    Ok(stateful_process_configs)
+  }
+
+  /// Brings the running processes in line with a new set of configs: new configs are started,
+  /// processes whose config changed are recycled onto it, and processes whose config was removed
+  /// are stopped.
+  fn reconcile_configs(&mut self, stateful_process_configs: Vec<StatefulProcessConfig>) {
+    for removed_config in self.configs.iter().filter(|x| !stateful_process_configs.iter().any(|c| c.name == x.name)) {
+      info!("Config [{}]: Removed", &removed_config.name);
+      self.request_stop_by_name(&removed_config.name);
+    }
+
+    for stateful_process_config in &stateful_process_configs {
+      match self.configs.iter().find(|x| x.name == stateful_process_config.name) {
+        None => {
+          self.sender.send(Event::ProcessConfigLoaded(Box::new(stateful_process_config.clone()))).unwrap();
+        }
+        Some(existing_config) if existing_config != stateful_process_config => {
+          info!("Config [{}]: Changed", &stateful_process_config.name);
+          self.request_stop_by_name(&stateful_process_config.name);
+          // Start any replicas added; the stopped instances restart with the new config.
+          self.sender.send(Event::ProcessConfigLoaded(Box::new(stateful_process_config.clone()))).unwrap();
+        }
+        Some(_) => {}
+      }
+    }
+
+    self.configs = stateful_process_configs;
+  }
+
+  fn request_stop_by_name(&self, process_name: &str) {
+    for process in self.processes.iter().filter(|p| p.config.name == process_name) {
+      self.sender.send(Event::ProcessRequestStop(process.id.clone())).unwrap();
+    }
+  }
+
+  fn start_remote_config_polling(&self, mut remote_config_source: RemoteConfigSource) {
+    let poll_interval = Duration::from_secs(self.cli_options.config_poll_interval_secs);
+    let remote_config_sender = self.sender.clone();
+
+    std::thread::spawn(move || {
+      loop {
+        std::thread::sleep(poll_interval);
+
+        match remote_config_source.fetch() {
+          Ok(Some(contents)) => remote_config_sender.send(Event::RemoteConfigFetched(contents)).unwrap(),
+          Ok(None) => trace!("EventPump: Remote config not modified"),
+          Err(fetch_error) => error!("EventPump: Remote config fetch failed: {}", fetch_error),
+        }
+      }
+    });
+  }
+
+  fn on_remote_config_fetched(&mut self, contents: String) -> VoidResult {
+    if self.remote_config_contents.as_deref() == Some(contents.as_str()) {
+      return Ok(())
+    }
+
+    info!("EventPump: Remote config changed");
+    self.remote_config_contents = Some(contents);
+
+    let stateful_process_configs = self.load_configs()?;
+    self.reconcile_configs(stateful_process_configs);
+
+    Ok(())
+  }
+
+  fn on_process_config_loaded(&mut self, config: Box<StatefulProcessConfig>) -> VoidResult {
+    for instance_index in 0..config.get_replicas() {
+      self.sender.send(Event::ProcessRequestStart(config.name.clone(), instance_index)).unwrap();
+    }
 
     Ok(())
   }
@@ -136,6 +558,11 @@ impl EventPump {
   fn on_orchestrator_request_stop(&mut self) -> VoidResult {
     self.is_stop_requested = true;
 
+    // Suspended processes could not handle a graceful stop.
+    if self.is_paused {
+      self.resume_processes();
+    }
+
     if self.processes.len() == 0 {
       self.sender.send(Event::OrchestratorStopping()).unwrap();
       return Ok(())
@@ -148,12 +575,75 @@ impl EventPump {
     Ok(())
   }
 
-  fn on_process_start(&mut self, process_name: String) -> VoidResult {
+  fn on_process_start(&mut self, process_name: String, instance_index: usize) -> VoidResult {
+    let start_result = self.start_process(&process_name, instance_index);
+
+    if let Err(start_error) = &start_result {
+      self.event_log.error(PROCESS_START_FAILED_EVENT_ID, &format!("Process [{}] instance {} failed to start: {}", &process_name, instance_index, start_error));
+    }
+
+    if self.rolling_restart_instance == Some((process_name, instance_index)) {
+      self.continue_rolling_restart();
+    }
+
+    start_result
+  }
+
+  fn start_process(&mut self, process_name: &str, instance_index: usize) -> VoidResult {
     if let Some(config) = self.configs.iter().find(|x| x.name == process_name) {
-      let mut process = StatefulProcess::new(config.clone(), self.sender.clone());
+      if !self.state.is_enabled(&config.name, config.enabled) {
+        info!("Process [{}]: Disabled, not starting", &config.name);
+        return Ok(())
+      }
+
+      if self.is_suspended && config.restart_on_resume.unwrap_or(false) {
+        return Ok(())
+      }
+
+      if self.stopped_process_names.contains(&config.name) {
+        return Ok(())
+      }
+
+      // The replica count may have been lowered, or the instance is already running.
+      if instance_index >= config.get_replicas() {
+        return Ok(())
+      }
+
+      if self.processes.iter().any(|p| p.config.name == process_name && p.instance_index == instance_index) {
+        return Ok(())
+      }
+
+      let mut process = StatefulProcess::new(config.clone(), instance_index, self.sender.clone());
+      process.is_output_mirrored = self.cli_options.console;
+      process.output_buffer = self.get_output_buffer(&process.config, instance_index);
+      process.log_shipper = self.log_shipper.as_ref().map(|x| x.for_process(&process.config.name, instance_index));
 
+      let event_log_patterns = process.config.get_event_log_patterns();
+      if !event_log_patterns.is_empty() {
+        process.output_events = Some(OutputEvents { event_log: self.event_log.clone(), rules: event_log_patterns });
+      }
+
+      let started_at = SystemTime::now();
       process.start_instance()?;
-      info!("Process [{}]: Started", &process.config.name);
+      info!("Process [{}]: Started", &process.id);
+
+      // Started while paused, e.g. by a restart, it is paused like the others.
+      if self.is_paused {
+        if let Err(suspend_error) = process.suspend() {
+          error!("Process [{}]: Could not suspend: {}", &process.id, suspend_error);
+        }
+      }
+      self.event_log.info(PROCESS_STARTED_EVENT_ID, &format!("Process [{}] started", &process.id));
+
+      if let Some(otlp_exporter) = &self.otlp_exporter {
+        otlp_exporter.export_process_span("process.start", &process, started_at, &[]);
+      }
+
+      let restart_count = *self.process_restart_counts
+        .entry((process_name.to_string(), instance_index))
+        .and_modify(|x| *x += 1)
+        .or_insert(0);
+      self.performance_counters.update(&process, restart_count);
 
       self.processes.push(process);
     }
@@ -171,6 +661,15 @@ impl EventPump {
     Ok(())
   }
 
+  fn on_process_log_file_fallback(&mut self, process_id: String, log_file: String, fallback_log_file: String, open_error: String) -> VoidResult {
+    let message = format!("Process [{}] writes to [{}], as log file [{}] could not be opened: {}", &process_id, &fallback_log_file, &log_file, &open_error);
+
+    warn!("Process [{}]: Writing to [{}], as log file [{}] could not be opened: {}", &process_id, &fallback_log_file, &log_file, &open_error);
+    self.event_log.warning(LOG_FILE_FALLBACK_EVENT_ID, &message);
+
+    Ok(())
+  }
+
   fn on_process_stopped(&mut self, process_id: String) -> VoidResult {
     let process_option = self.find_process_by_process_id(process_id.clone());
     if process_option.is_none() {
@@ -179,9 +678,32 @@ impl EventPump {
 
     let process = process_option.unwrap();
     let process_name = process.config.name.clone();
+    let instance_index = process.instance_index;
+
+    let is_stop_requested = process.is_stop_requested;
+    let exit_description = get_exit_description(process);
 
     process.on_stopped();
 
+    let stopped_process = self.processes.iter().find(|p| p.id == process_id);
+    if let (Some(otlp_exporter), Some(process)) = (&self.otlp_exporter, stopped_process) {
+      let stop_reason = if is_stop_requested { "requested" } else { "exited" };
+      let exit_code = process.get_exit_code().map(|x| x.to_string()).unwrap_or_default();
+      let started_at = process.stop_requested_at.unwrap_or_else(SystemTime::now);
+
+      otlp_exporter.export_process_span("process.stop", process, started_at, &[("reason", stop_reason.to_string()), ("exit_code", exit_code)]);
+    }
+
+    if is_stop_requested {
+      self.event_log.info(PROCESS_STOPPED_EVENT_ID, &format!("Process [{}] stopped", &process_id));
+    }
+    else {
+      self.event_log.error(PROCESS_EXITED_EVENT_ID, &format!("Process [{}] exited unexpectedly\n\n{}", &process_id, exit_description));
+    }
+
+    let restart_count = self.process_restart_counts.get(&(process_name.clone(), instance_index)).copied().unwrap_or(0);
+    self.performance_counters.clear(&process_name, instance_index, restart_count);
+
     let index_option = self.processes.iter().position(|p| p.id == process_id);
     if let Some(index) = index_option {
       self.processes.remove(index);
@@ -195,7 +717,7 @@ impl EventPump {
       return Ok(())
     }
     else {
-      self.sender.send(Event::ProcessRequestStart(process_name)).unwrap();
+      self.sender.send(Event::ProcessRequestStart(process_name, instance_index)).unwrap();
     }
 
     Ok(())
@@ -212,8 +734,8 @@ impl EventPump {
         return Ok(())
       }
 
-      if process.is_recycle_required() {
-        self.sender.send(Event::ProcessRequestStop(process_id.clone())).unwrap();
+      if let Some(recycle_reason) = process.get_recycle_reason().filter(|_| !process.is_stop_requested) {
+        self.request_recycle(&process_id, &recycle_reason);
         return Ok(())
       }
     }
@@ -221,14 +743,414 @@ impl EventPump {
     Ok(())
   }
 
+  fn on_process_request_enable(&mut self, process_name: String) -> VoidResult {
+    self.set_enabled_override(&process_name, true)?;
+    info!("Process [{}]: Enabled", &process_name);
+
+    if let Some(config) = self.configs.iter().find(|x| x.name == process_name) {
+      for instance_index in 0..config.get_replicas() {
+        self.sender.send(Event::ProcessRequestStart(process_name.clone(), instance_index)).unwrap();
+      }
+    }
+
+    Ok(())
+  }
+
+  fn on_process_request_disable(&mut self, process_name: String) -> VoidResult {
+    self.set_enabled_override(&process_name, false)?;
+    info!("Process [{}]: Disabled", &process_name);
+
+    for process in self.processes.iter().filter(|p| p.config.name == process_name) {
+      self.sender.send(Event::ProcessRequestStop(process.id.clone())).unwrap();
+    }
+
+    Ok(())
+  }
+
+  /// Records that a process was enabled or disabled at runtime, persisting it so it survives a
+  /// restart of the orchestrator.
+  fn set_enabled_override(&mut self, process_name: &str, enabled: bool) -> VoidResult {
+    if self.state.enabled_overrides.get(process_name) == Some(&enabled) {
+      return Ok(())
+    }
+
+    self.state.enabled_overrides.insert(process_name.to_string(), enabled);
+    save_state(&self.state_file_path, &self.state)?;
+    self.state_file_modified = get_state_file_modified(&self.state_file_path);
+
+    Ok(())
+  }
+
+  fn on_orchestrator_request_reload(&mut self) -> VoidResult {
+    info!("EventPump: Reloading configs");
+    self.config_files_modified = get_config_files_modified(&self.cli_options.get_config_locations()?)?;
+
+    let stateful_process_configs = self.load_configs()?;
+    self.reconcile_configs(stateful_process_configs);
+
+    Ok(())
+  }
+
+  /// Restarts the running processes one at a time, stopping the next one once the previous one has
+  /// been started again.
+  fn on_orchestrator_request_rolling_restart(&mut self) -> VoidResult {
+    if self.rolling_restart_instance.is_some() {
+      info!("EventPump: Rolling restart already in progress");
+      return Ok(())
+    }
+
+    self.rolling_restart_process_ids = self.processes.iter().map(|p| p.id.clone()).collect();
+    info!("EventPump: Rolling restart of {} processes", self.rolling_restart_process_ids.len());
+
+    self.continue_rolling_restart();
+
+    Ok(())
+  }
+
+  fn continue_rolling_restart(&mut self) {
+    self.rolling_restart_instance = None;
+
+    while let Some(process_id) = self.rolling_restart_process_ids.pop_front() {
+      if let Some(process) = self.processes.iter().find(|p| p.id == process_id) {
+        self.rolling_restart_instance = Some((process.config.name.clone(), process.instance_index));
+        self.sender.send(Event::ProcessRequestStop(process_id)).unwrap();
+        return;
+      }
+    }
+
+    info!("EventPump: Rolling restart finished");
+  }
+
+  fn on_orchestrator_request_status_dump(&mut self) -> VoidResult {
+    info!("Status: {} configs, {} processes running", self.configs.len(), self.processes.len());
+
+    for config in &self.configs {
+      let running_count = self.processes.iter().filter(|p| p.config.name == config.name).count();
+      let enabled = self.state.is_enabled(&config.name, config.enabled);
+      info!("Status: Config [{}]: enabled {}, {}/{} instances running", &config.name, enabled, running_count, config.get_replicas());
+    }
+
+    for process in &self.processes {
+      info!(
+        "Status: Process [{}]: uptime {:.0} seconds, memory {:.1}MB",
+        &process.id,
+        process.duration_secs.unwrap_or_default(),
+        process.memory_usage_mbs.unwrap_or_default());
+    }
+
+    Ok(())
+  }
+
+  /// Suspends every process, e.g. while a backup or snapshot is taken. Processes are not recycled
+  /// while paused.
+  fn on_orchestrator_request_pause(&mut self, paused_sender: Sender<()>) -> VoidResult {
+    self.is_paused = true;
+
+    for process in &mut self.processes {
+      if let Err(suspend_error) = process.suspend() {
+        error!("Process [{}]: Could not suspend: {}", &process.id, suspend_error);
+      }
+    }
+
+    paused_sender.send(()).ok();
+    Ok(())
+  }
+
+  fn on_orchestrator_request_continue(&mut self, continued_sender: Sender<()>) -> VoidResult {
+    self.resume_processes();
+    continued_sender.send(()).ok();
+    Ok(())
+  }
+
+  fn resume_processes(&mut self) {
+    self.is_paused = false;
+
+    for process in &mut self.processes {
+      if let Err(resume_error) = process.resume() {
+        error!("Process [{}]: Could not resume: {}", &process.id, resume_error);
+      }
+    }
+  }
+
+  /// Stops the processes that don't survive the machine sleeping, before it goes into standby.
+  fn on_orchestrator_suspending(&mut self) -> VoidResult {
+    self.is_suspended = true;
+
+    for config in self.configs.iter().filter(|x| x.restart_on_resume.unwrap_or(false)) {
+      info!("Config [{}]: Stopping for standby", &config.name);
+      self.request_stop_by_name(&config.name);
+    }
+
+    Ok(())
+  }
+
+  fn on_orchestrator_resumed(&mut self) -> VoidResult {
+    if !self.is_suspended {
+      return Ok(())
+    }
+
+    self.is_suspended = false;
+
+    for config in self.configs.iter().filter(|x| x.restart_on_resume.unwrap_or(false)) {
+      info!("Config [{}]: Starting after resume", &config.name);
+      self.sender.send(Event::ProcessConfigLoaded(Box::new(config.clone()))).unwrap();
+    }
+
+    Ok(())
+  }
+
+  /// Applies the `on_session_change` action each process configures for the session change.
+  fn on_orchestrator_session_changed(&mut self, session_change_event: SessionChangeEvent) -> VoidResult {
+    for config in &self.configs {
+      let session_change_action = config.on_session_change
+        .as_ref()
+        .and_then(|x| x.get(&session_change_event))
+        .copied();
+
+      match session_change_action {
+        Some(SessionChangeAction::Start) => {
+          info!("Config [{}]: Starting on {:?}", &config.name, session_change_event);
+          self.stopped_process_names.remove(&config.name);
+          self.sender.send(Event::ProcessConfigLoaded(Box::new(config.clone()))).unwrap();
+        }
+        Some(SessionChangeAction::Stop) => {
+          info!("Config [{}]: Stopping on {:?}", &config.name, session_change_event);
+          self.stopped_process_names.insert(config.name.clone());
+          self.request_stop_by_name(&config.name);
+        }
+        Some(SessionChangeAction::Restart) => {
+          info!("Config [{}]: Restarting on {:?}", &config.name, session_change_event);
+          self.stopped_process_names.remove(&config.name);
+          self.request_stop_by_name(&config.name);
+          self.sender.send(Event::ProcessConfigLoaded(Box::new(config.clone()))).unwrap();
+        }
+        None => {}
+      }
+    }
+
+    Ok(())
+  }
+
+  fn on_control_request_received(&mut self, request: ControlRequest, caller: ControlCaller, response_sender: Sender<ControlResponse>) -> VoidResult {
+    info!("Control: {:?} from {} [{}]", &request, caller.channel, &caller.identity);
+
+    let operation_name = request.get_operation_name();
+    let target = request.get_target().map(|x| x.to_string());
+    let is_audited = !request.is_read_only();
+
+    let response = match request {
+      ControlRequest::Status => ControlResponse {
+        ok: true,
+        processes: Some(self.get_process_statuses()),
+        ..Default::default()
+      },
+      ControlRequest::Events => ControlResponse {
+        ok: true,
+        events: Some(self.recent_events.iter().cloned().collect()),
+        ..Default::default()
+      },
+      ControlRequest::Logs { name } if !self.configs.iter().any(|x| x.name == name) => {
+        ControlResponse::failure_of_kind(ControlErrorKind::ProcessNotConfigured, OrchestratorError::ProcessNotConfigured(name).to_string())
+      }
+      ControlRequest::Logs { name } => ControlResponse {
+        ok: true,
+        logs: Some(self.get_process_logs(&name)),
+        ..Default::default()
+      },
+      ControlRequest::Reload => match self.on_orchestrator_request_reload() {
+        Ok(()) => ControlResponse::success(),
+        Err(reload_error) => ControlResponse::failure_of_kind(ControlErrorKind::ConfigInvalid, reload_error.to_string()),
+      },
+      ControlRequest::Start { name } | ControlRequest::Stop { name } | ControlRequest::Restart { name } if !self.configs.iter().any(|x| x.name == name) => {
+        ControlResponse::failure_of_kind(ControlErrorKind::ProcessNotConfigured, OrchestratorError::ProcessNotConfigured(name).to_string())
+      }
+      ControlRequest::Start { name } => {
+        let config = self.configs.iter().find(|x| x.name == name).unwrap();
+        self.stopped_process_names.remove(&name);
+        self.sender.send(Event::ProcessConfigLoaded(Box::new(config.clone()))).unwrap();
+        ControlResponse::success()
+      }
+      ControlRequest::Stop { name } => {
+        self.stopped_process_names.insert(name.clone());
+        self.request_stop_by_name(&name);
+        ControlResponse::success()
+      }
+      ControlRequest::Restart { name } => {
+        let config = self.configs.iter().find(|x| x.name == name).unwrap();
+        self.stopped_process_names.remove(&name);
+        self.request_stop_by_name(&name);
+        self.sender.send(Event::ProcessConfigLoaded(Box::new(config.clone()))).unwrap();
+        ControlResponse::success()
+      }
+    };
+
+    if is_audited {
+      let result = match &response.error {
+        Some(error) => Err(error.as_str()),
+        None => Ok(()),
+      };
+
+      self.audit_log.record(caller.channel, &caller.identity, operation_name, target.as_deref(), result);
+    }
+
+    // The client may have given up waiting.
+    let _ = response_sender.send(response);
+
+    Ok(())
+  }
+
+  /// Keeps the event for the `events` control request and sends it to the event streams and the
+  /// log collector.
+  fn report_event(&mut self, message_string: &str) {
+    let time = Utc::now();
+
+    if let Some(log_shipper) = &self.log_shipper {
+      log_shipper.ship_event(time, message_string);
+    }
+
+    let recent_event = RecentEvent {
+      time: time.to_rfc3339(),
+      event: message_string.to_string(),
+    };
+
+    if self.recent_events.len() == RECENT_EVENTS_CAPACITY {
+      self.recent_events.pop_front();
+    }
+
+    self.recent_events.push_back(recent_event.clone());
+    self.publish(&StreamMessage::Event(recent_event));
+  }
+
+  fn on_event_stream_subscribed(&mut self, stream_sender: Sender<StreamMessage>) -> VoidResult {
+    self.event_stream_senders.push(stream_sender);
+
+    Ok(())
+  }
+
+  /// Sends the message to every event stream, dropping the streams whose client has gone.
+  fn publish(&mut self, message: &StreamMessage) {
+    self.event_stream_senders.retain(|x| x.send(message.clone()).is_ok());
+  }
+
+  fn get_process_statuses(&self) -> Vec<ProcessStatus> {
+    let mut process_statuses = Vec::<ProcessStatus>::new();
+
+    for config in &self.configs {
+      let is_enabled = self.state.is_enabled(&config.name, config.enabled);
+
+      for instance_index in 0..config.get_replicas() {
+        let process = self.processes.iter().find(|p| p.config.name == config.name && p.instance_index == instance_index);
+
+        let state = match process {
+          Some(process) if process.is_stop_requested => "stopping",
+          Some(_) if self.is_paused => "paused",
+          Some(_) => "running",
+          None if !is_enabled => "disabled",
+          None => "stopped",
+        };
+
+        process_statuses.push(ProcessStatus {
+          name: config.name.clone(),
+          instance_index,
+          id: process.map(|p| p.id.clone()),
+          pid: process.and_then(|p| p.get_pid()),
+          state: state.to_string(),
+          uptime_secs: process.and_then(|p| p.duration_secs),
+          memory_mbs: process.and_then(|p| p.memory_usage_mbs),
+          cpu_percent: process.and_then(|p| p.cpu_percent),
+          restarts: self.process_restart_counts.get(&(config.name.clone(), instance_index)).copied().unwrap_or(0),
+          log_file: process.and_then(|p| p.get_log_file()),
+        });
+      }
+    }
+
+    process_statuses
+  }
+
+  /// Output kept for each instance of the process, leaving out those without an output buffer, so
+  /// none when it has no `output_buffer_kbs`.
+  fn get_process_logs(&self, process_name: &str) -> Vec<ProcessLogs> {
+    let config = self.configs.iter().find(|x| x.name == process_name).unwrap();
+
+    (0..config.get_replicas())
+      .filter_map(|instance_index| {
+        let output_buffer = self.output_buffers.get(&(config.name.clone(), instance_index))?;
+
+        Some(ProcessLogs {
+          name: config.name.clone(),
+          instance_index,
+          lines: output_buffer.get_lines(),
+        })
+      })
+      .collect()
+  }
+
+  /// Buffer the instance writes its output to, kept from its previous runs unless
+  /// `output_buffer_kbs` changed.
+  fn get_output_buffer(&mut self, config: &StatefulProcessConfig, instance_index: usize) -> Option<OutputBuffer> {
+    let key = (config.name.clone(), instance_index);
+
+    let capacity = match config.output_buffer_kbs {
+      Some(output_buffer_kbs) => output_buffer_kbs * 1024,
+      None => {
+        self.output_buffers.remove(&key);
+        return None;
+      }
+    };
+
+    let output_buffer = self.output_buffers
+      .entry(key)
+      .and_modify(|x| if x.get_capacity() != capacity { *x = OutputBuffer::new(capacity) })
+      .or_insert_with(|| OutputBuffer::new(capacity));
+
+    Some(output_buffer.clone())
+  }
+
   fn on_orchestrator_stopping(&mut self) -> VoidResult {
     self.is_stopped = true;
 
     Ok(())
   }
 
+  fn request_recycle(&self, process_id: &str, recycle_reason: &str) {
+    info!("Process [{}]: {}", process_id, recycle_reason);
+    self.event_log.warning(PROCESS_RECYCLED_EVENT_ID, &format!("Process [{}] recycled: {}", process_id, recycle_reason));
+
+    if let Some(otlp_exporter) = &self.otlp_exporter {
+      if let Some(process) = self.processes.iter().find(|p| p.id == process_id) {
+        otlp_exporter.export_process_span("process.recycle", process, SystemTime::now(), &[("reason", recycle_reason.to_string())]);
+      }
+    }
+
+    self.sender.send(Event::ProcessRequestStop(process_id.to_string())).unwrap();
+  }
+
   fn find_process_by_process_id(&mut self, process_id: String) -> Option<&mut StatefulProcess> {
     let item = self.processes.iter_mut().find(|p| p.id == process_id);
     return item;
   }
 }
+
+/// Whether the event is kept for the `events` control request and sent to the event streams.
+/// Polls and status queries happen every second and would drown everything else out.
+fn is_reported_event(event: &Event) -> bool {
+  match event {
+    Event::OrchestratorTick() | Event::ProcessRequestPoll(_) | Event::StreamSubscribed(_) => false,
+    Event::ControlRequestReceived(request, _, _) if request.is_read_only() => false,
+    _ => true,
+  }
+}
+
+/// Details of an exited process for its Event Log entry, one per line so alerts can pick them out.
+fn get_exit_description(process: &StatefulProcess) -> String {
+  let describe = |value: Option<String>| value.unwrap_or_else(|| String::from("unknown"));
+
+  format!(
+    "Name: {}\nId: {}\nPID: {}\nExit code: {}\nUptime: {}\nLast memory: {}",
+    &process.config.name,
+    &process.id,
+    describe(process.get_pid().map(|x| x.to_string())),
+    describe(process.get_exit_code().map(|x| x.to_string())),
+    describe(process.get_duration_in_seconds().or(process.duration_secs).map(|x| format!("{:.0} seconds", x))),
+    describe(process.memory_usage_mbs.map(|x| format!("{:.1}MB", x))))
+}
@@ -0,0 +1,131 @@
+use std::collections::BTreeMap;
+use std::ffi::OsStr;
+use std::os::windows::ffi::OsStrExt;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+use winapi::shared::minwindef::{FALSE, TRUE};
+use winapi::um::fileapi::{FindCloseChangeNotification, FindFirstChangeNotificationW, FindNextChangeNotification};
+use winapi::um::handleapi::INVALID_HANDLE_VALUE;
+use winapi::um::synchapi::WaitForSingleObject;
+use winapi::um::winbase::WAIT_OBJECT_0;
+use winapi::um::winnt::{HANDLE, FILE_NOTIFY_CHANGE_DIR_NAME, FILE_NOTIFY_CHANGE_FILE_NAME, FILE_NOTIFY_CHANGE_LAST_WRITE, FILE_NOTIFY_CHANGE_SIZE};
+
+/// Changes to the files of a directory that are worth listing them again for.
+const CHANGE_NOTIFY_FILTER: u32 = FILE_NOTIFY_CHANGE_FILE_NAME | FILE_NOTIFY_CHANGE_DIR_NAME | FILE_NOTIFY_CHANGE_SIZE | FILE_NOTIFY_CHANGE_LAST_WRITE;
+
+/// Modified time and size of each file under the watched paths, which changes when any of them is
+/// modified, added or removed, even when an older file is copied over another.
+type WatchedFilesFingerprint = BTreeMap<PathBuf, (Option<SystemTime>, u64)>;
+
+/// Tells when the files under some paths change. Windows signals changes in the directories
+/// holding them, and only then are the files listed again and compared with what they were, so a
+/// large folder is not walked at every poll.
+pub struct FileWatcher {
+  watched_paths: Vec<PathBuf>,
+  /// Change notifications of the watched directories, and of the directories of watched files.
+  notification_handles: Vec<HANDLE>,
+  /// Set when a path could not be watched, e.g. as neither it nor its directory exist yet, so the
+  /// files are listed at every poll instead.
+  is_listed_every_poll: bool,
+  fingerprint: WatchedFilesFingerprint,
+}
+
+impl FileWatcher {
+  pub fn new(watched_paths: &[String]) -> Self {
+    let watched_paths = watched_paths.iter().map(PathBuf::from).collect::<Vec<PathBuf>>();
+    let mut notification_handles = Vec::<HANDLE>::new();
+    let mut is_listed_every_poll = false;
+
+    for watched_path in &watched_paths {
+      match watch_changes(watched_path) {
+        Some(notification_handle) => notification_handles.push(notification_handle),
+        None => is_listed_every_poll = true,
+      }
+    }
+
+    let fingerprint = get_watched_files_fingerprint(&watched_paths);
+
+    Self {
+      watched_paths,
+      notification_handles,
+      is_listed_every_poll,
+      fingerprint,
+    }
+  }
+
+  /// Whether any of the files has been modified, added or removed since the last poll.
+  pub fn poll(&mut self) -> bool {
+    let mut is_change_signaled = self.is_listed_every_poll;
+
+    for notification_handle in &self.notification_handles {
+      unsafe {
+        if WaitForSingleObject(*notification_handle, 0) == WAIT_OBJECT_0 {
+          is_change_signaled = true;
+          FindNextChangeNotification(*notification_handle);
+        }
+      }
+    }
+
+    if !is_change_signaled {
+      return false;
+    }
+
+    // A change in the directory of a watched file may be to another file of the directory.
+    let fingerprint = get_watched_files_fingerprint(&self.watched_paths);
+    if fingerprint == self.fingerprint {
+      return false;
+    }
+
+    self.fingerprint = fingerprint;
+    true
+  }
+}
+
+impl Drop for FileWatcher {
+  fn drop(&mut self) {
+    for notification_handle in &self.notification_handles {
+      unsafe { FindCloseChangeNotification(*notification_handle); }
+    }
+  }
+}
+
+/// Change notification of a directory and everything under it, or of the directory of a file.
+fn watch_changes(watched_path: &Path) -> Option<HANDLE> {
+  let (directory_path, is_subtree_watched) = match watched_path.is_dir() {
+    true => (watched_path, TRUE),
+    false => (watched_path.parent().filter(|x| x.is_dir())?, FALSE),
+  };
+
+  let directory_path_wide = OsStr::new(directory_path).encode_wide().chain(std::iter::once(0)).collect::<Vec<u16>>();
+  let notification_handle = unsafe { FindFirstChangeNotificationW(directory_path_wide.as_ptr(), is_subtree_watched, CHANGE_NOTIFY_FILTER) };
+
+  Some(notification_handle).filter(|x| *x != INVALID_HANDLE_VALUE)
+}
+
+fn get_watched_files_fingerprint(watched_paths: &[PathBuf]) -> WatchedFilesFingerprint {
+  let mut watched_files_fingerprint = WatchedFilesFingerprint::new();
+
+  for watched_path in watched_paths {
+    add_to_watched_files_fingerprint(watched_path, &mut watched_files_fingerprint);
+  }
+
+  watched_files_fingerprint
+}
+
+fn add_to_watched_files_fingerprint(path: &Path, watched_files_fingerprint: &mut WatchedFilesFingerprint) {
+  let metadata = match std::fs::metadata(path) {
+    Ok(metadata) => metadata,
+    Err(_) => return,
+  };
+
+  if metadata.is_dir() {
+    if let Ok(directory_entries) = std::fs::read_dir(path) {
+      for directory_entry in directory_entries.flatten() {
+        add_to_watched_files_fingerprint(&directory_entry.path(), watched_files_fingerprint);
+      }
+    }
+  }
+  else {
+    watched_files_fingerprint.insert(path.to_path_buf(), (metadata.modified().ok(), metadata.len()));
+  }
+}
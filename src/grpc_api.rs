@@ -0,0 +1,354 @@
+use crate::control_server::{
+  ControlCaller, ControlErrorKind, ControlRequest as PipeControlRequest, ControlResponse as PipeControlResponse, ProcessStatus as PipeProcessStatus,
+  StreamMessage as PipeStreamMessage, send_to_event_pump,
+};
+use crate::errors::OrchestratorError;
+use crate::event_pump::Event;
+use crate::management_api::is_equal_in_constant_time;
+use crate::orchestrator_config::GrpcApiConfig;
+use crate::secrets::resolve_secret;
+
+use std::convert::TryFrom;
+use std::error::Error;
+use std::net::{TcpListener, ToSocketAddrs};
+use std::sync::mpsc::{Sender, channel};
+use log::{error, info, trace};
+use tokio_stream::wrappers::{ReceiverStream, TcpListenerStream};
+use tonic::{Request, Response, Status};
+
+mod generated {
+  include!(concat!(env!("OUT_DIR"), "/process_orchestrator.v1.Orchestrator.rs"));
+}
+
+use generated::orchestrator_server::{Orchestrator, OrchestratorServer};
+
+const DEFAULT_GRPC_API_ADDRESS: &str = "127.0.0.1:8651";
+
+/// Messages the event stream of a client buffers while it is slow to receive them.
+const EVENT_STREAM_BUFFER_SIZE: usize = 64;
+
+// Messages of `proto/orchestrator.proto`, by their field numbers.
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct StatusRequest {}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct StatusResponse {
+  #[prost(message, repeated, tag = "1")]
+  pub processes: Vec<ProcessStatus>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct ProcessStatus {
+  #[prost(string, tag = "1")]
+  pub name: String,
+  #[prost(uint32, tag = "2")]
+  pub instance_index: u32,
+  #[prost(string, optional, tag = "3")]
+  pub id: Option<String>,
+  #[prost(uint32, optional, tag = "4")]
+  pub pid: Option<u32>,
+  #[prost(string, tag = "5")]
+  pub state: String,
+  #[prost(double, optional, tag = "6")]
+  pub uptime_secs: Option<f64>,
+  #[prost(double, optional, tag = "7")]
+  pub memory_mbs: Option<f64>,
+  #[prost(uint64, tag = "8")]
+  pub restarts: u64,
+  #[prost(string, optional, tag = "9")]
+  pub log_file: Option<String>,
+  #[prost(double, optional, tag = "10")]
+  pub cpu_percent: Option<f64>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct ControlRequest {
+  #[prost(enumeration = "Operation", tag = "1")]
+  pub operation: i32,
+  #[prost(string, tag = "2")]
+  pub name: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, prost::Enumeration)]
+#[repr(i32)]
+pub enum Operation {
+  Unspecified = 0,
+  Start = 1,
+  Stop = 2,
+  Restart = 3,
+  Reload = 4,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct ControlResponse {
+  #[prost(bool, tag = "1")]
+  pub ok: bool,
+  #[prost(string, tag = "2")]
+  pub error: String,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct LogsRequest {
+  #[prost(string, tag = "1")]
+  pub name: String,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct LogsResponse {
+  #[prost(message, repeated, tag = "1")]
+  pub logs: Vec<InstanceLogs>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct InstanceLogs {
+  #[prost(string, tag = "1")]
+  pub name: String,
+  #[prost(uint32, tag = "2")]
+  pub instance_index: u32,
+  #[prost(string, repeated, tag = "3")]
+  pub lines: Vec<String>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct StreamEventsRequest {}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct StreamMessage {
+  #[prost(oneof = "StreamMessageKind", tags = "1, 2")]
+  pub message: Option<StreamMessageKind>,
+}
+
+#[derive(Clone, PartialEq, prost::Oneof)]
+pub enum StreamMessageKind {
+  #[prost(message, tag = "1")]
+  Event(StreamEvent),
+  #[prost(message, tag = "2")]
+  Metrics(StreamMetrics),
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct StreamEvent {
+  #[prost(string, tag = "1")]
+  pub time: String,
+  #[prost(string, tag = "2")]
+  pub event: String,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct StreamMetrics {
+  #[prost(string, tag = "1")]
+  pub time: String,
+  #[prost(message, repeated, tag = "2")]
+  pub processes: Vec<ProcessStatus>,
+}
+
+impl From<PipeProcessStatus> for ProcessStatus {
+  fn from(process_status: PipeProcessStatus) -> Self {
+    Self {
+      name: process_status.name,
+      instance_index: process_status.instance_index as u32,
+      id: process_status.id,
+      pid: process_status.pid,
+      state: process_status.state,
+      uptime_secs: process_status.uptime_secs,
+      memory_mbs: process_status.memory_mbs,
+      restarts: process_status.restarts,
+      log_file: process_status.log_file,
+      cpu_percent: process_status.cpu_percent,
+    }
+  }
+}
+
+impl From<PipeStreamMessage> for StreamMessage {
+  fn from(stream_message: PipeStreamMessage) -> Self {
+    let message = match stream_message {
+      PipeStreamMessage::Event(recent_event) => StreamMessageKind::Event(StreamEvent { time: recent_event.time, event: recent_event.event }),
+      PipeStreamMessage::Metrics { time, processes } => StreamMessageKind::Metrics(StreamMetrics {
+        time,
+        processes: processes.into_iter().map(ProcessStatus::from).collect(),
+      }),
+    };
+
+    Self { message: Some(message) }
+  }
+}
+
+struct OrchestratorService {
+  sender: Sender<Event>,
+  tokens: Vec<String>,
+}
+
+/// Serves the control requests of the control pipe over gRPC, as the `Orchestrator` service of
+/// `proto/orchestrator.proto`, for clients generated in other languages.
+pub fn start_grpc_api(config: &GrpcApiConfig, sender: Sender<Event>) -> Result<(), Box<dyn Error>> {
+  let address = config.address.clone().unwrap_or_else(|| String::from(DEFAULT_GRPC_API_ADDRESS));
+
+  let tokens = config.tokens
+    .as_deref()
+    .unwrap_or_default()
+    .iter()
+    .map(|x| resolve_secret(x))
+    .collect::<Result<Vec<String>, Box<dyn Error>>>()?;
+
+  // Without TLS, tokens and control requests would cross the network in plain text.
+  if !address.to_socket_addrs()?.all(|x| x.ip().is_loopback()) {
+    return Err(Box::new(OrchestratorError::GrpcApiNotLoopback(address)));
+  }
+
+  // Bound here, so an address in use fails the start rather than the server thread.
+  let listener = TcpListener::bind(address.as_str())?;
+  listener.set_nonblocking(true)?;
+
+  let runtime = tokio::runtime::Builder::new_multi_thread().enable_all().build()?;
+
+  info!("gRPC API: Listening on [{}]", &address);
+
+  let service = OrchestratorService { sender, tokens };
+
+  std::thread::spawn(move || {
+    let serve_result = runtime.block_on(async move {
+      let listener = tokio::net::TcpListener::from_std(listener)?;
+
+      tonic::transport::Server::builder()
+        .add_service(OrchestratorServer::new(service))
+        .serve_with_incoming(TcpListenerStream::new(listener))
+        .await
+        .map_err(|x| Box::new(x) as Box<dyn Error>)
+    });
+
+    if let Err(serve_error) = serve_result {
+      error!("gRPC API: Stopped serving: {}", serve_error);
+    }
+  });
+
+  Ok(())
+}
+
+// Failures are the status tonic answers with, however large.
+#[allow(clippy::result_large_err)]
+impl OrchestratorService {
+  /// Who sent the request, by its address and the number of the token it carries in its
+  /// `authorization: Bearer` metadata. Every request is authorized, without a token, when no
+  /// tokens are configured.
+  fn get_authorized_caller<T>(&self, request: &Request<T>) -> Result<ControlCaller, Status> {
+    let mut identity = request.remote_addr().map(|x| x.to_string()).unwrap_or_else(|| String::from("unknown address"));
+
+    if !self.tokens.is_empty() {
+      let token_index = request.metadata()
+        .get("authorization")
+        .and_then(|x| x.to_str().ok())
+        .and_then(|x| x.strip_prefix("Bearer "))
+        .and_then(|x| self.tokens.iter().position(|token| is_equal_in_constant_time(token.as_bytes(), x.trim().as_bytes())))
+        .ok_or_else(|| Status::unauthenticated("Missing or invalid bearer token"))?;
+
+      identity = format!("{} (token {})", identity, token_index + 1);
+    }
+
+    Ok(ControlCaller { channel: "grpc", identity })
+  }
+
+  /// Sends the request to the event pump from a blocking thread, as it waits for the response.
+  async fn send_to_event_pump(&self, request: PipeControlRequest, caller: ControlCaller) -> Result<PipeControlResponse, Status> {
+    let sender = self.sender.clone();
+
+    tokio::task::spawn_blocking(move || send_to_event_pump(request, caller, &sender))
+      .await
+      .map_err(|x| Status::internal(x.to_string()))
+  }
+}
+
+/// The status a failed response is answered with, as the management API answers with `404` a
+/// process that is not configured and with `504` when the orchestrator did not answer.
+fn get_error_status(response: &PipeControlResponse) -> Option<Status> {
+  if response.ok {
+    return None;
+  }
+
+  let error = response.error.clone().unwrap_or_default();
+
+  Some(match response.error_kind {
+    Some(ControlErrorKind::ProcessNotConfigured) => Status::not_found(error),
+    Some(ControlErrorKind::TimedOut) => Status::deadline_exceeded(error),
+    _ => Status::failed_precondition(error),
+  })
+}
+
+#[tonic::async_trait]
+impl Orchestrator for OrchestratorService {
+  type StreamEventsStream = ReceiverStream<Result<StreamMessage, Status>>;
+
+  async fn status(&self, request: Request<StatusRequest>) -> Result<Response<StatusResponse>, Status> {
+    let caller = self.get_authorized_caller(&request)?;
+    let response = self.send_to_event_pump(PipeControlRequest::Status, caller).await?;
+
+    if let Some(error_status) = get_error_status(&response) {
+      return Err(error_status);
+    }
+
+    let processes = response.processes.unwrap_or_default().into_iter().map(ProcessStatus::from).collect();
+    Ok(Response::new(StatusResponse { processes }))
+  }
+
+  async fn control(&self, request: Request<ControlRequest>) -> Result<Response<ControlResponse>, Status> {
+    let caller = self.get_authorized_caller(&request)?;
+    let name = request.get_ref().name.clone();
+
+    let control_request = match Operation::try_from(request.get_ref().operation) {
+      Ok(Operation::Start) => PipeControlRequest::Start { name },
+      Ok(Operation::Stop) => PipeControlRequest::Stop { name },
+      Ok(Operation::Restart) => PipeControlRequest::Restart { name },
+      Ok(Operation::Reload) => PipeControlRequest::Reload,
+      Ok(Operation::Unspecified) | Err(_) => return Err(Status::invalid_argument("Operation is not specified")),
+    };
+
+    let response = self.send_to_event_pump(control_request, caller).await?;
+
+    // A config that fails to load is reported in the response, as on the other channels.
+    match (response.ok, response.error_kind) {
+      (false, Some(ControlErrorKind::ProcessNotConfigured)) | (false, Some(ControlErrorKind::TimedOut)) => Err(get_error_status(&response).unwrap()),
+      _ => Ok(Response::new(ControlResponse { ok: response.ok, error: response.error.unwrap_or_default() })),
+    }
+  }
+
+  async fn logs(&self, request: Request<LogsRequest>) -> Result<Response<LogsResponse>, Status> {
+    let caller = self.get_authorized_caller(&request)?;
+    let name = request.into_inner().name;
+    let response = self.send_to_event_pump(PipeControlRequest::Logs { name }, caller).await?;
+
+    if let Some(error_status) = get_error_status(&response) {
+      return Err(error_status);
+    }
+
+    let logs = response.logs
+      .unwrap_or_default()
+      .into_iter()
+      .map(|x| InstanceLogs { name: x.name, instance_index: x.instance_index as u32, lines: x.lines })
+      .collect();
+
+    Ok(Response::new(LogsResponse { logs }))
+  }
+
+  /// Sends every event stream message to the client until it disconnects.
+  async fn stream_events(&self, request: Request<StreamEventsRequest>) -> Result<Response<Self::StreamEventsStream>, Status> {
+    self.get_authorized_caller(&request)?;
+
+    let (stream_sender, stream_receiver) = channel::<PipeStreamMessage>();
+    if self.sender.send(Event::StreamSubscribed(stream_sender)).is_err() {
+      return Err(Status::unavailable("Orchestrator is stopping"));
+    }
+
+    let (client_sender, client_receiver) = tokio::sync::mpsc::channel(EVENT_STREAM_BUFFER_SIZE);
+
+    std::thread::spawn(move || {
+      for message in stream_receiver {
+        if client_sender.blocking_send(Ok(StreamMessage::from(message))).is_err() {
+          trace!("gRPC API: Event stream closed");
+          break;
+        }
+      }
+    });
+
+    Ok(Response::new(ReceiverStream::new(client_receiver)))
+  }
+}
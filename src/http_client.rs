@@ -0,0 +1,16 @@
+use std::error::Error;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Creates an HTTP agent using the Windows TLS stack (SChannel), so certificates trusted by the
+/// machine are honoured.
+pub fn create_http_agent(timeout: Duration) -> Result<ureq::Agent, Box<dyn Error>> {
+  let tls_connector = native_tls::TlsConnector::new()?;
+
+  let agent = ureq::AgentBuilder::new()
+    .timeout(timeout)
+    .tls_connector(Arc::new(tls_connector))
+    .build();
+
+  Ok(agent)
+}
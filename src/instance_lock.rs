@@ -0,0 +1,76 @@
+use crate::errors::OrchestratorError;
+
+use std::error::Error;
+use std::ffi::OsStr;
+use std::os::windows::ffi::OsStrExt;
+use std::path::PathBuf;
+use std::ptr::null_mut;
+use winapi::shared::ntdef::HANDLE;
+use winapi::shared::winerror::{ERROR_ACCESS_DENIED, ERROR_ALREADY_EXISTS};
+use winapi::um::errhandlingapi::GetLastError;
+use winapi::um::handleapi::CloseHandle;
+use winapi::um::synchapi::CreateMutexW;
+
+/// Named mutex held by the orchestrator supervising a set of config locations, so a second one
+/// started on the same locations fails instead of starting every process twice. It is released when
+/// dropped, or when the orchestrator exits.
+pub struct InstanceLock {
+  mutex_handle: HANDLE,
+}
+
+impl InstanceLock {
+  pub fn acquire(config_locations: &[PathBuf]) -> Result<InstanceLock, Box<dyn Error>> {
+    let config_locations_description = config_locations
+      .iter()
+      .map(|x| x.canonicalize().unwrap_or_else(|_| x.clone()).to_string_lossy().to_lowercase())
+      .collect::<Vec<String>>()
+      .join(";");
+
+    // Mutex names can't hold backslashes beyond the namespace prefix.
+    let mutex_name = format!("process-orchestrator:{}", config_locations_description.replace('\\', "/"));
+
+    // The global namespace spans sessions, so a service and a console orchestrator see each other.
+    // Access is denied when the mutex exists but belongs to another account, e.g. the service
+    // running as LocalSystem, which is running all the same.
+    let mutex_handle = match create_mutex(&format!("Global\\{}", mutex_name)) {
+      Err(create_error) if create_error.raw_os_error() == Some(ERROR_ACCESS_DENIED as i32) => Ok(None),
+      create_result => create_result,
+    };
+
+    match mutex_handle {
+      Ok(Some(mutex_handle)) => Ok(InstanceLock { mutex_handle }),
+      Ok(None) => Err(Box::new(OrchestratorError::AlreadyRunning(config_locations_description))),
+      Err(create_error) => Err(Box::new(create_error)),
+    }
+  }
+}
+
+impl Drop for InstanceLock {
+  fn drop(&mut self) {
+    unsafe {
+      CloseHandle(self.mutex_handle);
+    }
+  }
+}
+
+/// Creates the named mutex, or returns `None` when another process already has it.
+fn create_mutex(mutex_name: &str) -> std::io::Result<Option<HANDLE>> {
+  let mutex_name_wide = OsStr::new(mutex_name)
+    .encode_wide()
+    .chain(std::iter::once(0))
+    .collect::<Vec<u16>>();
+
+  unsafe {
+    let mutex_handle = CreateMutexW(null_mut(), 0, mutex_name_wide.as_ptr());
+    if mutex_handle.is_null() {
+      return Err(std::io::Error::last_os_error());
+    }
+
+    if GetLastError() == ERROR_ALREADY_EXISTS {
+      CloseHandle(mutex_handle);
+      return Ok(None);
+    }
+
+    Ok(Some(mutex_handle))
+  }
+}
@@ -0,0 +1,57 @@
+use std::ffi::OsString;
+use std::os::windows::ffi::OsStringExt;
+use std::path::PathBuf;
+use std::ptr::null_mut;
+use winapi::shared::guiddef::GUID;
+use winapi::shared::winerror::S_OK;
+use winapi::um::combaseapi::CoTaskMemFree;
+use winapi::um::knownfolders::{
+  FOLDERID_LocalAppData, FOLDERID_Profile, FOLDERID_ProgramData, FOLDERID_ProgramFiles,
+  FOLDERID_ProgramFilesX86, FOLDERID_Public, FOLDERID_RoamingAppData, FOLDERID_Windows,
+};
+use winapi::um::shlobj::SHGetKnownFolderPath;
+
+/// Returns the folder behind a well known environment variable name (`ProgramData`,
+/// `LocalAppData`, `TEMP`, ...). Used when the variable is not set, as happens for some of them
+/// when running as a service.
+pub fn get_known_folder(variable_name: &str) -> Option<PathBuf> {
+  let folder_id = match variable_name.to_ascii_lowercase().as_str() {
+    "temp" | "tmp" => return Some(std::env::temp_dir()),
+    "programdata" | "allusersprofile" => &FOLDERID_ProgramData,
+    "programfiles" => &FOLDERID_ProgramFiles,
+    "programfiles(x86)" => &FOLDERID_ProgramFilesX86,
+    "appdata" => &FOLDERID_RoamingAppData,
+    "localappdata" => &FOLDERID_LocalAppData,
+    "userprofile" => &FOLDERID_Profile,
+    "public" => &FOLDERID_Public,
+    "systemroot" | "windir" => &FOLDERID_Windows,
+    _ => return None,
+  };
+
+  get_known_folder_path(folder_id)
+}
+
+/// Returns the home directory of the account the orchestrator runs as, which `~` refers to.
+pub fn get_home_directory() -> Option<PathBuf> {
+  std::env::var_os("USERPROFILE")
+    .map(PathBuf::from)
+    .or_else(|| get_known_folder_path(&FOLDERID_Profile))
+}
+
+fn get_known_folder_path(folder_id: &GUID) -> Option<PathBuf> {
+  unsafe {
+    let mut folder_path_wide = null_mut();
+
+    if SHGetKnownFolderPath(folder_id, 0, null_mut(), &mut folder_path_wide) != S_OK {
+      CoTaskMemFree(folder_path_wide as *mut _);
+      return None;
+    }
+
+    let length = (0..).take_while(|&i| *folder_path_wide.offset(i) != 0).count();
+    let folder_path = OsString::from_wide(std::slice::from_raw_parts(folder_path_wide, length));
+
+    CoTaskMemFree(folder_path_wide as *mut _);
+
+    Some(PathBuf::from(folder_path))
+  }
+}
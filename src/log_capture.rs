@@ -0,0 +1,872 @@
+use crate::event_log::{EventLog, EventLogLevel, PROCESS_OUTPUT_EVENT_ID};
+use crate::log_shipper::ProcessLogShipper;
+
+use serde::{Deserialize, Serialize};
+use chrono::{DateTime, Local, SecondsFormat};
+use std::error::Error;
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::collections::VecDeque;
+use std::cmp::Reverse;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::ptr::null_mut;
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant, SystemTime};
+use flate2::Compression;
+use flate2::write::GzEncoder;
+use glob::Pattern;
+use log::{error, trace};
+use winapi::shared::minwindef::{DWORD, TRUE};
+use winapi::um::fileapi::ReadFile;
+use winapi::um::handleapi::{CloseHandle, SetHandleInformation};
+use winapi::um::minwinbase::SECURITY_ATTRIBUTES;
+use winapi::um::namedpipeapi::CreatePipe;
+use winapi::um::stringapiset::MultiByteToWideChar;
+use winapi::um::winbase::HANDLE_FLAG_INHERIT;
+use winapi::um::winnls::{CP_OEMCP, IsDBCSLeadByteEx};
+use winapi::um::winnt::HANDLE;
+
+const LOG_CAPTURE_BUFFER_SIZE: usize = 64 * 1024;
+
+/// Length past which output without a line break is written as a line, so a process that never
+/// ends its lines, e.g. one drawing a progress bar, doesn't grow it without bound.
+const MAX_PARTIAL_LINE_LENGTH: usize = LOG_CAPTURE_BUFFER_SIZE;
+
+/// Rotated log files kept when no maximum is configured.
+pub const DEFAULT_LOG_MAX_FILES: usize = 5;
+
+/// Most lines of a process written to the event log in a minute, so a process failing in a loop
+/// doesn't flood it.
+const MAX_OUTPUT_EVENTS_PER_MINUTE: usize = 30;
+
+/// When a captured log file is rotated, which of the rotated files are kept, and whether they are
+/// gzipped.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct LogRotation {
+  pub max_size_bytes: Option<u64>,
+  pub period: Option<LogRotationPeriod>,
+  pub max_files: usize,
+  pub max_age: Option<Duration>,
+  pub compress: bool,
+}
+
+/// Period a log file covers before it is rotated to a file stamped with it.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum LogRotationPeriod {
+  Hourly,
+  Daily,
+}
+
+impl LogRotationPeriod {
+  /// Stamp of the period the time falls in, e.g. `2026-10-15` or `2026-10-15-14`.
+  fn get_stamp(&self, time: DateTime<Local>) -> String {
+    match self {
+      LogRotationPeriod::Hourly => time.format("%Y-%m-%d-%H").to_string(),
+      LogRotationPeriod::Daily => time.format("%Y-%m-%d").to_string(),
+    }
+  }
+}
+
+/// Encoding a process writes its output in, transcoded to UTF-8 when captured.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum OutputEncoding {
+  Utf8,
+  Utf16le,
+  /// The OEM code page of the machine, e.g. 437 or 850, which console programs write in.
+  Oem,
+}
+
+/// How the output captured from a process is written to its log file.
+#[derive(Debug, Default, Clone)]
+pub struct LogCaptureOptions {
+  /// Encoding of the output, written as is when not set.
+  pub output_encoding: Option<OutputEncoding>,
+  /// Prefix each line with the time it was captured and the process ID.
+  pub is_timestamped: bool,
+  /// Leave out the ANSI escape sequences, e.g. colors, from the log file.
+  pub is_ansi_stripped: bool,
+  /// Name the lines are prefixed with when also written to the orchestrator's console.
+  pub console_name: Option<String>,
+  /// Buffer also keeping the end of the output.
+  pub output_buffer: Option<OutputBuffer>,
+  /// Collector the lines are also forwarded to.
+  pub log_shipper: Option<ProcessLogShipper>,
+  /// Rules writing the lines that match them to the event log.
+  pub output_events: Option<OutputEvents>,
+  /// Set while the output is not to be written to the log file, e.g. while its volume is low on
+  /// space.
+  pub is_write_paused: Arc<AtomicBool>,
+}
+
+/// Writes the lines of output matching a rule to the event log, at the level of the first rule
+/// they match.
+#[derive(Debug, Clone)]
+pub struct OutputEvents {
+  pub event_log: EventLog,
+  pub rules: Vec<(Pattern, EventLogLevel)>,
+}
+
+/// The last bytes a process wrote, kept in memory across its restarts, e.g. to see why it crashed
+/// when it has no log file.
+#[derive(Debug, Clone)]
+pub struct OutputBuffer {
+  bytes: Arc<Mutex<VecDeque<u8>>>,
+  capacity: usize,
+}
+
+impl OutputBuffer {
+  pub fn new(capacity: usize) -> Self {
+    Self {
+      bytes: Arc::new(Mutex::new(VecDeque::with_capacity(capacity))),
+      capacity,
+    }
+  }
+
+  pub fn get_capacity(&self) -> usize {
+    self.capacity
+  }
+
+  fn push(&self, output: &[u8]) {
+    let mut bytes = self.bytes.lock().unwrap();
+    let output = &output[output.len().saturating_sub(self.capacity)..];
+
+    let overflow_length = (bytes.len() + output.len()).saturating_sub(self.capacity);
+    bytes.drain(..overflow_length);
+    bytes.extend(output);
+  }
+
+  /// Lines of the output kept, without the first one when only its end is still kept.
+  pub fn get_lines(&self) -> Vec<String> {
+    let bytes = self.bytes.lock().unwrap();
+    let is_trimmed = bytes.len() == self.capacity;
+    let output = bytes.iter().copied().collect::<Vec<u8>>();
+
+    String::from_utf8_lossy(&output)
+      .lines()
+      .skip(if is_trimmed { 1 } else { 0 })
+      .map(|x| x.to_string())
+      .collect()
+  }
+}
+
+/// Where the output captured from a process goes.
+struct OutputCapture {
+  process_id: String,
+  log_file: Option<RotatingLogFile>,
+  options: LogCaptureOptions,
+  output_decoder: Option<OutputDecoder>,
+  output_lines: OutputLines,
+  /// When the last lines were written to the event log, within the last minute.
+  output_event_times: VecDeque<Instant>,
+  ansi_stripper: AnsiStripper,
+}
+
+impl OutputCapture {
+  fn write(&mut self, output: &[u8]) {
+    let decoded_output;
+    let output = match self.output_decoder.as_mut() {
+      Some(output_decoder) => {
+        decoded_output = output_decoder.decode(output);
+        &decoded_output
+      }
+      None => output,
+    };
+
+    if let Some(output_buffer) = &self.options.output_buffer {
+      output_buffer.push(output);
+    }
+
+    // Without timestamps, the output goes to the log file as soon as it is read, even mid-line.
+    if !self.options.is_timestamped {
+      self.write_log_file(output);
+    }
+
+    let is_split_into_lines = self.options.is_timestamped
+      || self.options.console_name.is_some()
+      || self.options.log_shipper.is_some()
+      || self.options.output_events.is_some();
+
+    if !is_split_into_lines {
+      return;
+    }
+
+    for line in self.output_lines.push(output) {
+      self.write_line(&line);
+    }
+  }
+
+  /// Writes the last line, when the process exited without ending it.
+  fn finish(&mut self) {
+    if let Some(line) = self.output_lines.take_partial_line() {
+      self.write_line(&line);
+    }
+  }
+
+  fn write_line(&mut self, line: &[u8]) {
+    if self.options.is_timestamped {
+      let timestamped_line = get_timestamped_line(&self.process_id, line);
+      self.write_log_file(&timestamped_line);
+    }
+
+    if let Some(console_name) = &self.options.console_name {
+      write_console_line(console_name, line);
+    }
+
+    if let Some(log_shipper) = &self.options.log_shipper {
+      log_shipper.ship_line(&self.process_id, line);
+    }
+
+    if self.options.output_events.is_some() {
+      self.write_output_event(line);
+    }
+  }
+
+  fn write_output_event(&mut self, line: &[u8]) {
+    let output_events = self.options.output_events.as_ref().unwrap();
+    let line = String::from_utf8_lossy(line);
+    let line = line.trim_end_matches(&['\r', '\n'][..]);
+
+    let level = match output_events.rules.iter().find(|(pattern, _)| pattern.matches(line)) {
+      Some((_, level)) => *level,
+      None => return,
+    };
+
+    while self.output_event_times.front().map(|x| x.elapsed() > Duration::from_secs(60)).unwrap_or(false) {
+      self.output_event_times.pop_front();
+    }
+
+    if self.output_event_times.len() == MAX_OUTPUT_EVENTS_PER_MINUTE {
+      trace!("Process [{}]: Too many lines written to the event log, skipping: {}", &self.process_id, line);
+      return;
+    }
+
+    self.output_event_times.push_back(Instant::now());
+    output_events.event_log.report_level(level, PROCESS_OUTPUT_EVENT_ID, &format!("Process [{}] wrote: {}", &self.process_id, line));
+  }
+
+  fn write_log_file(&mut self, output: &[u8]) {
+    if self.options.is_write_paused.load(Ordering::Relaxed) {
+      return;
+    }
+
+    if let Some(log_file) = self.log_file.as_mut() {
+      let stripped_output;
+      let output = if self.options.is_ansi_stripped {
+        stripped_output = self.ansi_stripper.strip(output);
+        &stripped_output
+      } else {
+        output
+      };
+
+      if let Err(write_error) = log_file.append(output) {
+        error!("Process [{}]: Could not write to log file [{}]: {}", &self.process_id, log_file.file_path.display(), write_error);
+      }
+    }
+  }
+}
+
+/// Transcodes the output of a process to UTF-8, keeping the end of the output read so far when it
+/// stops in the middle of a character, until the rest of it is read. Invalid characters are
+/// replaced with `U+FFFD`.
+#[derive(Debug)]
+struct OutputDecoder {
+  output_encoding: OutputEncoding,
+  partial_character: Vec<u8>,
+}
+
+impl OutputDecoder {
+  fn new(output_encoding: OutputEncoding) -> Self {
+    Self {
+      output_encoding,
+      partial_character: Vec::new(),
+    }
+  }
+
+  fn decode(&mut self, output: &[u8]) -> Vec<u8> {
+    let mut bytes = std::mem::take(&mut self.partial_character);
+    bytes.extend_from_slice(output);
+
+    let decoded = match self.output_encoding {
+      OutputEncoding::Utf8 => self.decode_utf8(&bytes),
+      OutputEncoding::Utf16le => self.decode_utf16le(&bytes),
+      OutputEncoding::Oem => self.decode_oem(&bytes),
+    };
+
+    decoded.into_bytes()
+  }
+
+  fn decode_utf8(&mut self, bytes: &[u8]) -> String {
+    let mut decoded = String::new();
+    let mut remaining = bytes;
+
+    loop {
+      match std::str::from_utf8(remaining) {
+        Ok(valid) => {
+          decoded.push_str(valid);
+          break;
+        }
+        Err(utf8_error) => {
+          let (valid, invalid) = remaining.split_at(utf8_error.valid_up_to());
+          decoded.push_str(std::str::from_utf8(valid).unwrap());
+
+          match utf8_error.error_len() {
+            Some(invalid_length) => {
+              decoded.push(char::REPLACEMENT_CHARACTER);
+              remaining = &invalid[invalid_length..];
+            }
+            None => {
+              self.partial_character = invalid.to_vec();
+              break;
+            }
+          }
+        }
+      }
+    }
+
+    decoded
+  }
+
+  fn decode_utf16le(&mut self, bytes: &[u8]) -> String {
+    let mut units = bytes.chunks_exact(2).map(|x| u16::from_le_bytes([x[0], x[1]])).collect::<Vec<u16>>();
+    let mut partial_length = bytes.len() % 2;
+
+    // A high surrogate is completed by the next unit.
+    if units.last().map(|x| (0xD800..0xDC00).contains(x)).unwrap_or(false) {
+      units.pop();
+      partial_length += 2;
+    }
+
+    self.partial_character = bytes[bytes.len() - partial_length..].to_vec();
+
+    std::char::decode_utf16(units)
+      .map(|x| x.unwrap_or(char::REPLACEMENT_CHARACTER))
+      .filter(|x| *x != '\u{FEFF}')
+      .collect()
+  }
+
+  fn decode_oem(&mut self, bytes: &[u8]) -> String {
+    // Double-byte code pages, e.g. 932, have characters of two bytes, led by a lead byte.
+    let mut complete_length = 0;
+    while complete_length < bytes.len() {
+      let is_lead_byte = unsafe { IsDBCSLeadByteEx(CP_OEMCP, bytes[complete_length]) != 0 };
+      let character_length = if is_lead_byte { 2 } else { 1 };
+
+      if complete_length + character_length > bytes.len() {
+        break;
+      }
+
+      complete_length += character_length;
+    }
+
+    self.partial_character = bytes[complete_length..].to_vec();
+
+    if complete_length == 0 {
+      return String::new();
+    }
+
+    let bytes = &bytes[..complete_length];
+
+    unsafe {
+      let wide_length = MultiByteToWideChar(CP_OEMCP, 0, bytes.as_ptr() as _, bytes.len() as i32, null_mut(), 0);
+      let mut wide = vec![0u16; wide_length.max(0) as usize];
+      MultiByteToWideChar(CP_OEMCP, 0, bytes.as_ptr() as _, bytes.len() as i32, wide.as_mut_ptr(), wide_length);
+
+      String::from_utf16_lossy(&wide)
+    }
+  }
+}
+
+/// Splits the output of a process into lines, keeping the end of the output read so far until the
+/// rest of its line is read.
+#[derive(Debug, Default)]
+struct OutputLines {
+  partial_line: Vec<u8>,
+}
+
+impl OutputLines {
+  /// Lines completed by the output, each with its line break, and the output without one once it
+  /// is too long.
+  fn push(&mut self, output: &[u8]) -> Vec<Vec<u8>> {
+    let mut lines = Vec::new();
+
+    for output_line in output.split_inclusive(|x| *x == b'\n') {
+      self.partial_line.extend_from_slice(output_line);
+
+      if output_line.ends_with(b"\n") || self.partial_line.len() >= MAX_PARTIAL_LINE_LENGTH {
+        lines.push(std::mem::take(&mut self.partial_line));
+      }
+    }
+
+    lines
+  }
+
+  fn take_partial_line(&mut self) -> Option<Vec<u8>> {
+    if self.partial_line.is_empty() {
+      return None;
+    }
+
+    Some(std::mem::take(&mut self.partial_line))
+  }
+}
+
+/// The line prefixed with the current time and the process ID, e.g.
+/// `2026-10-16T09:12:03.511+02:00 [worker-1] Listening on 8080`, ending with a line break even
+/// when the process did not end it.
+fn get_timestamped_line(process_id: &str, line: &[u8]) -> Vec<u8> {
+  let mut timestamped_line = format!("{} [{}] ", Local::now().to_rfc3339_opts(SecondsFormat::Millis, false), process_id).into_bytes();
+  timestamped_line.extend_from_slice(line);
+
+  if !line.ends_with(b"\n") {
+    timestamped_line.extend_from_slice(b"\r\n");
+  }
+
+  timestamped_line
+}
+
+/// Where an `AnsiStripper` is in the output, which may end in the middle of an escape sequence.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+enum AnsiState {
+  #[default]
+  Text,
+  /// After `ESC`.
+  Escape,
+  /// In a control sequence, `ESC [` up to its final byte, e.g. `ESC [ 1 ; 31 m`.
+  ControlSequence,
+  /// In an operating system command, `ESC ]` up to `BEL` or `ESC \`, e.g. a window title.
+  OperatingSystemCommand,
+  /// After `ESC` in an operating system command.
+  OperatingSystemCommandEscape,
+}
+
+/// Removes ANSI escape sequences, such as colors and cursor movements, from output written in
+/// chunks.
+#[derive(Debug, Default)]
+struct AnsiStripper {
+  state: AnsiState,
+}
+
+impl AnsiStripper {
+  fn strip(&mut self, output: &[u8]) -> Vec<u8> {
+    let mut text = Vec::with_capacity(output.len());
+
+    for &byte in output {
+      self.state = match (self.state, byte) {
+        (AnsiState::Text, 0x1b) => AnsiState::Escape,
+        (AnsiState::Text, _) => {
+          text.push(byte);
+          AnsiState::Text
+        }
+        (AnsiState::Escape, b'[') => AnsiState::ControlSequence,
+        (AnsiState::Escape, b']') => AnsiState::OperatingSystemCommand,
+        // Intermediate bytes of a two character sequence, e.g. `ESC ( B`.
+        (AnsiState::Escape, 0x20..=0x2f) => AnsiState::Escape,
+        (AnsiState::Escape, _) => AnsiState::Text,
+        (AnsiState::ControlSequence, 0x20..=0x3f) => AnsiState::ControlSequence,
+        (AnsiState::ControlSequence, _) => AnsiState::Text,
+        (AnsiState::OperatingSystemCommand, 0x07) => AnsiState::Text,
+        (AnsiState::OperatingSystemCommand, 0x1b) => AnsiState::OperatingSystemCommandEscape,
+        (AnsiState::OperatingSystemCommand, _) => AnsiState::OperatingSystemCommand,
+        (AnsiState::OperatingSystemCommandEscape, b'\\') => AnsiState::Text,
+        (AnsiState::OperatingSystemCommandEscape, _) => AnsiState::OperatingSystemCommand,
+      };
+    }
+
+    text
+  }
+}
+
+/// Writes the line to the orchestrator's STDOUT prefixed with the process name, whole, so the
+/// lines of processes writing at the same time are not mixed up.
+fn write_console_line(console_name: &str, line: &[u8]) {
+  let stdout = std::io::stdout();
+  let mut stdout = stdout.lock();
+
+  let _ = write!(stdout, "[{}] ", console_name);
+  let _ = stdout.write_all(line);
+
+  if !line.ends_with(b"\n") {
+    let _ = stdout.write_all(b"\r\n");
+  }
+
+  let _ = stdout.flush();
+}
+
+/// Pipe a process writes its STDOUT / STDERR to, for the orchestrator to write it to the log file,
+/// so the file can be rotated while the process runs, and named after the process' PID, or to
+/// its own console.
+pub struct LogPipe {
+  read_handle: HANDLE,
+  /// Inheritable end to hand to the process.
+  pub write_handle: HANDLE,
+}
+
+impl LogPipe {
+  pub fn create() -> Result<Self, Box<dyn Error>> {
+    let mut read_handle: HANDLE = null_mut();
+    let mut write_handle: HANDLE = null_mut();
+
+    unsafe {
+      let mut security_attributes = std::mem::zeroed::<SECURITY_ATTRIBUTES>();
+      security_attributes.nLength = std::mem::size_of::<SECURITY_ATTRIBUTES>() as u32;
+      security_attributes.bInheritHandle = TRUE;
+
+      if CreatePipe(&mut read_handle, &mut write_handle, &mut security_attributes, 0) == 0 {
+        return Err(Box::new(std::io::Error::last_os_error()));
+      }
+
+      // Only the process gets the write end.
+      SetHandleInformation(read_handle, HANDLE_FLAG_INHERIT, 0);
+    }
+
+    Ok(Self { read_handle, write_handle })
+  }
+
+  /// Writes what the process writes to the log file, and to the console when mirrored, once the
+  /// process has been created with its own copy of the write end. Closing this one right away keeps
+  /// the processes started after it from inheriting it, so the capture ends when the process, and
+  /// any child holding the pipe, has exited.
+  pub fn start_capture(self, process_id: &str, log_file: Option<RotatingLogFile>, options: LogCaptureOptions) {
+    unsafe { CloseHandle(self.write_handle); }
+
+    let mut output_capture = OutputCapture {
+      process_id: process_id.to_string(),
+      log_file,
+      output_decoder: options.output_encoding.map(OutputDecoder::new),
+      options,
+      output_lines: OutputLines::default(),
+      output_event_times: VecDeque::new(),
+      ansi_stripper: AnsiStripper::default(),
+    };
+    let read_handle_value = self.read_handle as usize;
+
+    std::thread::spawn(move || {
+      let read_handle = read_handle_value as HANDLE;
+      let mut buffer = vec![0u8; LOG_CAPTURE_BUFFER_SIZE];
+
+      loop {
+        let mut read_length: DWORD = 0;
+        let is_read = unsafe {
+          ReadFile(read_handle, buffer.as_mut_ptr() as _, buffer.len() as DWORD, &mut read_length, null_mut()) != 0
+        };
+
+        if !is_read || read_length == 0 {
+          break;
+        }
+
+        output_capture.write(&buffer[..read_length as usize]);
+      }
+
+      output_capture.finish();
+
+      unsafe { CloseHandle(read_handle); }
+      trace!("Process [{}]: Log capture ended", &output_capture.process_id);
+    });
+  }
+
+  /// Closes the pipe when the process could not be created.
+  pub fn close(self) {
+    unsafe {
+      CloseHandle(self.write_handle);
+      CloseHandle(self.read_handle);
+    }
+  }
+}
+
+/// A log file moved aside once it would grow past its maximum size, or at the first write of a new
+/// period.
+///
+/// Rotated by size only, it moves to `<file>.1`, shifting the previously rotated files to
+/// `<file>.2` and on. Rotated by period, it moves to a file stamped with the period it covers,
+/// e.g. `worker.2026-10-15.log`, numbered (`worker.2026-10-15.1.log`) when rotated by size within
+/// the period. Either way, rotated files are then gzipped when configured, and only the newest,
+/// and those younger than the maximum age, are kept.
+pub struct RotatingLogFile {
+  file_path: PathBuf,
+  file: File,
+  size: u64,
+  /// Stamp of the period the current file covers.
+  period_stamp: Option<String>,
+  log_rotation: LogRotation,
+  /// Compression and clean-up of the last rotated file, which must be done before the files are
+  /// moved again.
+  maintenance: Option<JoinHandle<()>>,
+}
+
+impl RotatingLogFile {
+  pub fn open(file_path: &Path, log_rotation: LogRotation, is_truncated: bool) -> std::io::Result<Self> {
+    create_parent_directory(file_path)?;
+
+    if is_truncated {
+      File::create(file_path)?;
+    }
+
+    let file = OpenOptions::new().create(true).append(true).open(file_path)?;
+    let metadata = file.metadata()?;
+
+    // A file left from before the orchestrator started covers the period it was last written in.
+    let period_stamp = log_rotation.period.map(|period| {
+      let modified = metadata.modified().map(DateTime::<Local>::from).unwrap_or_else(|_| Local::now());
+      period.get_stamp(modified)
+    });
+
+    Ok(Self {
+      file_path: file_path.to_path_buf(),
+      file,
+      size: metadata.len(),
+      period_stamp,
+      log_rotation,
+      maintenance: None,
+    })
+  }
+
+  fn append(&mut self, data: &[u8]) -> std::io::Result<()> {
+    let current_period_stamp = self.log_rotation.period.map(|x| x.get_stamp(Local::now()));
+    let is_rotation_due = (self.size > 0 && current_period_stamp != self.period_stamp) || self.is_size_rotation_due(data.len());
+
+    // While the last rotated file is still being compressed, the rotation waits for a later
+    // write rather than the writer waiting for it. That maintenance logs through the orchestrator's
+    // logger, which may be the one writing here.
+    if !is_rotation_due || !self.is_maintenance_running() {
+      if is_rotation_due {
+        self.rotate()?;
+      }
+
+      self.period_stamp = current_period_stamp;
+    }
+
+    self.file.write_all(data)?;
+    self.size += data.len() as u64;
+
+    Ok(())
+  }
+
+  fn is_size_rotation_due(&self, write_length: usize) -> bool {
+    self.size > 0 && self.log_rotation.max_size_bytes.map(|x| self.size + write_length as u64 > x).unwrap_or(false)
+  }
+
+  fn is_maintenance_running(&self) -> bool {
+    self.maintenance.as_ref().map(|x| !x.is_finished()).unwrap_or(false)
+  }
+
+  fn rotate(&mut self) -> std::io::Result<()> {
+    if let Some(maintenance) = self.maintenance.take() {
+      let _ = maintenance.join();
+    }
+
+    if self.period_stamp.is_none() && self.log_rotation.max_files == 0 {
+      self.file = OpenOptions::new().write(true).truncate(true).open(&self.file_path)?;
+      self.size = 0;
+      return Ok(());
+    }
+
+    let rotated_file_path = match self.period_stamp.clone() {
+      Some(period_stamp) => self.move_to_stamped_file(&period_stamp)?,
+      None => self.move_to_numbered_file()?,
+    };
+
+    self.file = OpenOptions::new().create(true).append(true).open(&self.file_path)?;
+    self.size = 0;
+
+    // Compressing a large file takes a while, during which the process must not be kept from
+    // writing.
+    let file_path = self.file_path.clone();
+    let log_rotation = self.log_rotation.clone();
+
+    self.maintenance = Some(std::thread::spawn(move || {
+      if log_rotation.compress {
+        if let Err(compress_error) = compress_file(&rotated_file_path) {
+          error!("LogCapture: Could not compress [{}]: {}", rotated_file_path.display(), compress_error);
+        }
+      }
+
+      remove_expired_files(&file_path, &log_rotation);
+    }));
+
+    Ok(())
+  }
+
+  fn move_to_stamped_file(&self, period_stamp: &str) -> std::io::Result<PathBuf> {
+    let mut stamped_file_path = get_stamped_file_path(&self.file_path, period_stamp);
+    let mut index = 1;
+    while stamped_file_path.exists() || get_compressed_file_path(&stamped_file_path).exists() {
+      stamped_file_path = get_stamped_file_path(&self.file_path, &format!("{}.{}", period_stamp, index));
+      index += 1;
+    }
+
+    std::fs::rename(&self.file_path, &stamped_file_path)?;
+
+    Ok(stamped_file_path)
+  }
+
+  fn move_to_numbered_file(&self) -> std::io::Result<PathBuf> {
+    let max_files = self.log_rotation.max_files;
+
+    for oldest_file_path in [get_numbered_file_path(&self.file_path, max_files), get_compressed_file_path(&get_numbered_file_path(&self.file_path, max_files))] {
+      if oldest_file_path.exists() {
+        std::fs::remove_file(&oldest_file_path)?;
+      }
+    }
+
+    for index in (1..max_files).rev() {
+      let numbered_file_path = get_numbered_file_path(&self.file_path, index);
+      let next_numbered_file_path = get_numbered_file_path(&self.file_path, index + 1);
+
+      if numbered_file_path.exists() {
+        std::fs::rename(&numbered_file_path, &next_numbered_file_path)?;
+      }
+
+      if get_compressed_file_path(&numbered_file_path).exists() {
+        std::fs::rename(get_compressed_file_path(&numbered_file_path), get_compressed_file_path(&next_numbered_file_path))?;
+      }
+    }
+
+    let numbered_file_path = get_numbered_file_path(&self.file_path, 1);
+    std::fs::rename(&self.file_path, &numbered_file_path)?;
+
+    Ok(numbered_file_path)
+  }
+}
+
+/// Lets the orchestrator write its own log to a rotated file.
+impl Write for RotatingLogFile {
+  fn write(&mut self, data: &[u8]) -> std::io::Result<usize> {
+    self.append(data)?;
+    Ok(data.len())
+  }
+
+  fn flush(&mut self) -> std::io::Result<()> {
+    self.file.flush()
+  }
+}
+
+/// Creates the directory of a log file, which placeholders in its path can make new on each start.
+fn create_parent_directory(file_path: &Path) -> std::io::Result<()> {
+  match file_path.parent().filter(|x| !x.as_os_str().is_empty()) {
+    Some(directory) => std::fs::create_dir_all(directory),
+    None => Ok(()),
+  }
+}
+
+/// Replaces the file with a gzipped `<file>.gz`.
+fn compress_file(file_path: &Path) -> std::io::Result<()> {
+  let compressed_file_path = get_compressed_file_path(file_path);
+
+  let compress_result = (|| {
+    let mut encoder = GzEncoder::new(File::create(&compressed_file_path)?, Compression::default());
+    std::io::copy(&mut File::open(file_path)?, &mut encoder)?;
+    encoder.finish()?;
+    Ok(())
+  })();
+
+  match compress_result {
+    Ok(()) => std::fs::remove_file(file_path),
+    Err(compress_error) => {
+      let _ = std::fs::remove_file(&compressed_file_path);
+      Err(compress_error)
+    }
+  }
+}
+
+/// Removes every rotated file of the log file, returning how many were removed.
+pub fn remove_rotated_files(file_path: &Path) -> std::io::Result<usize> {
+  let rotated_files = get_rotated_files(file_path)?;
+  let mut removed_count = 0;
+
+  for (rotated_file_path, _) in &rotated_files {
+    match std::fs::remove_file(rotated_file_path) {
+      Ok(()) => removed_count += 1,
+      Err(remove_error) => trace!("LogCapture: Could not remove [{}]: {}", rotated_file_path.display(), remove_error),
+    }
+  }
+
+  Ok(removed_count)
+}
+
+/// Removes the rotated files beyond the number kept, oldest first, and those older than the
+/// maximum age.
+fn remove_expired_files(file_path: &Path, log_rotation: &LogRotation) {
+  let mut rotated_files = match get_rotated_files(file_path) {
+    Ok(rotated_files) => rotated_files,
+    Err(list_error) => {
+      error!("LogCapture: Could not list the rotated files of [{}]: {}", file_path.display(), list_error);
+      return;
+    }
+  };
+
+  rotated_files.sort_by_key(|x| Reverse(x.1));
+
+  for (index, (rotated_file_path, modified)) in rotated_files.iter().enumerate() {
+    let is_expired = log_rotation.max_age
+      .map(|max_age| modified.elapsed().map(|x| x > max_age).unwrap_or(false))
+      .unwrap_or(false);
+
+    if index < log_rotation.max_files && !is_expired {
+      continue;
+    }
+
+    if let Err(remove_error) = std::fs::remove_file(rotated_file_path) {
+      trace!("LogCapture: Could not remove [{}]: {}", rotated_file_path.display(), remove_error);
+    }
+  }
+}
+
+/// `worker.log` stamped with `2026-10-15` is `worker.2026-10-15.log`.
+fn get_stamped_file_path(file_path: &Path, stamp: &str) -> PathBuf {
+  let file_stem = file_path.file_stem().unwrap_or_default().to_string_lossy();
+
+  match file_path.extension() {
+    Some(extension) => file_path.with_file_name(format!("{}.{}.{}", file_stem, stamp, extension.to_string_lossy())),
+    None => file_path.with_file_name(format!("{}.{}", file_stem, stamp)),
+  }
+}
+
+fn get_numbered_file_path(file_path: &Path, index: usize) -> PathBuf {
+  let mut numbered_file_name = file_path.file_name().unwrap_or_default().to_os_string();
+  numbered_file_name.push(format!(".{}", index));
+  file_path.with_file_name(numbered_file_name)
+}
+
+fn get_compressed_file_path(file_path: &Path) -> PathBuf {
+  let mut compressed_file_name = file_path.file_name().unwrap_or_default().to_os_string();
+  compressed_file_name.push(".gz");
+  file_path.with_file_name(compressed_file_name)
+}
+
+/// The rotated files of a log file, numbered (`worker.log.3`) or stamped with a period
+/// (`worker.2026-10-15.log`, `worker.2026-10-15-14.1.log`), gzipped or not, with when they were
+/// last modified.
+fn get_rotated_files(file_path: &Path) -> std::io::Result<Vec<(PathBuf, SystemTime)>> {
+  let file_name = file_path.file_name().unwrap_or_default().to_string_lossy().to_string();
+  let file_stem = file_path.file_stem().unwrap_or_default().to_string_lossy().to_string();
+  let extension = file_path.extension().map(|x| format!(".{}", x.to_string_lossy())).unwrap_or_default();
+  let directory = file_path.parent().filter(|x| !x.as_os_str().is_empty()).unwrap_or_else(|| Path::new("."));
+
+  let mut rotated_files = Vec::<(PathBuf, SystemTime)>::new();
+
+  for entry in std::fs::read_dir(directory)? {
+    let entry = entry?;
+    let entry_file_name = entry.file_name().to_string_lossy().to_string();
+    let entry_file_name = entry_file_name.strip_suffix(".gz").unwrap_or(&entry_file_name);
+
+    let is_numbered = entry_file_name
+      .strip_prefix(&format!("{}.", file_name))
+      .map(|x| !x.is_empty() && x.chars().all(|c| c.is_ascii_digit()))
+      .unwrap_or(false);
+
+    let is_stamped = entry_file_name
+      .strip_prefix(&format!("{}.", file_stem))
+      .and_then(|x| x.strip_suffix(&extension))
+      .and_then(|x| x.get(..10))
+      .is_some_and(|x| x.chars().all(|c| c.is_ascii_digit() || c == '-'));
+
+    if is_numbered || is_stamped {
+      rotated_files.push((entry.path(), entry.metadata()?.modified()?));
+    }
+  }
+
+  Ok(rotated_files)
+}
@@ -0,0 +1,125 @@
+use crate::orchestrator_config::LogRetentionConfig;
+use crate::stateful_process::StatefulProcessConfig;
+
+use std::collections::HashSet;
+use std::cmp::Reverse;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant, SystemTime};
+use log::{info, trace};
+
+const DEFAULT_INTERVAL_MINS: f64 = 60f64;
+
+/// Removes the log files of each process older than `max_age_days`, and the oldest ones once they
+/// add up to more than `max_total_size_mbs`, every `interval_mins`. The files a process is
+/// writing to are never removed.
+#[derive(Debug)]
+pub struct LogRetention {
+  config: LogRetentionConfig,
+  cleaned_at: Option<Instant>,
+}
+
+impl LogRetention {
+  pub fn new(config: LogRetentionConfig) -> Self {
+    Self {
+      config,
+      cleaned_at: None,
+    }
+  }
+
+  pub fn is_clean_due(&self) -> bool {
+    let interval = Duration::from_secs_f64(self.config.interval_mins.unwrap_or(DEFAULT_INTERVAL_MINS) * 60f64);
+    self.cleaned_at.map(|x| x.elapsed() >= interval).unwrap_or(true)
+  }
+
+  pub fn clean(&mut self, configs: &[StatefulProcessConfig], open_log_files: &HashSet<PathBuf>) {
+    self.cleaned_at = Some(Instant::now());
+
+    for config in configs {
+      let log_file = match &config.log_file {
+        Some(log_file) => log_file,
+        None => continue,
+      };
+
+      let mut log_files = get_process_log_files(log_file)
+        .into_iter()
+        .filter(|(path, _, _)| !open_log_files.contains(path))
+        .collect::<Vec<(PathBuf, SystemTime, u64)>>();
+
+      // Newest first, so the budget is used by the most recent files.
+      log_files.sort_by_key(|x| Reverse(x.1));
+
+      let max_age = self.config.max_age_days.map(|x| Duration::from_secs_f64(x * 86400f64));
+      let max_total_size_bytes = self.config.max_total_size_mbs.map(|x| (x * 1024f64 * 1024f64) as u64);
+      let mut total_size_bytes = 0u64;
+
+      for (path, modified, size) in log_files {
+        total_size_bytes += size;
+
+        let is_expired = max_age.map(|max_age| modified.elapsed().map(|x| x > max_age).unwrap_or(false)).unwrap_or(false);
+        let is_over_budget = max_total_size_bytes.map(|x| total_size_bytes > x).unwrap_or(false);
+
+        if !is_expired && !is_over_budget {
+          continue;
+        }
+
+        match std::fs::remove_file(&path) {
+          Ok(()) => info!("Config [{}]: Removed log file [{}]", &config.name, path.display()),
+          Err(remove_error) => trace!("LogRetention: Could not remove [{}]: {}", path.display(), remove_error),
+        }
+      }
+    }
+  }
+}
+
+/// The files a process configured with the log file may have written, with when they were last
+/// modified and their size: those of every instance, start and PID, with `{...}` placeholders
+/// matching anything, and their rotated files, named after the log file followed by a dot, e.g.
+/// `worker.log.1`, `worker.2026-10-15.log.gz` or `worker.20261016-091203.log`.
+fn get_process_log_files(log_file: &str) -> Vec<(PathBuf, SystemTime, u64)> {
+  let log_file_pattern = get_placeholder_pattern(log_file);
+  let log_file_path = Path::new(&log_file_pattern);
+
+  let rotated_file_pattern = match log_file_path.file_stem() {
+    Some(file_stem) => log_file_path.with_file_name(format!("{}.*", file_stem.to_string_lossy())),
+    None => return Vec::new(),
+  };
+
+  let mut paths = HashSet::<PathBuf>::new();
+
+  for pattern in &[log_file_pattern.clone(), rotated_file_pattern.to_string_lossy().to_string()] {
+    match glob::glob(pattern) {
+      Ok(entries) => paths.extend(entries.filter_map(|x| x.ok())),
+      Err(pattern_error) => trace!("LogRetention: Invalid pattern [{}]: {}", pattern, pattern_error),
+    }
+  }
+
+  paths
+    .into_iter()
+    .filter_map(|path| {
+      let metadata = std::fs::metadata(&path).ok().filter(|x| x.is_file())?;
+      Some((path, metadata.modified().ok()?, metadata.len()))
+    })
+    .collect()
+}
+
+/// The log file with each `{...}` placeholder replaced by `*`, and the other glob characters
+/// escaped.
+fn get_placeholder_pattern(log_file: &str) -> String {
+  let mut pattern = String::new();
+  let mut is_in_placeholder = false;
+
+  for character in log_file.chars() {
+    match character {
+      '{' => {
+        is_in_placeholder = true;
+        pattern.push('*');
+      }
+      '}' if is_in_placeholder => is_in_placeholder = false,
+      _ if is_in_placeholder => {}
+      '*' | '?' | '[' | ']' => pattern.push_str(&glob::Pattern::escape(&character.to_string())),
+      _ => pattern.push(character),
+    }
+  }
+
+  pattern
+}
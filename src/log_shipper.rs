@@ -0,0 +1,216 @@
+use crate::http_client::create_http_agent;
+use crate::orchestrator_config::{LogShippingConfig, LogShippingFormat};
+
+use chrono::{DateTime, Utc};
+use serde_json::{json, Value};
+use std::error::Error;
+use std::sync::mpsc::{Receiver, RecvTimeoutError, SyncSender, TrySendError, sync_channel};
+use std::time::{Duration, Instant};
+use log::{trace, warn};
+
+const DEFAULT_BATCH_SIZE: usize = 500;
+const DEFAULT_BATCH_INTERVAL_SECS: f64 = 5f64;
+const DEFAULT_MAX_RETRIES: u32 = 3;
+
+/// Records waiting to be shipped, beyond which new ones are dropped, so a collector that is down
+/// never holds up the processes writing their output.
+const MAX_QUEUED_RECORDS: usize = 10_000;
+
+/// Source of the records of the orchestrator's own events.
+const ORCHESTRATOR_SOURCE: &str = "orchestrator";
+
+/// GELF level of the lines of output and of the events, informational.
+const GELF_INFORMATIONAL_LEVEL: u8 = 6;
+
+/// A line of process output, or an orchestrator event, to be shipped.
+#[derive(Debug, Clone)]
+pub struct LogRecord {
+  pub time: DateTime<Utc>,
+  /// Name of the process, or `orchestrator`.
+  pub source: String,
+  pub instance_index: Option<usize>,
+  pub process_id: Option<String>,
+  pub message: String,
+}
+
+/// Forwards process output and orchestrator events to a log collector over HTTP, in batches sent
+/// from a background thread, retried with a growing delay when the collector can't be reached.
+#[derive(Debug, Clone)]
+pub struct LogShipper {
+  sender: SyncSender<LogRecord>,
+}
+
+/// Ships the output of one process instance.
+#[derive(Debug, Clone)]
+pub struct ProcessLogShipper {
+  log_shipper: LogShipper,
+  name: String,
+  instance_index: usize,
+}
+
+impl LogShipper {
+  pub fn new(config: &LogShippingConfig) -> Result<Self, Box<dyn Error>> {
+    let agent = create_http_agent(Duration::from_secs(10))?;
+    let config = config.clone();
+
+    let (sender, receiver) = sync_channel::<LogRecord>(MAX_QUEUED_RECORDS);
+
+    std::thread::spawn(move || ship_batches(&agent, &config, receiver));
+
+    Ok(Self { sender })
+  }
+
+  pub fn for_process(&self, name: &str, instance_index: usize) -> ProcessLogShipper {
+    ProcessLogShipper {
+      log_shipper: self.clone(),
+      name: name.to_string(),
+      instance_index,
+    }
+  }
+
+  pub fn ship_event(&self, time: DateTime<Utc>, event: &str) {
+    self.ship(LogRecord {
+      time,
+      source: ORCHESTRATOR_SOURCE.to_string(),
+      instance_index: None,
+      process_id: None,
+      message: event.to_string(),
+    });
+  }
+
+  fn ship(&self, record: LogRecord) {
+    match self.sender.try_send(record) {
+      Ok(()) => {}
+      Err(TrySendError::Full(_)) => trace!("LogShipper: Queue is full, dropping record"),
+      Err(TrySendError::Disconnected(_)) => trace!("LogShipper: Shipping thread has stopped"),
+    }
+  }
+}
+
+impl ProcessLogShipper {
+  /// Ships a line of output, without its line break.
+  pub fn ship_line(&self, process_id: &str, line: &[u8]) {
+    let line = String::from_utf8_lossy(line);
+
+    self.log_shipper.ship(LogRecord {
+      time: Utc::now(),
+      source: self.name.clone(),
+      instance_index: Some(self.instance_index),
+      process_id: Some(process_id.to_string()),
+      message: line.trim_end_matches(&['\r', '\n'][..]).to_string(),
+    });
+  }
+}
+
+/// Sends the records once `batch_size` of them are waiting, or `batch_interval_secs` after the
+/// first one of the batch, until every shipper is dropped.
+fn ship_batches(agent: &ureq::Agent, config: &LogShippingConfig, receiver: Receiver<LogRecord>) {
+  let batch_size = config.batch_size.unwrap_or(DEFAULT_BATCH_SIZE).max(1);
+  let batch_interval = Duration::from_secs_f64(config.batch_interval_secs.unwrap_or(DEFAULT_BATCH_INTERVAL_SECS));
+  let host_name = std::env::var("COMPUTERNAME").unwrap_or_default();
+
+  let mut batch = Vec::<LogRecord>::new();
+  let mut batch_deadline = None::<Instant>;
+
+  loop {
+    let received = match batch_deadline {
+      Some(deadline) => receiver.recv_timeout(deadline.saturating_duration_since(Instant::now())),
+      None => receiver.recv().map_err(|_| RecvTimeoutError::Disconnected),
+    };
+
+    let is_disconnected = match received {
+      Ok(record) => {
+        batch.push(record);
+        batch_deadline.get_or_insert_with(|| Instant::now() + batch_interval);
+
+        if batch.len() < batch_size {
+          continue;
+        }
+
+        false
+      }
+      Err(RecvTimeoutError::Timeout) => false,
+      Err(RecvTimeoutError::Disconnected) => true,
+    };
+
+    if !batch.is_empty() {
+      send_batch(agent, config, &host_name, &batch);
+      batch.clear();
+    }
+
+    batch_deadline = None;
+
+    if is_disconnected {
+      break;
+    }
+  }
+}
+
+fn send_batch(agent: &ureq::Agent, config: &LogShippingConfig, host_name: &str, batch: &[LogRecord]) {
+  let format = config.format.unwrap_or(LogShippingFormat::Json);
+  let max_retries = config.max_retries.unwrap_or(DEFAULT_MAX_RETRIES);
+
+  let (content_type, body) = match format {
+    LogShippingFormat::Json => (
+      "application/json",
+      Value::Array(batch.iter().map(|x| get_json_record(x, host_name)).collect()).to_string(),
+    ),
+    LogShippingFormat::Gelf => (
+      "application/x-ndjson",
+      batch.iter().map(|x| get_gelf_message(x, host_name).to_string()).collect::<Vec<String>>().join("\n"),
+    ),
+  };
+
+  for attempt in 0..=max_retries {
+    let mut request = agent.post(&config.endpoint).set("Content-Type", content_type);
+
+    for (header_name, header_value) in config.headers.iter().flatten() {
+      request = request.set(header_name, header_value);
+    }
+
+    match request.send_string(&body) {
+      Ok(_) => return,
+      Err(ship_error) if attempt < max_retries => {
+        trace!("LogShipper [{}]: Attempt {} failed: {}", &config.endpoint, attempt + 1, ship_error);
+        std::thread::sleep(Duration::from_secs(1 << attempt.min(6)));
+      }
+      Err(ship_error) => {
+        warn!("LogShipper [{}]: Dropping {} records: {}", &config.endpoint, batch.len(), ship_error);
+      }
+    }
+  }
+}
+
+fn get_json_record(record: &LogRecord, host_name: &str) -> Value {
+  json!({
+    "time": record.time.to_rfc3339(),
+    "host": host_name,
+    "source": &record.source,
+    "instance_index": record.instance_index,
+    "process_id": &record.process_id,
+    "message": &record.message,
+  })
+}
+
+/// The record as a GELF message, leaving out the additional fields it has no value for, which
+/// Graylog does not accept as `null`.
+fn get_gelf_message(record: &LogRecord, host_name: &str) -> Value {
+  let mut message = json!({
+    "version": "1.1",
+    "host": host_name,
+    "short_message": &record.message,
+    "timestamp": record.time.timestamp_millis() as f64 / 1000f64,
+    "level": GELF_INFORMATIONAL_LEVEL,
+    "_source": &record.source,
+  });
+
+  if let Some(instance_index) = record.instance_index {
+    message["_instance_index"] = json!(instance_index);
+  }
+
+  if let Some(process_id) = &record.process_id {
+    message["_process_id"] = json!(process_id);
+  }
+
+  message
+}
@@ -3,69 +3,139 @@ mod windows_service_host;
 mod stateful_process;
 mod event_pump;
 mod config;
+mod cli_options;
+mod commands;
+mod secrets;
+mod state_store;
+mod remote_config;
+mod http_client;
+mod known_folders;
+mod orchestrator_config;
+mod instance_lock;
+mod event_log;
+mod etw_provider;
+mod statsd_sink;
+mod otlp_exporter;
+mod performance_counters;
+mod control_server;
+mod management_api;
+#[cfg(feature = "grpc")]
+mod grpc_api;
+mod audit_log;
+mod top;
+mod log_capture;
+mod log_shipper;
+mod disk_guard;
+mod log_retention;
+mod process_token;
+mod process_attributes;
+mod app_container;
+mod process_job;
+mod shell;
+mod file_watcher;
 
-use crate::errors::OrchestratorError;
+use crate::cli_options::CliOptions;
+use crate::errors::{OrchestratorError, get_exit_code};
+use crate::instance_lock::InstanceLock;
+use crate::log_capture::RotatingLogFile;
+use crate::orchestrator_config::{load_orchestrator_config, OrchestratorLogConfig, ORCHESTRATOR_CONFIG_FILE_NAME};
 use crate::windows_service_host::{start_windows_service};
 
-use log::LevelFilter;
+use log::{error, LevelFilter};
 use structopt::StructOpt;
-use simplelog::{CombinedLogger, TermLogger, Config, TerminalMode, ColorChoice, WriteLogger};
-use std::fs::File;
+use simplelog::{CombinedLogger, TermLogger, Config, TerminalMode, ColorChoice, WriteLogger, SharedLogger};
+use std::path::Path;
 
 #[cfg(windows)]
 fn main() -> windows_service::Result<()> {
-  let cli_options = CliOptions::from_args();
+  let cli_options = CliOptions {
+    working_directory: std::env::current_dir().ok(),
+    ..CliOptions::from_args()
+  };
 
   set_current_directory_as_executable_directory();
-  set_executable_logging_file(cli_options.verbose);
 
-  let start_result = start_windows_service();
+  if let Some(command) = &cli_options.command {
+    if let Err(command_error) = commands::run_command(&cli_options, command) {
+      eprintln!("{}", command_error);
+      std::process::exit(get_exit_code(command_error.as_ref()));
+    }
+
+    return Ok(());
+  }
+
+  // The orchestrator config says where to log, so it is loaded before the logger is set up, and
+  // failing to load it is logged after.
+  let orchestrator_config = load_orchestrator_config();
+  let log_config = orchestrator_config.as_ref().ok().and_then(|x| x.log.clone()).unwrap_or_default();
+  set_executable_logging_file(cli_options.service_name.as_deref(), cli_options.verbose, &log_config);
+
+  let orchestrator_config = match orchestrator_config {
+    Ok(orchestrator_config) => orchestrator_config,
+    Err(load_orchestrator_config_error) => {
+      error!("Could not load [{}]: {}", ORCHESTRATOR_CONFIG_FILE_NAME, load_orchestrator_config_error);
+      std::process::exit(1);
+    }
+  };
+
+  if cli_options.console {
+    run_console(cli_options);
+    return Ok(());
+  }
+
+  let start_result = start_windows_service(&cli_options, &orchestrator_config);
 
   if let Err(OrchestratorError::ServiceControllerNotPresent()) = start_result {
-    let mut event_pump = event_pump::EventPump::new();
-    event_pump.run();
+    run_console(CliOptions { console: true, ..cli_options });
   }
 
   Ok(())
 }
 
+#[cfg(windows)]
+fn run_console(cli_options: CliOptions) {
+  let _instance_lock = match cli_options.get_config_locations().map_err(|x| x.into()).and_then(|x| InstanceLock::acquire(&x)) {
+    Ok(instance_lock) => instance_lock,
+    Err(instance_lock_error) => {
+      error!("{}", instance_lock_error);
+      std::process::exit(1);
+    }
+  };
+
+  let mut event_pump = event_pump::EventPump::new(cli_options);
+  event_pump.run();
+}
+
 #[cfg(not(windows))]
 fn main() {
   panic!("Only works on windows")
 }
 
-#[derive(StructOpt)]
-#[structopt(
-  name = "process-orchestrator",
-  about = "Keeps processes up and running using desired-state-configuration")]
-struct CliOptions {
-  // #[structopt(short = "c", long = "config-directory")]
-  // pub config_directory: Option<PathBuf>,
-
-  #[structopt(long = "verbose")]
-  pub verbose: bool,
-}
-
 fn set_current_directory_as_executable_directory() {
   let mut path = std::env::current_exe().unwrap();
   path.pop();
   std::env::set_current_dir(path).unwrap();
 }
 
-fn set_executable_logging_file(verbose: bool) {
+fn set_executable_logging_file(service_name: Option<&str>, verbose: bool, log_config: &OrchestratorLogConfig) {
   let executable_path = std::env::current_exe().unwrap();
   let executable_name = executable_path.file_name().unwrap().to_str().unwrap();
-  let log_file_name = format!("{}.log", executable_name);
+  let log_file_name = format!("{}.log", service_name.unwrap_or(executable_name));
+  let log_file_path = Path::new(log_config.directory.as_deref().unwrap_or_default()).join(log_file_name);
 
   let mut level_filter = LevelFilter::Info;
   if verbose {
     level_filter = LevelFilter::Trace;
   }
 
-  CombinedLogger::init(
-    vec![
-      TermLogger::new(level_filter, Config::default(), TerminalMode::Mixed, ColorChoice::Auto),
-      WriteLogger::new(level_filter, Config::default(), File::create(log_file_name).unwrap()),
-    ]
-  ).unwrap();
-}
\ No newline at end of file
+  let mut loggers: Vec<Box<dyn SharedLogger>> = vec![
+    TermLogger::new(level_filter, Config::default(), TerminalMode::Mixed, ColorChoice::Auto),
+  ];
+
+  match RotatingLogFile::open(&log_file_path, log_config.get_log_rotation(), false) {
+    Ok(log_file) => loggers.push(WriteLogger::new(level_filter, Config::default(), log_file)),
+    Err(open_error) => eprintln!("Could not open log file [{}]: {}", log_file_path.display(), open_error),
+  }
+
+  CombinedLogger::init(loggers).unwrap();
+}
@@ -0,0 +1,369 @@
+use crate::control_server::{ControlCaller, ControlErrorKind, ControlRequest, ControlResponse, StreamMessage, send_to_event_pump};
+use crate::errors::OrchestratorError;
+use crate::event_pump::Event;
+use crate::orchestrator_config::{ManagementApiConfig, ManagementApiTlsConfig};
+use crate::secrets::resolve_secret;
+
+use native_tls::{Identity, TlsAcceptor, TlsStream};
+use std::collections::HashMap;
+use std::error::Error;
+use std::io::{Read, Write};
+use std::net::{Shutdown, SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
+use std::sync::{Arc, Mutex};
+use std::sync::mpsc::{Sender, channel};
+use std::time::Duration;
+use log::{error, info, trace, warn};
+use tiny_http::{Header, Method, Request, Response, Server};
+use tungstenite::handshake::derive_accept_key;
+use tungstenite::protocol::Role;
+use tungstenite::{Message, WebSocket};
+
+const DEFAULT_MANAGEMENT_API_ADDRESS: &str = "127.0.0.1:8650";
+const EVENT_STREAM_PATH: &str = "/events/stream";
+const DASHBOARD: &str = include_str!("dashboard.html");
+
+/// Query parameter a bearer token can be given in, for browsers opening the dashboard and its
+/// WebSocket, which can't set headers.
+const ACCESS_TOKEN_PARAMETER: &str = "access_token";
+
+/// How long the forwarding of a TLS connection waits for the client before letting the response
+/// side write to the TLS stream they share.
+const TLS_READ_TIMEOUT: Duration = Duration::from_millis(50);
+
+/// Addresses of the TLS clients, by the address of the connection forwarding them to the HTTP
+/// server, which is all it sees of them.
+type TlsClientAddresses = Arc<Mutex<HashMap<SocketAddr, SocketAddr>>>;
+
+/// Serves the control requests of the control pipe over HTTP, as JSON:
+///
+/// - `GET /status`
+/// - `GET /events`
+/// - `POST /reload`
+/// - `POST /processes/<name>/start`, `/stop` and `/restart`
+///
+/// streams events and process metrics to WebSocket clients of `GET /events/stream`, and serves a
+/// dashboard page built on both at `GET /`.
+pub fn start_management_api(config: &ManagementApiConfig, sender: Sender<Event>) -> Result<(), Box<dyn Error>> {
+  let address = config.address.clone().unwrap_or_else(|| String::from(DEFAULT_MANAGEMENT_API_ADDRESS));
+
+  let tokens = config.tokens
+    .as_deref()
+    .unwrap_or_default()
+    .iter()
+    .map(|x| resolve_secret(x))
+    .collect::<Result<Vec<String>, Box<dyn Error>>>()?;
+
+  let is_loopback = address.to_socket_addrs()?.all(|x| x.ip().is_loopback());
+  if !is_loopback && tokens.is_empty() {
+    return Err(Box::new(OrchestratorError::ManagementApiTokensRequired(address)));
+  }
+
+  let tls_client_addresses = TlsClientAddresses::default();
+  let is_tls = config.tls.is_some();
+
+  let server = match &config.tls {
+    Some(tls_config) => start_tls_server(&address, tls_config, tls_client_addresses.clone())?,
+    None => {
+      if !is_loopback {
+        warn!("Management API: Listening on [{}] without TLS, tokens are sent in plain text", &address);
+      }
+
+      Server::http(address.as_str()).map_err(|x| x.to_string())?
+    }
+  };
+
+  info!("Management API: Listening on [{}]", &address);
+
+  let tokens = Arc::new(tokens);
+
+  std::thread::spawn(move || {
+    for request in server.incoming_requests() {
+      let request_sender = sender.clone();
+      let request_tokens = tokens.clone();
+      let request_tls_client_addresses = tls_client_addresses.clone();
+
+      std::thread::spawn(move || {
+        // Behind TLS, the HTTP server only answers the connections forwarding TLS clients, and not
+        // other local processes connecting to its loopback port in plain text.
+        if is_tls && !is_forwarded_tls_client(&request, &request_tls_client_addresses) {
+          respond_json(request, 403, &ControlResponse::failure(String::from("The management API only accepts TLS connections")));
+          return;
+        }
+
+        let caller = match get_authorized_caller(&request, &request_tokens, &request_tls_client_addresses) {
+          Some(caller) => caller,
+          None => {
+            respond_json(request, 401, &ControlResponse::failure(String::from("Missing or invalid bearer token")));
+            return;
+          }
+        };
+
+        handle_request(request, caller, request_sender);
+      });
+    }
+  });
+
+  Ok(())
+}
+
+/// Terminates TLS in front of the HTTP server, which then listens on an ephemeral loopback port
+/// that the TLS connections are forwarded to, and only answers those.
+fn start_tls_server(address: &str, tls_config: &ManagementApiTlsConfig, tls_client_addresses: TlsClientAddresses) -> Result<Server, Box<dyn Error>> {
+  let certificate_file_contents = std::fs::read(&tls_config.certificate_file)?;
+  let certificate_password = resolve_secret(tls_config.certificate_password.as_deref().unwrap_or_default())?;
+  let tls_acceptor = Arc::new(TlsAcceptor::new(Identity::from_pkcs12(&certificate_file_contents, &certificate_password)?)?);
+
+  let server = Server::http("127.0.0.1:0").map_err(|x| x.to_string())?;
+  let server_address = server.server_addr().to_ip().ok_or("Management API server has no IP address")?;
+
+  let tls_listener = TcpListener::bind(address)?;
+
+  std::thread::spawn(move || {
+    for tcp_stream in tls_listener.incoming().flatten() {
+      let connection_tls_acceptor = tls_acceptor.clone();
+      let connection_tls_client_addresses = tls_client_addresses.clone();
+
+      std::thread::spawn(move || {
+        if let Err(forward_error) = forward_tls_connection(tcp_stream, &connection_tls_acceptor, server_address, &connection_tls_client_addresses) {
+          trace!("Management API: TLS connection closed: {}", forward_error);
+        }
+      });
+    }
+  });
+
+  Ok(server)
+}
+
+fn forward_tls_connection(tcp_stream: TcpStream, tls_acceptor: &TlsAcceptor, server_address: SocketAddr, tls_client_addresses: &TlsClientAddresses) -> Result<(), Box<dyn Error>> {
+  let client_address = tcp_stream.peer_addr()?;
+  let tls_stream = tls_acceptor.accept(tcp_stream)?;
+  let server_stream = TcpStream::connect(server_address)?;
+  let forwarding_address = server_stream.local_addr()?;
+
+  // Registered before anything is forwarded, so the HTTP server knows the connection is from here
+  // when the first request arrives.
+  tls_client_addresses.lock().unwrap().insert(forwarding_address, client_address);
+  tls_stream.get_ref().set_read_timeout(Some(TLS_READ_TIMEOUT))?;
+  let forward_result = forward_tls_stream(Arc::new(Mutex::new(tls_stream)), server_stream);
+  tls_client_addresses.lock().unwrap().remove(&forwarding_address);
+
+  forward_result
+}
+
+/// Forwards the client requests and the server responses in a thread each. The TLS stream they
+/// share can't be split, so the thread reading it only holds it for a read of the client, which
+/// times out when the client is quiet, letting the other write the responses in between.
+fn forward_tls_stream(tls_stream: Arc<Mutex<TlsStream<TcpStream>>>, mut server_stream: TcpStream) -> Result<(), Box<dyn Error>> {
+  let request_tls_stream = tls_stream.clone();
+  let mut request_server_stream = server_stream.try_clone()?;
+
+  let request_thread = std::thread::spawn(move || -> std::io::Result<()> {
+    let mut buffer = [0u8; 16 * 1024];
+
+    loop {
+      let read_result = request_tls_stream.lock().unwrap().read(&mut buffer);
+
+      match read_result {
+        Ok(0) => break,
+        Ok(length) => request_server_stream.write_all(&buffer[..length])?,
+        Err(read_error) if is_read_timeout(&read_error) => continue,
+        Err(read_error) => return Err(read_error),
+      }
+    }
+
+    // The server sees the client is done, and closes its side once it has responded.
+    request_server_stream.shutdown(Shutdown::Write)
+  });
+
+  let mut buffer = [0u8; 16 * 1024];
+
+  let response_result = loop {
+    match server_stream.read(&mut buffer) {
+      Ok(0) => break Ok(()),
+      Ok(length) => {
+        let mut tls_stream = tls_stream.lock().unwrap();
+        if let Err(write_error) = tls_stream.write_all(&buffer[..length]).and_then(|_| tls_stream.flush()) {
+          break Err(write_error);
+        }
+      }
+      Err(read_error) => break Err(read_error),
+    }
+  };
+
+  // Ends the read of the client the request thread may be waiting on.
+  {
+    let mut tls_stream = tls_stream.lock().unwrap();
+    let _ = tls_stream.shutdown();
+    let _ = tls_stream.get_ref().shutdown(Shutdown::Both);
+  }
+
+  let _ = server_stream.shutdown(Shutdown::Both);
+  let request_result = request_thread.join().map_err(|_| "Management API TLS request thread panicked")?;
+
+  response_result?;
+  request_result?;
+
+  Ok(())
+}
+
+fn is_read_timeout(read_error: &std::io::Error) -> bool {
+  matches!(read_error.kind(), std::io::ErrorKind::WouldBlock | std::io::ErrorKind::TimedOut)
+}
+
+/// Whether the request came through a connection forwarding a TLS client.
+fn is_forwarded_tls_client(request: &Request, tls_client_addresses: &TlsClientAddresses) -> bool {
+  request.remote_addr().is_some_and(|x| tls_client_addresses.lock().unwrap().contains_key(x))
+}
+
+/// Who sent the request, by its address and the number of the token it carries, when it's
+/// authorized.
+fn get_authorized_caller(request: &Request, tokens: &[String], tls_client_addresses: &TlsClientAddresses) -> Option<ControlCaller> {
+  let token_index = get_authorized_token_index(request, tokens)?;
+
+  let remote_address = request.remote_addr().map(|remote_address| {
+    tls_client_addresses.lock().unwrap().get(remote_address).copied().unwrap_or(*remote_address)
+  });
+
+  let mut identity = remote_address.map(|x| x.to_string()).unwrap_or_else(|| String::from("unknown address"));
+  if let Some(token_index) = token_index {
+    identity = format!("{} (token {})", identity, token_index + 1);
+  }
+
+  Some(ControlCaller { channel: "http", identity })
+}
+
+/// Index of the token the request carries, in an `Authorization: Bearer` header or in the
+/// `access_token` query parameter. Every request is authorized, without a token, when no tokens
+/// are configured.
+fn get_authorized_token_index(request: &Request, tokens: &[String]) -> Option<Option<usize>> {
+  if tokens.is_empty() {
+    return Some(None);
+  }
+
+  let header_token = request.headers()
+    .iter()
+    .find(|x| x.field.equiv("Authorization"))
+    .and_then(|x| x.value.as_str().strip_prefix("Bearer "))
+    .map(|x| x.trim().to_string());
+
+  let query_token = request.url()
+    .split_once('?')
+    .and_then(|(_, query)| query.split('&').find_map(|x| x.strip_prefix(ACCESS_TOKEN_PARAMETER).and_then(|x| x.strip_prefix('='))))
+    .map(|x| x.to_string());
+
+  header_token
+    .into_iter()
+    .chain(query_token)
+    .find_map(|x| tokens.iter().position(|token| is_equal_in_constant_time(token.as_bytes(), x.as_bytes())))
+    .map(Some)
+}
+
+/// Compares without returning at the first difference, so response times don't leak how much of a
+/// guessed token is right.
+pub fn is_equal_in_constant_time(left: &[u8], right: &[u8]) -> bool {
+  left.len() == right.len() && left.iter().zip(right).fold(0u8, |difference, (x, y)| difference | (x ^ y)) == 0
+}
+
+fn handle_request(request: Request, caller: ControlCaller, sender: Sender<Event>) {
+  let path = get_path(request.url()).to_string();
+
+  if path == EVENT_STREAM_PATH && is_websocket_upgrade(&request) {
+    stream_events(request, sender);
+    return;
+  }
+
+  if request.method() == &Method::Get && path == "/" {
+    let content_type_header = Header::from_bytes(&b"Content-Type"[..], &b"text/html; charset=utf-8"[..]).unwrap();
+    if let Err(respond_error) = request.respond(Response::from_string(DASHBOARD).with_header(content_type_header)) {
+      error!("Management API: Could not respond: {}", respond_error);
+    }
+    return;
+  }
+
+  let (status_code, response) = match get_control_request(request.method(), &path) {
+    Some(control_request) => {
+      let response = send_to_event_pump(control_request, caller, &sender);
+      let status_code = match (response.ok, response.error_kind) {
+        (true, _) => 200,
+        (false, Some(ControlErrorKind::ProcessNotConfigured)) => 404,
+        (false, Some(ControlErrorKind::TimedOut)) => 504,
+        (false, _) => 400,
+      };
+      (status_code, response)
+    }
+    None => (404, ControlResponse::failure(format!("No such endpoint: {} {}", request.method(), &path))),
+  };
+
+  respond_json(request, status_code, &response);
+}
+
+fn respond_json(request: Request, status_code: u16, response: &ControlResponse) {
+  let body = serde_json::to_string(response).unwrap_or_default();
+  let content_type_header = Header::from_bytes(&b"Content-Type"[..], &b"application/json"[..]).unwrap();
+
+  if let Err(respond_error) = request.respond(Response::from_string(body).with_status_code(status_code).with_header(content_type_header)) {
+    error!("Management API: Could not respond: {}", respond_error);
+  }
+}
+
+fn get_path(url: &str) -> &str {
+  url.split('?').next().unwrap_or_default()
+}
+
+fn get_control_request(method: &Method, path: &str) -> Option<ControlRequest> {
+  let segments = path.trim_matches('/').split('/').collect::<Vec<&str>>();
+
+  match (method, segments.as_slice()) {
+    (Method::Get, ["status"]) => Some(ControlRequest::Status),
+    (Method::Get, ["events"]) => Some(ControlRequest::Events),
+    (Method::Post, ["reload"]) => Some(ControlRequest::Reload),
+    (Method::Get, ["processes", name, "logs"]) => Some(ControlRequest::Logs { name: name.to_string() }),
+    (Method::Post, ["processes", name, "start"]) => Some(ControlRequest::Start { name: name.to_string() }),
+    (Method::Post, ["processes", name, "stop"]) => Some(ControlRequest::Stop { name: name.to_string() }),
+    (Method::Post, ["processes", name, "restart"]) => Some(ControlRequest::Restart { name: name.to_string() }),
+    _ => None,
+  }
+}
+
+fn is_websocket_upgrade(request: &Request) -> bool {
+  request.headers().iter().any(|x| x.field.equiv("Upgrade") && x.value.as_str().eq_ignore_ascii_case("websocket"))
+}
+
+/// Completes the WebSocket handshake, then sends every event stream message to the client as a
+/// text message until it disconnects.
+fn stream_events(request: Request, sender: Sender<Event>) {
+  let websocket_key = request.headers()
+    .iter()
+    .find(|x| x.field.equiv("Sec-WebSocket-Key"))
+    .map(|x| x.value.as_str().to_string());
+
+  let websocket_key = match websocket_key {
+    Some(websocket_key) => websocket_key,
+    None => {
+      let _ = request.respond(Response::from_string("Missing Sec-WebSocket-Key").with_status_code(400));
+      return;
+    }
+  };
+
+  let (stream_sender, stream_receiver) = channel::<StreamMessage>();
+  if sender.send(Event::StreamSubscribed(stream_sender)).is_err() {
+    return;
+  }
+
+  let accept_header = Header::from_bytes(&b"Sec-WebSocket-Accept"[..], derive_accept_key(websocket_key.as_bytes()).as_bytes()).unwrap();
+
+  let stream = request.upgrade("websocket", Response::empty(101).with_header(accept_header));
+  let mut websocket = WebSocket::from_raw_socket(stream, Role::Server, None);
+
+  for message in stream_receiver {
+    let message_json = match serde_json::to_string(&message) {
+      Ok(message_json) => message_json,
+      Err(_) => continue,
+    };
+
+    if let Err(write_error) = websocket.write_message(Message::Text(message_json)) {
+      trace!("Management API: Event stream closed: {}", write_error);
+      break;
+    }
+  }
+}
@@ -0,0 +1,191 @@
+use crate::log_capture::{DEFAULT_LOG_MAX_FILES, LogRotation, LogRotationPeriod};
+
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::error::Error;
+use std::path::PathBuf;
+use std::time::Duration;
+
+/// Name of the file holding the settings of the orchestrator itself, rather than of a process.
+pub const ORCHESTRATOR_CONFIG_FILE_NAME: &str = "orchestrator.yml";
+
+/// Settings of the orchestrator itself, read from `orchestrator.yml` next to the executable.
+#[derive(Debug, Default, Deserialize, Clone)]
+pub struct OrchestratorConfig {
+  #[serde(default)]
+  pub service: ServiceConfig,
+  /// Where per-process metrics are pushed in StatsD/DogStatsD format.
+  pub statsd: Option<StatsdConfig>,
+  /// OpenTelemetry collector that process spans and metrics are exported to.
+  pub opentelemetry: Option<OpenTelemetryConfig>,
+  /// HTTP server for managing the orchestrator, off unless configured.
+  pub management_api: Option<ManagementApiConfig>,
+  /// gRPC server for managing the orchestrator with clients generated from
+  /// `proto/orchestrator.proto`, off unless configured.
+  pub grpc_api: Option<GrpcApiConfig>,
+  /// Log collector that process output and orchestrator events are forwarded to.
+  pub log_shipping: Option<LogShippingConfig>,
+  /// What is done when a volume the processes log to runs low on free space.
+  pub log_disk_guard: Option<LogDiskGuardConfig>,
+  /// When the log files of the processes are removed.
+  pub log_retention: Option<LogRetentionConfig>,
+  /// Where the orchestrator writes its own log, and how it is rotated.
+  pub log: Option<OrchestratorLogConfig>,
+}
+
+/// How the orchestrator is registered as a Windows service. Install flags take precedence.
+#[derive(Debug, Default, Deserialize, Clone)]
+pub struct ServiceConfig {
+  pub name: Option<String>,
+  pub display_name: Option<String>,
+  pub description: Option<String>,
+  /// Windows services started before the orchestrator.
+  pub dependencies: Option<Vec<String>>,
+  pub delayed_auto_start: Option<bool>,
+  /// What the service control manager does when the orchestrator crashes.
+  pub recovery: Option<ServiceRecoveryConfig>,
+  /// How long Windows waits for the processes to stop when shutting down.
+  pub preshutdown_timeout_secs: Option<u64>,
+}
+
+#[derive(Debug, Default, Deserialize, Clone)]
+pub struct ServiceRecoveryConfig {
+  pub restart_delay_secs: Option<u64>,
+  pub command: Option<String>,
+  pub reset_period_secs: Option<u64>,
+}
+
+#[derive(Debug, Default, Deserialize, Clone)]
+pub struct StatsdConfig {
+  pub host: Option<String>,
+  pub port: Option<u16>,
+  pub prefix: Option<String>,
+  /// DogStatsD tags added to every metric, e.g. `env:prod`.
+  pub tags: Option<Vec<String>>,
+}
+
+#[derive(Debug, Default, Deserialize, Clone)]
+pub struct OpenTelemetryConfig {
+  /// Base URL of the OTLP/HTTP receiver, `/v1/traces` and `/v1/metrics` are appended to it.
+  pub endpoint: Option<String>,
+  /// Defaults to the name of the service.
+  pub service_name: Option<String>,
+  pub headers: Option<HashMap<String, String>>,
+}
+
+#[derive(Debug, Default, Deserialize, Clone)]
+pub struct LogShippingConfig {
+  /// URL the batches of records are posted to, e.g. a fluentd `in_http` or Graylog GELF HTTP input.
+  pub endpoint: String,
+  pub format: Option<LogShippingFormat>,
+  /// Records sent in one request, 500 by default.
+  pub batch_size: Option<usize>,
+  /// Longest a record waits for its batch to fill up, 5 by default.
+  pub batch_interval_secs: Option<f64>,
+  /// Times a batch is sent again when it fails, 3 by default, before it is dropped.
+  pub max_retries: Option<u32>,
+  pub headers: Option<HashMap<String, String>>,
+}
+
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum LogShippingFormat {
+  /// A JSON array of records per batch.
+  Json,
+  /// Newline-delimited GELF messages, for a Graylog input with bulk receiving enabled.
+  Gelf,
+}
+
+#[derive(Debug, Default, Deserialize, Clone)]
+pub struct LogDiskGuardConfig {
+  /// Free space below which the action is taken.
+  pub min_free_mbs: f64,
+  pub action: Option<LogDiskGuardAction>,
+  /// How often the free space is checked, 30 by default.
+  pub check_interval_secs: Option<f64>,
+}
+
+/// What the orchestrator does about the processes logging to a volume low on free space.
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum LogDiskGuardAction {
+  /// Stop writing their captured output to their log files until there is enough space again.
+  PauseCapture,
+  /// Remove their rotated log files, keeping the current ones.
+  RemoveRotated,
+  /// Recycle the process with the largest log file.
+  Recycle,
+}
+
+#[derive(Debug, Default, Deserialize, Clone)]
+pub struct LogRetentionConfig {
+  pub max_age_days: Option<f64>,
+  /// Size the log files of each process may add up to.
+  pub max_total_size_mbs: Option<f64>,
+  /// How often the log files are cleaned up, 60 by default.
+  pub interval_mins: Option<f64>,
+}
+
+#[derive(Debug, Default, Deserialize, Clone)]
+pub struct OrchestratorLogConfig {
+  /// Directory of the log file, next to the executable by default.
+  pub directory: Option<String>,
+  pub max_size_mbs: Option<f64>,
+  pub rotation: Option<LogRotationPeriod>,
+  pub max_files: Option<usize>,
+  pub max_age_days: Option<f64>,
+  pub compress: Option<bool>,
+}
+
+impl OrchestratorLogConfig {
+  /// How the log file is rotated, as for the log files of the processes.
+  pub fn get_log_rotation(&self) -> LogRotation {
+    LogRotation {
+      max_size_bytes: self.max_size_mbs.map(|x| (x * 1024f64 * 1024f64) as u64),
+      period: self.rotation,
+      max_files: self.max_files.unwrap_or(DEFAULT_LOG_MAX_FILES),
+      max_age: self.max_age_days.map(|x| Duration::from_secs_f64(x * 86400f64)),
+      compress: self.compress.unwrap_or(false),
+    }
+  }
+}
+
+#[derive(Debug, Default, Deserialize, Clone)]
+pub struct ManagementApiConfig {
+  /// Address the API listens on, `127.0.0.1:8650` by default.
+  pub address: Option<String>,
+  /// Bearer tokens accepted by the API, required when it listens beyond the loopback address.
+  /// They can be encrypted or Credential Manager references.
+  pub tokens: Option<Vec<String>>,
+  pub tls: Option<ManagementApiTlsConfig>,
+}
+
+#[derive(Debug, Default, Deserialize, Clone)]
+pub struct GrpcApiConfig {
+  /// Loopback address the API listens on, `127.0.0.1:8651` by default.
+  pub address: Option<String>,
+  /// Bearer tokens accepted by the API, so that not every local user can control the processes.
+  pub tokens: Option<Vec<String>>,
+}
+
+#[derive(Debug, Default, Deserialize, Clone)]
+pub struct ManagementApiTlsConfig {
+  /// PKCS#12 (`.pfx`) file holding the certificate and its private key.
+  pub certificate_file: String,
+  pub certificate_password: Option<String>,
+}
+
+pub fn get_orchestrator_config_file_path() -> PathBuf {
+  std::env::current_exe().unwrap().with_file_name(ORCHESTRATOR_CONFIG_FILE_NAME)
+}
+
+pub fn load_orchestrator_config() -> Result<OrchestratorConfig, Box<dyn Error>> {
+  let orchestrator_config_file_path = get_orchestrator_config_file_path();
+  if !orchestrator_config_file_path.exists() {
+    return Ok(OrchestratorConfig::default());
+  }
+
+  let orchestrator_config_file_contents = std::fs::read_to_string(orchestrator_config_file_path)?;
+  let orchestrator_config = serde_yaml::from_str::<OrchestratorConfig>(orchestrator_config_file_contents.as_str())?;
+  Ok(orchestrator_config)
+}
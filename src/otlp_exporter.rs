@@ -0,0 +1,145 @@
+use crate::http_client::create_http_agent;
+use crate::orchestrator_config::OpenTelemetryConfig;
+use crate::stateful_process::StatefulProcess;
+
+use nanoid::nanoid;
+use serde_json::{json, Value};
+use std::error::Error;
+use std::sync::mpsc::{Sender, channel};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use log::{trace, warn};
+
+const DEFAULT_OTLP_ENDPOINT: &str = "http://localhost:4318";
+const OTLP_SCOPE_NAME: &str = "process-orchestrator";
+
+/// Exports spans and metrics to an OpenTelemetry collector, as OTLP JSON over HTTP. Requests are
+/// sent from a background thread, so a slow or missing collector never holds up the event pump.
+pub struct OtlpExporter {
+  sender: Sender<(String, Value)>,
+  resource: Value,
+}
+
+impl OtlpExporter {
+  pub fn new(config: &OpenTelemetryConfig, service_name: &str) -> Result<Self, Box<dyn Error>> {
+    let agent = create_http_agent(Duration::from_secs(10))?;
+    let endpoint = config.endpoint.clone().unwrap_or_else(|| String::from(DEFAULT_OTLP_ENDPOINT));
+    let headers = config.headers.clone().unwrap_or_default();
+
+    let (sender, receiver) = channel::<(String, Value)>();
+
+    std::thread::spawn(move || {
+      for (path, body) in receiver {
+        let url = format!("{}{}", endpoint.trim_end_matches('/'), path);
+        let mut request = agent.post(&url).set("Content-Type", "application/json");
+
+        for (header_name, header_value) in &headers {
+          request = request.set(header_name, header_value);
+        }
+
+        if let Err(export_error) = request.send_string(&body.to_string()) {
+          warn!("OpenTelemetry [{}]: Export failed: {}", &url, export_error);
+        }
+      }
+    });
+
+    let host_name = std::env::var("COMPUTERNAME").unwrap_or_default();
+
+    Ok(Self {
+      sender,
+      resource: json!({
+        "attributes": [
+          get_attribute("service.name", config.service_name.as_deref().unwrap_or(service_name)),
+          get_attribute("host.name", &host_name),
+        ]
+      }),
+    })
+  }
+
+  /// Exports a span about a process, e.g. its start, stop or recycle, with extra attributes such as
+  /// the reason for it.
+  pub fn export_process_span(&self, span_name: &str, process: &StatefulProcess, started_at: SystemTime, attributes: &[(&str, String)]) {
+    let mut span_attributes = get_process_attributes(process);
+    span_attributes.extend(attributes.iter().map(|(key, value)| get_attribute(key, value)));
+
+    let span = json!({
+      "traceId": get_random_hex_id(32),
+      "spanId": get_random_hex_id(16),
+      "name": span_name,
+      "kind": 1,
+      "startTimeUnixNano": get_unix_nanos(started_at),
+      "endTimeUnixNano": get_unix_nanos(SystemTime::now()),
+      "attributes": span_attributes,
+    });
+
+    self.export("/v1/traces", json!({
+      "resourceSpans": [{
+        "resource": self.resource,
+        "scopeSpans": [{ "scope": { "name": OTLP_SCOPE_NAME }, "spans": [span] }]
+      }]
+    }));
+  }
+
+  /// Exports the memory and uptime gauges of the processes in a single request.
+  pub fn export_process_metrics(&self, processes: &[StatefulProcess]) {
+    let time_unix_nano = get_unix_nanos(SystemTime::now());
+
+    let get_data_points = |get_value: &dyn Fn(&StatefulProcess) -> Option<f64>| {
+      processes
+        .iter()
+        .filter_map(|process| get_value(process).map(|value| json!({
+          "asDouble": value,
+          "timeUnixNano": time_unix_nano,
+          "attributes": get_process_attributes(process),
+        })))
+        .collect::<Vec<Value>>()
+    };
+
+    let metrics = json!([
+      {
+        "name": "process.memory",
+        "unit": "MBy",
+        "gauge": { "dataPoints": get_data_points(&|process| process.memory_usage_mbs) }
+      },
+      {
+        "name": "process.uptime",
+        "unit": "s",
+        "gauge": { "dataPoints": get_data_points(&|process| process.duration_secs) }
+      }
+    ]);
+
+    self.export("/v1/metrics", json!({
+      "resourceMetrics": [{
+        "resource": self.resource,
+        "scopeMetrics": [{ "scope": { "name": OTLP_SCOPE_NAME }, "metrics": metrics }]
+      }]
+    }));
+  }
+
+  fn export(&self, path: &str, body: Value) {
+    if self.sender.send((path.to_string(), body)).is_err() {
+      trace!("OpenTelemetry: Exporter thread has stopped");
+    }
+  }
+}
+
+fn get_process_attributes(process: &StatefulProcess) -> Vec<Value> {
+  vec![
+    get_attribute("process.name", &process.config.name),
+    get_attribute("process.id", &process.id),
+    get_attribute("process.instance_index", &process.instance_index.to_string()),
+    get_attribute("process.pid", &process.get_pid().map(|x| x.to_string()).unwrap_or_default()),
+  ]
+}
+
+fn get_attribute(key: &str, value: &str) -> Value {
+  json!({ "key": key, "value": { "stringValue": value } })
+}
+
+fn get_random_hex_id(length: usize) -> String {
+  let alphabet: [char; 16] = ['0', '1', '2', '3', '4', '5', '6', '7', '8', '9', 'a', 'b', 'c', 'd', 'e', 'f'];
+  nanoid!(length, &alphabet)
+}
+
+fn get_unix_nanos(time: SystemTime) -> String {
+  time.duration_since(UNIX_EPOCH).unwrap_or_default().as_nanos().to_string()
+}
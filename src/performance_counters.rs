@@ -0,0 +1,257 @@
+use crate::stateful_process::StatefulProcess;
+
+use std::collections::HashMap;
+use std::error::Error;
+use std::ffi::OsStr;
+use std::os::windows::ffi::OsStrExt;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::ptr::null_mut;
+use log::trace;
+use winapi::shared::guiddef::GUID;
+use winapi::shared::minwindef::ULONG;
+use winapi::shared::winerror::ERROR_SUCCESS;
+use winapi::um::perflib::{
+  PerfCreateInstance, PerfSetCounterSetInfo, PerfSetULongLongCounterValue, PerfStartProvider, PerfStopProvider,
+  PERF_COUNTERSET_INFO, PERF_COUNTERSET_INSTANCE, PERF_COUNTERSET_MULTI_INSTANCES, PERF_COUNTER_INFO,
+};
+use winapi::um::winnt::HANDLE;
+
+/// `{f84ea073-eeee-421c-b01d-777ace04e135}`
+const PERF_PROVIDER_ID: GUID = GUID {
+  Data1: 0xf84ea073,
+  Data2: 0xeeee,
+  Data3: 0x421c,
+  Data4: [0xb0, 0x1d, 0x77, 0x7a, 0xce, 0x04, 0xe1, 0x35],
+};
+
+/// `{9a061dcf-7fd1-43c4-b629-3e054c411dbc}`
+const PERF_COUNTERSET_ID: GUID = GUID {
+  Data1: 0x9a061dcf,
+  Data2: 0x7fd1,
+  Data3: 0x43c4,
+  Data4: [0xb6, 0x29, 0x3e, 0x05, 0x4c, 0x41, 0x1d, 0xbc],
+};
+
+const PERF_COUNTERSET_NAME: &str = "Process Orchestrator";
+
+const MEMORY_MBS_COUNTER_ID: ULONG = 1;
+const UPTIME_SECS_COUNTER_ID: ULONG = 2;
+const RESTARTS_COUNTER_ID: ULONG = 3;
+
+// From winperf.h, which winapi doesn't have.
+const PERF_COUNTER_LARGE_RAWCOUNT: ULONG = 0x00010100;
+const PERF_DETAIL_NOVICE: ULONG = 100;
+
+/// Counter set template in the layout `PerfSetCounterSetInfo` expects: the set, then its counters.
+#[repr(C)]
+struct CounterSetTemplate {
+  counter_set: PERF_COUNTERSET_INFO,
+  counters: [PERF_COUNTER_INFO; 3],
+}
+
+/// Publishes the memory, uptime and restart count of every managed process as Windows performance
+/// counters, with an instance per process name and instance index. The counter set is only
+/// visible to perfmon once registered with `install_performance_counters`.
+pub struct PerformanceCounters {
+  provider_handle: Option<HANDLE>,
+  instances: HashMap<(String, usize), *mut PERF_COUNTERSET_INSTANCE>,
+}
+
+impl PerformanceCounters {
+  pub fn start() -> Self {
+    let mut performance_counters = Self {
+      provider_handle: None,
+      instances: HashMap::new(),
+    };
+
+    unsafe {
+      let mut provider_handle: HANDLE = null_mut();
+      let mut provider_id = PERF_PROVIDER_ID;
+
+      let start_result = PerfStartProvider(&mut provider_id, None, &mut provider_handle);
+      if start_result != ERROR_SUCCESS {
+        trace!("PerformanceCounters: Could not start provider: {}", std::io::Error::from_raw_os_error(start_result as i32));
+        return performance_counters;
+      }
+
+      let mut template = CounterSetTemplate {
+        counter_set: PERF_COUNTERSET_INFO {
+          CounterSetGuid: PERF_COUNTERSET_ID,
+          ProviderGuid: PERF_PROVIDER_ID,
+          NumCounters: 3,
+          InstanceType: PERF_COUNTERSET_MULTI_INSTANCES,
+        },
+        counters: [
+          get_counter_info(MEMORY_MBS_COUNTER_ID, 0),
+          get_counter_info(UPTIME_SECS_COUNTER_ID, 1),
+          get_counter_info(RESTARTS_COUNTER_ID, 2),
+        ],
+      };
+
+      let set_result = PerfSetCounterSetInfo(provider_handle, &mut template.counter_set, std::mem::size_of::<CounterSetTemplate>() as ULONG);
+      if set_result != ERROR_SUCCESS {
+        trace!("PerformanceCounters: Could not set counter set: {}", std::io::Error::from_raw_os_error(set_result as i32));
+        PerfStopProvider(provider_handle);
+        return performance_counters;
+      }
+
+      performance_counters.provider_handle = Some(provider_handle);
+    }
+
+    performance_counters
+  }
+
+  pub fn update(&mut self, process: &StatefulProcess, restart_count: u64) {
+    let memory_usage_mbs = process.memory_usage_mbs.unwrap_or(0f64).round() as u64;
+    let duration_secs = process.duration_secs.unwrap_or(0f64).round() as u64;
+
+    self.set_values(&process.config.name, process.instance_index, memory_usage_mbs, duration_secs, restart_count);
+  }
+
+  /// Zeroes the memory and uptime of a stopped process, keeping its restart count.
+  pub fn clear(&mut self, process_name: &str, instance_index: usize, restart_count: u64) {
+    self.set_values(process_name, instance_index, 0, 0, restart_count);
+  }
+
+  fn set_values(&mut self, process_name: &str, instance_index: usize, memory_usage_mbs: u64, duration_secs: u64, restart_count: u64) {
+    let provider_handle = match self.provider_handle {
+      Some(provider_handle) => provider_handle,
+      None => return,
+    };
+
+    let instance = match self.get_instance(provider_handle, process_name, instance_index) {
+      Some(instance) => instance,
+      None => return,
+    };
+
+    unsafe {
+      PerfSetULongLongCounterValue(provider_handle, instance, MEMORY_MBS_COUNTER_ID, memory_usage_mbs);
+      PerfSetULongLongCounterValue(provider_handle, instance, UPTIME_SECS_COUNTER_ID, duration_secs);
+      PerfSetULongLongCounterValue(provider_handle, instance, RESTARTS_COUNTER_ID, restart_count);
+    }
+  }
+
+  fn get_instance(&mut self, provider_handle: HANDLE, process_name: &str, instance_index: usize) -> Option<*mut PERF_COUNTERSET_INSTANCE> {
+    let key = (process_name.to_string(), instance_index);
+    if let Some(instance) = self.instances.get(&key) {
+      return Some(*instance);
+    }
+
+    let instance_name_wide = to_wide(&format!("{}#{}", process_name, instance_index));
+
+    let instance = unsafe {
+      PerfCreateInstance(provider_handle, &PERF_COUNTERSET_ID, instance_name_wide.as_ptr(), self.instances.len() as ULONG)
+    };
+
+    if instance.is_null() {
+      trace!("PerformanceCounters: Could not create instance [{}#{}]: {}", process_name, instance_index, std::io::Error::last_os_error());
+      return None;
+    }
+
+    self.instances.insert(key, instance);
+    Some(instance)
+  }
+}
+
+impl Drop for PerformanceCounters {
+  fn drop(&mut self) {
+    if let Some(provider_handle) = self.provider_handle {
+      unsafe {
+        // Stopping the provider deletes its instances.
+        PerfStopProvider(provider_handle);
+      }
+    }
+  }
+}
+
+/// Registers the counter set with Windows through a manifest written next to the executable.
+pub fn install_performance_counters() -> Result<(), Box<dyn Error>> {
+  let manifest_file_path = get_manifest_file_path();
+  std::fs::write(&manifest_file_path, get_manifest())?;
+
+  run_counter_tool("lodctr", &manifest_file_path)
+}
+
+pub fn uninstall_performance_counters() -> Result<(), Box<dyn Error>> {
+  let manifest_file_path = get_manifest_file_path();
+  if !manifest_file_path.exists() {
+    return Ok(());
+  }
+
+  run_counter_tool("unlodctr", &manifest_file_path)?;
+  std::fs::remove_file(&manifest_file_path)?;
+
+  Ok(())
+}
+
+fn run_counter_tool(tool_name: &str, manifest_file_path: &Path) -> Result<(), Box<dyn Error>> {
+  let output = Command::new(tool_name)
+    .arg(format!("/m:{}", manifest_file_path.to_string_lossy()))
+    .output()?;
+
+  if !output.status.success() {
+    return Err(format!("{} failed: {}", tool_name, String::from_utf8_lossy(&output.stdout).trim()).into());
+  }
+
+  Ok(())
+}
+
+fn get_manifest_file_path() -> PathBuf {
+  std::env::current_exe().unwrap().with_extension("perfcounters.man")
+}
+
+fn get_manifest() -> String {
+  let executable_name = std::env::current_exe()
+    .ok()
+    .and_then(|x| x.file_name().map(|x| x.to_string_lossy().to_string()))
+    .unwrap_or_default();
+
+  let get_counter = |counter_id: ULONG, name: &str, description: &str| format!(
+    r#"          <counter id="{}" uri="ProcessOrchestrator.{}" name="{}" description="{}" type="perf_counter_large_rawcount" detailLevel="standard" />"#,
+    counter_id, name.replace(' ', ""), name, description);
+
+  [
+    r#"<?xml version="1.0" encoding="UTF-8"?>"#.to_string(),
+    r#"<instrumentationManifest xmlns="http://schemas.microsoft.com/win/2004/08/events" xmlns:win="http://manifests.microsoft.com/win/2004/08/windows/events" xmlns:xs="http://www.w3.org/2001/XMLSchema">"#.to_string(),
+    r#"  <instrumentation>"#.to_string(),
+    r#"    <counters xmlns="http://schemas.microsoft.com/win/2005/12/counters" schemaVersion="2.0">"#.to_string(),
+    format!(r#"      <provider providerName="ProcessOrchestrator" providerGuid="{{{}}}" applicationIdentity="{}" providerType="userMode">"#, format_guid(&PERF_PROVIDER_ID), executable_name),
+    format!(r#"        <counterSet guid="{{{}}}" uri="ProcessOrchestrator" name="{}" description="Processes managed by the process orchestrator" instances="multiple">"#, format_guid(&PERF_COUNTERSET_ID), PERF_COUNTERSET_NAME),
+    get_counter(MEMORY_MBS_COUNTER_ID, "Memory MB", "Working set of the process in megabytes"),
+    get_counter(UPTIME_SECS_COUNTER_ID, "Uptime Seconds", "Seconds since the process was started"),
+    get_counter(RESTARTS_COUNTER_ID, "Restarts", "Times the process has been restarted by the orchestrator"),
+    r#"        </counterSet>"#.to_string(),
+    r#"      </provider>"#.to_string(),
+    r#"    </counters>"#.to_string(),
+    r#"  </instrumentation>"#.to_string(),
+    r#"</instrumentationManifest>"#.to_string(),
+  ].join("\r\n")
+}
+
+fn get_counter_info(counter_id: ULONG, index: i32) -> PERF_COUNTER_INFO {
+  PERF_COUNTER_INFO {
+    CounterId: counter_id,
+    Type: PERF_COUNTER_LARGE_RAWCOUNT,
+    Attrib: 0,
+    Size: std::mem::size_of::<u64>() as ULONG,
+    DetailLevel: PERF_DETAIL_NOVICE,
+    Scale: 0,
+    Offset: index * std::mem::size_of::<u64>() as i32,
+  }
+}
+
+fn format_guid(guid: &GUID) -> String {
+  format!(
+    "{:08x}-{:04x}-{:04x}-{:02x}{:02x}-{:02x}{:02x}{:02x}{:02x}{:02x}{:02x}",
+    guid.Data1, guid.Data2, guid.Data3,
+    guid.Data4[0], guid.Data4[1], guid.Data4[2], guid.Data4[3],
+    guid.Data4[4], guid.Data4[5], guid.Data4[6], guid.Data4[7])
+}
+
+fn to_wide(value: &str) -> Vec<u16> {
+  OsStr::new(value)
+    .encode_wide()
+    .chain(std::iter::once(0))
+    .collect()
+}
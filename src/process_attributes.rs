@@ -0,0 +1,176 @@
+use serde::{Deserialize, Serialize};
+use std::ptr::null_mut;
+use winapi::shared::basetsd::{DWORD_PTR, SIZE_T};
+use winapi::shared::minwindef::DWORD;
+use winapi::um::processthreadsapi::{
+  DeleteProcThreadAttributeList, InitializeProcThreadAttributeList, UpdateProcThreadAttribute, LPPROC_THREAD_ATTRIBUTE_LIST,
+};
+use winapi::um::systemtopologyapi::GetNumaNodeProcessorMaskEx;
+use winapi::um::winbase::{GetActiveProcessorCount, GetActiveProcessorGroupCount};
+use winapi::um::winnt::GROUP_AFFINITY;
+
+/// `PROC_THREAD_ATTRIBUTE_SECURITY_CAPABILITIES`, left out of winapi: runs the process in the
+/// AppContainer of a `SECURITY_CAPABILITIES`.
+pub const PROC_THREAD_ATTRIBUTE_SECURITY_CAPABILITIES: DWORD_PTR = 0x00020009;
+
+/// `PROC_THREAD_ATTRIBUTE_MITIGATION_POLICY`: the `PROCESS_CREATION_MITIGATION_POLICY_*` flags
+/// the process is created with.
+pub const PROC_THREAD_ATTRIBUTE_MITIGATION_POLICY: DWORD_PTR = 0x00020007;
+
+/// `PROC_THREAD_ATTRIBUTE_CHILD_PROCESS_POLICY`: whether the process can create processes.
+pub const PROC_THREAD_ATTRIBUTE_CHILD_PROCESS_POLICY: DWORD_PTR = 0x0002000E;
+
+/// `PROCESS_CREATION_CHILD_PROCESS_RESTRICTED`
+pub const CHILD_PROCESS_RESTRICTED: DWORD = 0x01;
+
+/// `PROC_THREAD_ATTRIBUTE_GROUP_AFFINITY`: the processor group, and processors in it, the first
+/// thread of the process runs on, as a `GROUP_AFFINITY`.
+pub const PROC_THREAD_ATTRIBUTE_GROUP_AFFINITY: DWORD_PTR = 0x00030003;
+
+/// `PROC_THREAD_ATTRIBUTE_PREFERRED_NODE`: the NUMA node the memory of the process is allocated
+/// from first.
+pub const PROC_THREAD_ATTRIBUTE_PREFERRED_NODE: DWORD_PTR = 0x00020004;
+
+/// The processors of the NUMA node, unless the machine has no such node.
+pub fn get_numa_node_affinity(numa_node: u16) -> Option<GROUP_AFFINITY> {
+  unsafe {
+    let mut affinity = std::mem::zeroed::<GROUP_AFFINITY>();
+
+    if GetNumaNodeProcessorMaskEx(numa_node, &mut affinity) == 0 || affinity.Mask == 0 {
+      return None;
+    }
+
+    Some(affinity)
+  }
+}
+
+/// Every processor of the processor group, unless the machine has no such group.
+pub fn get_processor_group_affinity(processor_group: u16) -> Option<GROUP_AFFINITY> {
+  unsafe {
+    if processor_group >= GetActiveProcessorGroupCount() {
+      return None;
+    }
+
+    let processor_count = GetActiveProcessorCount(processor_group);
+    let mut affinity = std::mem::zeroed::<GROUP_AFFINITY>();
+    affinity.Group = processor_group;
+    affinity.Mask = match processor_count {
+      0 => return None,
+      count if count as usize >= usize::BITS as usize => usize::MAX,
+      count => (1usize << count) - 1,
+    };
+
+    Some(affinity)
+  }
+}
+
+/// Exploit mitigation a process can be created with, on top of those its executable opts into.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum Mitigation {
+  /// Data execution prevention, always on for 64-bit processes.
+  Dep,
+  /// Structured exception handler overwrite protection.
+  Sehop,
+  /// Relocate images not built for ASLR.
+  ForceAslr,
+  BottomUpAslr,
+  HighEntropyAslr,
+  /// Control flow guard, for executables and DLLs built with it.
+  ControlFlowGuard,
+  /// Only load DLLs signed by Microsoft.
+  BlockNonMicrosoftBinaries,
+  /// Don't load images from network shares.
+  NoRemoteImages,
+  /// Don't load images labeled low integrity, e.g. downloaded.
+  NoLowLabelImages,
+  /// Load DLLs from `System32` before the application's folder.
+  PreferSystem32Images,
+  /// Don't load legacy extension points, e.g. AppInit DLLs and IMEs.
+  DisableExtensionPoints,
+  StrictHandleChecks,
+  /// Don't let the process create processes.
+  NoChildProcesses,
+}
+
+impl Mitigation {
+  /// The `PROCESS_CREATION_MITIGATION_POLICY_*` flag of the mitigation, when it is one.
+  pub fn get_policy_flag(&self) -> Option<u64> {
+    match self {
+      Mitigation::Dep => Some(0x01),
+      Mitigation::Sehop => Some(0x04),
+      Mitigation::ForceAslr => Some(1 << 8),
+      Mitigation::BottomUpAslr => Some(1 << 16),
+      Mitigation::HighEntropyAslr => Some(1 << 20),
+      Mitigation::StrictHandleChecks => Some(1 << 24),
+      Mitigation::DisableExtensionPoints => Some(1 << 32),
+      Mitigation::ControlFlowGuard => Some(1 << 40),
+      Mitigation::BlockNonMicrosoftBinaries => Some(1 << 44),
+      Mitigation::NoRemoteImages => Some(1 << 52),
+      Mitigation::NoLowLabelImages => Some(1 << 56),
+      Mitigation::PreferSystem32Images => Some(1 << 60),
+      Mitigation::NoChildProcesses => None,
+    }
+  }
+}
+
+/// Attributes a process is created with through extended startup information, e.g. to run it in
+/// an AppContainer.
+#[derive(Default)]
+pub struct ProcessAttributes {
+  /// Each attribute with its value and the value's size. Windows reads the values when the process
+  /// is created, so they are kept here until then, 8-byte aligned for the pointers they hold.
+  attributes: Vec<(DWORD_PTR, Vec<u64>, usize)>,
+  list: Vec<u64>,
+}
+
+impl ProcessAttributes {
+  pub fn is_empty(&self) -> bool {
+    self.attributes.is_empty()
+  }
+
+  pub fn add<T: Copy>(&mut self, attribute: DWORD_PTR, value: T) {
+    let size = std::mem::size_of::<T>();
+    let mut storage = vec![0u64; size.div_ceil(8)];
+
+    unsafe { std::ptr::write(storage.as_mut_ptr() as *mut T, value); }
+
+    self.attributes.push((attribute, storage, size));
+  }
+
+  /// The attribute list to create the process with, valid until this is dropped.
+  pub fn create_list(&mut self) -> std::io::Result<LPPROC_THREAD_ATTRIBUTE_LIST> {
+    let attribute_count = self.attributes.len() as DWORD;
+    let mut list_size: SIZE_T = 0;
+
+    unsafe {
+      // Fails, giving the size needed.
+      InitializeProcThreadAttributeList(null_mut(), attribute_count, 0, &mut list_size);
+
+      let mut list = vec![0u64; list_size.div_ceil(8)];
+      if InitializeProcThreadAttributeList(list.as_mut_ptr() as LPPROC_THREAD_ATTRIBUTE_LIST, attribute_count, 0, &mut list_size) == 0 {
+        return Err(std::io::Error::last_os_error());
+      }
+
+      // Deleted when dropped from here on.
+      self.list = list;
+      let list = self.list.as_mut_ptr() as LPPROC_THREAD_ATTRIBUTE_LIST;
+
+      for (attribute, value, value_size) in &mut self.attributes {
+        if UpdateProcThreadAttribute(list, 0, *attribute, value.as_mut_ptr() as _, *value_size, null_mut(), null_mut()) == 0 {
+          return Err(std::io::Error::last_os_error());
+        }
+      }
+
+      Ok(list)
+    }
+  }
+}
+
+impl Drop for ProcessAttributes {
+  fn drop(&mut self) {
+    if !self.list.is_empty() {
+      unsafe { DeleteProcThreadAttributeList(self.list.as_mut_ptr() as LPPROC_THREAD_ATTRIBUTE_LIST); }
+    }
+  }
+}
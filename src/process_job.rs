@@ -0,0 +1,141 @@
+use serde::{Deserialize, Serialize};
+use std::ptr::null_mut;
+use winapi::shared::minwindef::{DWORD, LPVOID};
+use winapi::um::handleapi::CloseHandle;
+use winapi::um::jobapi2::{
+  AssignProcessToJobObject, CreateJobObjectW, QueryInformationJobObject, SetInformationJobObject, TerminateJobObject,
+};
+use winapi::um::winnt::{
+  HANDLE, JOBOBJECT_CPU_RATE_CONTROL_INFORMATION, JOBOBJECT_EXTENDED_LIMIT_INFORMATION, JOB_OBJECT_CPU_RATE_CONTROL_ENABLE,
+  JOB_OBJECT_CPU_RATE_CONTROL_HARD_CAP, JOB_OBJECT_LIMIT_ACTIVE_PROCESS, JOB_OBJECT_LIMIT_BREAKAWAY_OK, JOB_OBJECT_LIMIT_JOB_MEMORY,
+  JOB_OBJECT_LIMIT_SILENT_BREAKAWAY_OK, JobObjectCpuRateControlInformation,
+  JobObjectExtendedLimitInformation,
+};
+
+/// Whether the processes a process starts may leave its job, and so its limits.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum JobBreakaway {
+  /// Every process started stays in the job.
+  None,
+  /// Processes started with `CREATE_BREAKAWAY_FROM_JOB` leave the job.
+  Allowed,
+  /// Every process started leaves the job.
+  Silent,
+}
+
+/// Job object a process is put in for the kernel to enforce limits on it, and on the processes it
+/// starts, closed when dropped.
+pub struct ProcessJob {
+  handle: HANDLE,
+}
+
+impl ProcessJob {
+  pub fn create() -> std::io::Result<Self> {
+    let handle = unsafe { CreateJobObjectW(null_mut(), null_mut()) };
+
+    if handle.is_null() {
+      return Err(std::io::Error::last_os_error());
+    }
+
+    Ok(Self { handle })
+  }
+
+  /// Caps the CPU the processes of the job use together, as a percentage of all the processors of
+  /// the machine, from 0.01 to 100.
+  pub fn set_cpu_rate_limit(&self, cpu_limit_percent: f64) -> std::io::Result<()> {
+    unsafe {
+      let mut cpu_rate_control = std::mem::zeroed::<JOBOBJECT_CPU_RATE_CONTROL_INFORMATION>();
+      cpu_rate_control.ControlFlags = JOB_OBJECT_CPU_RATE_CONTROL_ENABLE | JOB_OBJECT_CPU_RATE_CONTROL_HARD_CAP;
+      // In hundredths of a percent.
+      *cpu_rate_control.u.CpuRate_mut() = (cpu_limit_percent * 100f64).round().clamp(1f64, 10000f64) as DWORD;
+
+      if SetInformationJobObject(
+        self.handle,
+        JobObjectCpuRateControlInformation,
+        &mut cpu_rate_control as *mut JOBOBJECT_CPU_RATE_CONTROL_INFORMATION as LPVOID,
+        std::mem::size_of::<JOBOBJECT_CPU_RATE_CONTROL_INFORMATION>() as DWORD) == 0 {
+        return Err(std::io::Error::last_os_error());
+      }
+    }
+
+    Ok(())
+  }
+
+  /// Limits the memory the processes of the job commit together, beyond which their allocations
+  /// fail.
+  pub fn set_memory_limit(&self, memory_limit_mbs: f64) -> std::io::Result<()> {
+    self.update_extended_limits(|limits| {
+      limits.BasicLimitInformation.LimitFlags |= JOB_OBJECT_LIMIT_JOB_MEMORY;
+      limits.JobMemoryLimit = (memory_limit_mbs * 1024f64 * 1024f64) as usize;
+    })
+  }
+
+  /// Limits how many processes of the job run at once, the first one included, beyond which
+  /// starting another one fails.
+  pub fn set_active_process_limit(&self, max_active_processes: u32) -> std::io::Result<()> {
+    self.update_extended_limits(|limits| {
+      limits.BasicLimitInformation.LimitFlags |= JOB_OBJECT_LIMIT_ACTIVE_PROCESS;
+      limits.BasicLimitInformation.ActiveProcessLimit = max_active_processes;
+    })
+  }
+
+  pub fn set_breakaway(&self, breakaway: JobBreakaway) -> std::io::Result<()> {
+    let breakaway_flag = match breakaway {
+      JobBreakaway::None => return Ok(()),
+      JobBreakaway::Allowed => JOB_OBJECT_LIMIT_BREAKAWAY_OK,
+      JobBreakaway::Silent => JOB_OBJECT_LIMIT_SILENT_BREAKAWAY_OK,
+    };
+
+    self.update_extended_limits(|limits| limits.BasicLimitInformation.LimitFlags |= breakaway_flag)
+  }
+
+  pub fn assign(&self, process_handle: HANDLE) -> std::io::Result<()> {
+    unsafe {
+      if AssignProcessToJobObject(self.handle, process_handle) == 0 {
+        return Err(std::io::Error::last_os_error());
+      }
+    }
+
+    Ok(())
+  }
+
+  /// Terminates every process of the job.
+  pub fn terminate(&self, exit_code: u32) -> std::io::Result<()> {
+    unsafe {
+      if TerminateJobObject(self.handle, exit_code) == 0 {
+        return Err(std::io::Error::last_os_error());
+      }
+    }
+
+    Ok(())
+  }
+
+  /// Changes the limits of the job, keeping those already set.
+  fn update_extended_limits(&self, update: impl FnOnce(&mut JOBOBJECT_EXTENDED_LIMIT_INFORMATION)) -> std::io::Result<()> {
+    let size = std::mem::size_of::<JOBOBJECT_EXTENDED_LIMIT_INFORMATION>() as DWORD;
+
+    unsafe {
+      let mut limits = std::mem::zeroed::<JOBOBJECT_EXTENDED_LIMIT_INFORMATION>();
+      let limits_ptr = &mut limits as *mut JOBOBJECT_EXTENDED_LIMIT_INFORMATION as LPVOID;
+
+      if QueryInformationJobObject(self.handle, JobObjectExtendedLimitInformation, limits_ptr, size, null_mut()) == 0 {
+        return Err(std::io::Error::last_os_error());
+      }
+
+      update(&mut limits);
+
+      if SetInformationJobObject(self.handle, JobObjectExtendedLimitInformation, limits_ptr, size) == 0 {
+        return Err(std::io::Error::last_os_error());
+      }
+    }
+
+    Ok(())
+  }
+}
+
+impl Drop for ProcessJob {
+  fn drop(&mut self) {
+    unsafe { CloseHandle(self.handle); }
+  }
+}
@@ -0,0 +1,464 @@
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::ffi::OsStr;
+use std::os::windows::ffi::OsStrExt;
+use std::ptr::null_mut;
+use winapi::shared::minwindef::{BOOL, DWORD, FALSE, LPVOID, TRUE};
+use winapi::shared::winerror::ERROR_SUCCESS;
+use winapi::um::accctrl::{EXPLICIT_ACCESS_W, GRANT_ACCESS, NO_INHERITANCE, SE_WINDOW_OBJECT, TRUSTEE_IS_SID, TRUSTEE_IS_USER};
+use winapi::um::aclapi::{GetSecurityInfo, SetEntriesInAclW, SetSecurityInfo};
+use winapi::um::handleapi::CloseHandle;
+use winapi::um::winuser::{
+  CloseDesktop, CloseWindowStation, GetProcessWindowStation, GetThreadDesktop, GetUserObjectInformationW, OpenDesktopW, OpenWindowStationW,
+  DESKTOP_CREATEMENU, DESKTOP_CREATEWINDOW, DESKTOP_ENUMERATE, DESKTOP_HOOKCONTROL, DESKTOP_READOBJECTS, DESKTOP_WRITEOBJECTS, UOI_NAME,
+  WINSTA_ACCESSGLOBALATOMS, WINSTA_ENUMDESKTOPS, WINSTA_ENUMERATE, WINSTA_READATTRIBUTES,
+};
+use winapi::um::userenv::{CreateEnvironmentBlock, DestroyEnvironmentBlock, UnloadUserProfile};
+use winapi::um::processthreadsapi::{CreateProcessAsUserW, GetCurrentProcess, GetCurrentThreadId, OpenProcessToken, PROCESS_INFORMATION};
+use winapi::um::securitybaseapi::{CreateRestrictedToken, CreateWellKnownSid, DuplicateTokenEx, GetLengthSid, GetTokenInformation, SetTokenInformation};
+use winapi::um::winbase::{STARTUPINFOEXW, LocalFree, LogonUserW, WTSGetActiveConsoleSessionId, LOGON32_LOGON_BATCH, LOGON32_PROVIDER_DEFAULT};
+use winapi::um::wtsapi32::WTSQueryUserToken;
+use winapi::um::winnt::{
+  HANDLE, LPCWSTR, LPWSTR, DISABLE_MAX_PRIVILEGE, SECURITY_MAX_SID_SIZE, SE_GROUP_INTEGRITY, SID_AND_ATTRIBUTES, SecurityImpersonation,
+  TOKEN_ADJUST_DEFAULT, TOKEN_ADJUST_SESSIONID, TOKEN_ALL_ACCESS, TOKEN_ASSIGN_PRIMARY, TOKEN_DUPLICATE, TOKEN_MANDATORY_LABEL,
+  TOKEN_QUERY, TokenIntegrityLevel, TokenPrimary, TokenSessionId, WELL_KNOWN_SID_TYPE, WinBuiltinAdministratorsSid, WinLowLabelSid,
+  WinMediumLabelSid, DACL_SECURITY_INFORMATION, PACL, PSECURITY_DESCRIPTOR, PSID, READ_CONTROL, TOKEN_USER, TokenUser, WRITE_DAC,
+};
+
+/// Access to the orchestrator's own token needed to start a process with a token derived from it.
+const PROCESS_TOKEN_ACCESS: DWORD = TOKEN_DUPLICATE | TOKEN_ASSIGN_PRIMARY | TOKEN_QUERY | TOKEN_ADJUST_DEFAULT | TOKEN_ADJUST_SESSIONID;
+
+/// Session ID `WTSGetActiveConsoleSessionId` returns while the console is being switched between
+/// sessions.
+const NO_CONSOLE_SESSION_ID: DWORD = 0xFFFFFFFF;
+
+/// Desktop of the user logged on to a session.
+pub const INTERACTIVE_DESKTOP: &str = "winsta0\\default";
+
+/// Access to the window station of the orchestrator a process started on it needs to load
+/// `user32.dll`.
+const WINDOW_STATION_ACCESS: DWORD = WINSTA_ACCESSGLOBALATOMS | WINSTA_ENUMDESKTOPS | WINSTA_ENUMERATE | WINSTA_READATTRIBUTES;
+
+/// Access to the desktop of the orchestrator a process started on it needs to create windows,
+/// hidden or not, and hooks.
+const DESKTOP_ACCESS: DWORD = DESKTOP_CREATEMENU | DESKTOP_CREATEWINDOW | DESKTOP_ENUMERATE | DESKTOP_HOOKCONTROL | DESKTOP_READOBJECTS | DESKTOP_WRITEOBJECTS;
+
+/// Don't show a dialog when the profile can't be loaded.
+const PI_NOUI: DWORD = 1;
+
+/// `PROFILEINFOW`, left out of winapi along with `LoadUserProfileW`.
+#[repr(C)]
+#[allow(non_snake_case, clippy::upper_case_acronyms)]
+struct PROFILEINFOW {
+  dwSize: DWORD,
+  dwFlags: DWORD,
+  lpUserName: LPWSTR,
+  lpProfilePath: LPWSTR,
+  lpDefaultPath: LPWSTR,
+  lpServerName: LPWSTR,
+  lpPolicyPath: LPWSTR,
+  hProfile: HANDLE,
+}
+
+#[link(name = "userenv")]
+extern "system" {
+  fn LoadUserProfileW(token_handle: HANDLE, profile_info: *mut PROFILEINFOW) -> BOOL;
+}
+
+/// Integrity level a process can be started at, below that of the orchestrator, so it can't write
+/// to what is labeled above it, e.g. most of the file system for `low`.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum IntegrityLevel {
+  Low,
+  Medium,
+}
+
+impl IntegrityLevel {
+  fn get_label_sid_type(&self) -> WELL_KNOWN_SID_TYPE {
+    match self {
+      IntegrityLevel::Low => WinLowLabelSid,
+      IntegrityLevel::Medium => WinMediumLabelSid,
+    }
+  }
+}
+
+/// Primary token a process is started with, other than the orchestrator's own, closed when
+/// dropped.
+pub struct ProcessToken {
+  handle: HANDLE,
+}
+
+impl ProcessToken {
+  /// The token the orchestrator runs with, to derive a token from.
+  pub fn open_current() -> std::io::Result<Self> {
+    let mut handle: HANDLE = null_mut();
+
+    unsafe {
+      if OpenProcessToken(GetCurrentProcess(), PROCESS_TOKEN_ACCESS, &mut handle) == 0 {
+        return Err(std::io::Error::last_os_error());
+      }
+    }
+
+    Ok(Self { handle })
+  }
+
+  /// Logs the account on as a batch job, which it needs the `Log on as a batch job` right for. A
+  /// user principal name, e.g. `worker@corp.example`, is logged on without a domain, and any other
+  /// account without a domain as a local account.
+  pub fn logon(username: &str, domain: Option<&str>, password: &str) -> std::io::Result<Self> {
+    let username_wide = to_wide(username);
+    let password_wide = to_wide(password);
+    let domain_wide = match domain {
+      Some(domain) => Some(to_wide(domain)),
+      None if username.contains('@') => None,
+      None => Some(to_wide(".")),
+    };
+
+    let mut handle: HANDLE = null_mut();
+
+    unsafe {
+      let is_logged_on = LogonUserW(
+        username_wide.as_ptr(),
+        domain_wide.as_ref().map(|x| x.as_ptr()).unwrap_or(null_mut()),
+        password_wide.as_ptr(),
+        LOGON32_LOGON_BATCH,
+        LOGON32_PROVIDER_DEFAULT,
+        &mut handle) != 0;
+
+      if !is_logged_on {
+        return Err(std::io::Error::last_os_error());
+      }
+    }
+
+    Ok(Self { handle })
+  }
+
+  /// The token of the user logged on to the session, which only LocalSystem can get.
+  pub fn query_session_user(session_id: DWORD) -> std::io::Result<Self> {
+    let mut handle: HANDLE = null_mut();
+
+    unsafe {
+      if WTSQueryUserToken(session_id, &mut handle) == 0 {
+        return Err(std::io::Error::last_os_error());
+      }
+    }
+
+    Ok(Self { handle })
+  }
+
+  /// Has the processes started with the token run in the session, which the orchestrator needs to
+  /// run as LocalSystem to do.
+  pub fn set_session(&self, session_id: DWORD) -> std::io::Result<()> {
+    let mut session_id = session_id;
+
+    unsafe {
+      if SetTokenInformation(self.handle, TokenSessionId, &mut session_id as *mut DWORD as LPVOID, std::mem::size_of::<DWORD>() as DWORD) == 0 {
+        return Err(std::io::Error::last_os_error());
+      }
+    }
+
+    Ok(())
+  }
+
+  /// The token with every privilege but `SeChangeNotifyPrivilege` removed, and the Administrators
+  /// group only able to deny access.
+  pub fn restrict(&self) -> std::io::Result<Self> {
+    let mut administrators_sid = create_well_known_sid(WinBuiltinAdministratorsSid)?;
+    let mut handle: HANDLE = null_mut();
+
+    unsafe {
+      let mut sids_to_disable = [SID_AND_ATTRIBUTES { Sid: administrators_sid.as_mut_ptr() as _, Attributes: 0 }];
+
+      if CreateRestrictedToken(
+        self.handle,
+        DISABLE_MAX_PRIVILEGE,
+        sids_to_disable.len() as DWORD,
+        sids_to_disable.as_mut_ptr(),
+        0,
+        null_mut(),
+        0,
+        null_mut(),
+        &mut handle) == 0 {
+        return Err(std::io::Error::last_os_error());
+      }
+    }
+
+    Ok(Self { handle })
+  }
+
+  /// A copy of the token labeled with the integrity level.
+  pub fn with_integrity_level(&self, integrity_level: IntegrityLevel) -> std::io::Result<Self> {
+    let mut label_sid = create_well_known_sid(integrity_level.get_label_sid_type())?;
+
+    // Closed if it can't be labeled.
+    let token = self.duplicate()?;
+
+    unsafe {
+
+      let mut mandatory_label = TOKEN_MANDATORY_LABEL {
+        Label: SID_AND_ATTRIBUTES { Sid: label_sid.as_mut_ptr() as _, Attributes: SE_GROUP_INTEGRITY },
+      };
+
+      if SetTokenInformation(
+        token.handle,
+        TokenIntegrityLevel,
+        &mut mandatory_label as *mut TOKEN_MANDATORY_LABEL as LPVOID,
+        (std::mem::size_of::<TOKEN_MANDATORY_LABEL>() + label_sid.len()) as DWORD) == 0 {
+        return Err(std::io::Error::last_os_error());
+      }
+
+      Ok(token)
+    }
+  }
+
+  /// Environment variables of the account, as Windows sets them for its logon.
+  pub fn get_environment_variables(&self) -> std::io::Result<HashMap<String, String>> {
+    unsafe {
+      let mut environment_block: LPVOID = null_mut();
+      if CreateEnvironmentBlock(&mut environment_block, self.handle, FALSE) == 0 {
+        return Err(std::io::Error::last_os_error());
+      }
+
+      let environment_variables = read_environment_block(environment_block as *const u16);
+      DestroyEnvironmentBlock(environment_block);
+
+      Ok(environment_variables)
+    }
+  }
+
+  /// Lets the account of the token use the window station and desktop of the orchestrator, which a
+  /// process started without a desktop of its own runs on. Without access to them, as to the
+  /// desktop of the service, a process linking `user32.dll` fails to start with
+  /// `STATUS_DLL_INIT_FAILED`. Access is granted to the account rather than to its logon, so it is
+  /// granted once, however often the process is started.
+  pub fn grant_desktop_access(&self) -> std::io::Result<()> {
+    let mut user_sid = self.get_user_sid()?;
+    let user_sid_ptr = user_sid.as_mut_ptr() as PSID;
+
+    unsafe {
+      let window_station_name = get_user_object_name(GetProcessWindowStation() as HANDLE)?;
+      let window_station = OpenWindowStationW(window_station_name.as_ptr(), FALSE, READ_CONTROL | WRITE_DAC);
+      if window_station.is_null() {
+        return Err(std::io::Error::last_os_error());
+      }
+
+      let grant_result = grant_window_object_access(window_station as HANDLE, user_sid_ptr, WINDOW_STATION_ACCESS);
+      CloseWindowStation(window_station);
+      grant_result?;
+
+      let desktop_name = get_user_object_name(GetThreadDesktop(GetCurrentThreadId()) as HANDLE)?;
+      let desktop = OpenDesktopW(desktop_name.as_ptr(), 0, FALSE, READ_CONTROL | WRITE_DAC);
+      if desktop.is_null() {
+        return Err(std::io::Error::last_os_error());
+      }
+
+      let grant_result = grant_window_object_access(desktop as HANDLE, user_sid_ptr, DESKTOP_ACCESS);
+      CloseDesktop(desktop);
+      grant_result
+    }
+  }
+
+  /// SID of the account of the token.
+  fn get_user_sid(&self) -> std::io::Result<Vec<u8>> {
+    unsafe {
+      let mut token_user_size: DWORD = 0;
+      GetTokenInformation(self.handle, TokenUser, null_mut(), 0, &mut token_user_size);
+
+      // Of pointers, so the `TOKEN_USER` at its start is aligned.
+      let mut token_user_buffer = vec![0usize; (token_user_size as usize).div_ceil(std::mem::size_of::<usize>())];
+      if GetTokenInformation(self.handle, TokenUser, token_user_buffer.as_mut_ptr() as LPVOID, token_user_size, &mut token_user_size) == 0 {
+        return Err(std::io::Error::last_os_error());
+      }
+
+      let user_sid = (*(token_user_buffer.as_ptr() as *const TOKEN_USER)).User.Sid;
+      Ok(std::slice::from_raw_parts(user_sid as *const u8, GetLengthSid(user_sid) as usize).to_vec())
+    }
+  }
+
+  fn duplicate(&self) -> std::io::Result<Self> {
+    let mut handle: HANDLE = null_mut();
+
+    unsafe {
+      if DuplicateTokenEx(self.handle, TOKEN_ALL_ACCESS, null_mut(), SecurityImpersonation, TokenPrimary, &mut handle) == 0 {
+        return Err(std::io::Error::last_os_error());
+      }
+    }
+
+    Ok(Self { handle })
+  }
+
+  /// Creates the process with the token, handing it the standard handles and the desktop of the
+  /// startup information. The orchestrator needs to run as LocalSystem, as the
+  /// service does by default, to be allowed to, unless the token is a restricted version of its
+  /// own.
+  pub unsafe fn create_process(
+    &self,
+    command_line_wide: &mut [u16],
+    environment: LPVOID,
+    working_directory_wide: LPCWSTR,
+    creation_flags: DWORD,
+    startup_information: &mut STARTUPINFOEXW,
+    process_information: &mut PROCESS_INFORMATION) -> bool {
+    CreateProcessAsUserW(
+      self.handle,
+      null_mut(),
+      command_line_wide.as_mut_ptr(),
+      null_mut(),
+      null_mut(),
+      TRUE,
+      creation_flags,
+      environment,
+      working_directory_wide,
+      &mut startup_information.StartupInfo,
+      process_information) != 0
+  }
+}
+
+impl Drop for ProcessToken {
+  fn drop(&mut self) {
+    unsafe { CloseHandle(self.handle); }
+  }
+}
+
+/// Session of the user logged on to the console, locally rather than over Remote Desktop.
+pub fn get_active_console_session_id() -> Option<DWORD> {
+  let session_id = unsafe { WTSGetActiveConsoleSessionId() };
+  Some(session_id).filter(|x| *x != NO_CONSOLE_SESSION_ID)
+}
+
+/// Profile of an account loaded for a process running as it, so it has its registry hive under
+/// `HKEY_CURRENT_USER` and its own environment, e.g. `%APPDATA%`. It is unloaded when dropped,
+/// once the process has exited.
+pub struct UserProfile {
+  token: ProcessToken,
+  profile_handle: HANDLE,
+  /// Environment variables of the account, as Windows sets them for its logon.
+  pub environment_variables: HashMap<String, String>,
+}
+
+impl UserProfile {
+  pub fn load(token: &ProcessToken, username: &str) -> std::io::Result<Self> {
+    let token = token.duplicate()?;
+    let mut username_wide = to_wide(username);
+
+    unsafe {
+      let mut profile_info = std::mem::zeroed::<PROFILEINFOW>();
+      profile_info.dwSize = std::mem::size_of::<PROFILEINFOW>() as DWORD;
+      profile_info.dwFlags = PI_NOUI;
+      profile_info.lpUserName = username_wide.as_mut_ptr();
+
+      if LoadUserProfileW(token.handle, &mut profile_info) == 0 {
+        return Err(std::io::Error::last_os_error());
+      }
+
+      let mut user_profile = Self {
+        token,
+        profile_handle: profile_info.hProfile,
+        environment_variables: HashMap::new(),
+      };
+
+      user_profile.environment_variables = user_profile.token.get_environment_variables()?;
+
+      Ok(user_profile)
+    }
+  }
+}
+
+impl Drop for UserProfile {
+  fn drop(&mut self) {
+    unsafe { UnloadUserProfile(self.token.handle, self.profile_handle); }
+  }
+}
+
+/// The `NAME=value` pairs of an environment block, ended by an empty one. Those starting with `=`,
+/// e.g. the current directory of each drive, are left out.
+unsafe fn read_environment_block(environment_block: *const u16) -> HashMap<String, String> {
+  let mut environment_variables = HashMap::new();
+  let mut pair_start = environment_block;
+
+  loop {
+    let mut pair_length = 0;
+    while *pair_start.add(pair_length) != 0 {
+      pair_length += 1;
+    }
+
+    if pair_length == 0 {
+      break;
+    }
+
+    let pair = String::from_utf16_lossy(std::slice::from_raw_parts(pair_start, pair_length));
+    if let Some((name, value)) = pair.split_once('=').filter(|(name, _)| !name.is_empty()) {
+      environment_variables.insert(name.to_string(), value.to_string());
+    }
+
+    pair_start = pair_start.add(pair_length + 1);
+  }
+
+  environment_variables
+}
+
+/// Name of a window station or desktop, ended by a NUL.
+unsafe fn get_user_object_name(object: HANDLE) -> std::io::Result<Vec<u16>> {
+  let mut name_size: DWORD = 0;
+  GetUserObjectInformationW(object, UOI_NAME as i32, null_mut(), 0, &mut name_size);
+
+  let mut name = vec![0u16; (name_size as usize).div_ceil(2)];
+  if GetUserObjectInformationW(object, UOI_NAME as i32, name.as_mut_ptr() as LPVOID, name_size, &mut name_size) == 0 {
+    return Err(std::io::Error::last_os_error());
+  }
+
+  Ok(name)
+}
+
+/// Adds the access to what the SID is allowed on the window station or desktop, merged into the
+/// entry it already has, if any.
+unsafe fn grant_window_object_access(object: HANDLE, sid: PSID, access: DWORD) -> std::io::Result<()> {
+  let mut dacl: PACL = null_mut();
+  let mut security_descriptor: PSECURITY_DESCRIPTOR = null_mut();
+
+  let get_result = GetSecurityInfo(object, SE_WINDOW_OBJECT, DACL_SECURITY_INFORMATION, null_mut(), null_mut(), &mut dacl, null_mut(), &mut security_descriptor);
+  if get_result != ERROR_SUCCESS {
+    return Err(std::io::Error::from_raw_os_error(get_result as i32));
+  }
+
+  let mut explicit_access = std::mem::zeroed::<EXPLICIT_ACCESS_W>();
+  explicit_access.grfAccessPermissions = access;
+  explicit_access.grfAccessMode = GRANT_ACCESS;
+  explicit_access.grfInheritance = NO_INHERITANCE;
+  explicit_access.Trustee.TrusteeForm = TRUSTEE_IS_SID;
+  explicit_access.Trustee.TrusteeType = TRUSTEE_IS_USER;
+  explicit_access.Trustee.ptstrName = sid as LPWSTR;
+
+  let mut new_dacl: PACL = null_mut();
+  let set_entries_result = SetEntriesInAclW(1, &mut explicit_access, dacl, &mut new_dacl);
+  LocalFree(security_descriptor);
+  if set_entries_result != ERROR_SUCCESS {
+    return Err(std::io::Error::from_raw_os_error(set_entries_result as i32));
+  }
+
+  let set_result = SetSecurityInfo(object, SE_WINDOW_OBJECT, DACL_SECURITY_INFORMATION, null_mut(), null_mut(), new_dacl, null_mut());
+  LocalFree(new_dacl as LPVOID);
+  if set_result != ERROR_SUCCESS {
+    return Err(std::io::Error::from_raw_os_error(set_result as i32));
+  }
+
+  Ok(())
+}
+
+pub fn create_well_known_sid(sid_type: WELL_KNOWN_SID_TYPE) -> std::io::Result<[u8; SECURITY_MAX_SID_SIZE]> {
+  let mut sid = [0u8; SECURITY_MAX_SID_SIZE];
+  let mut sid_size = SECURITY_MAX_SID_SIZE as DWORD;
+
+  unsafe {
+    if CreateWellKnownSid(sid_type, null_mut(), sid.as_mut_ptr() as _, &mut sid_size) == 0 {
+      return Err(std::io::Error::last_os_error());
+    }
+  }
+
+  Ok(sid)
+}
+
+fn to_wide(value: &str) -> Vec<u16> {
+  OsStr::new(value).encode_wide().chain(std::iter::once(0)).collect()
+}
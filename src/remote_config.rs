@@ -0,0 +1,48 @@
+use crate::http_client::create_http_agent;
+
+use std::error::Error;
+use std::time::Duration;
+use log::info;
+
+/// Fetches the config bundle from an HTTP(S) URL. The bundle is YAML in the same form as a config
+/// file: a `processes:` list, or several documents separated by `---`.
+pub struct RemoteConfigSource {
+  url: String,
+  etag: Option<String>,
+  agent: ureq::Agent,
+}
+
+impl RemoteConfigSource {
+  pub fn new(url: String) -> Result<Self, Box<dyn Error>> {
+    let agent = create_http_agent(Duration::from_secs(30))?;
+
+    Ok(Self {
+      url,
+      etag: None,
+      agent,
+    })
+  }
+
+  /// Returns the bundle when it changed since the last fetch, or `None` when the server answered
+  /// `304 Not Modified` to the ETag of the previous response.
+  pub fn fetch(&mut self) -> Result<Option<String>, Box<dyn Error>> {
+    let mut request = self.agent.get(self.url.as_str());
+
+    if let Some(etag) = &self.etag {
+      request = request.set("If-None-Match", etag.as_str());
+    }
+
+    let response = request.call()?;
+
+    if response.status() == 304 {
+      return Ok(None);
+    }
+
+    self.etag = response.header("ETag").map(|x| x.to_string());
+
+    let contents = response.into_string()?;
+    info!("Remote config [{}]: Fetched", &self.url);
+
+    Ok(Some(contents))
+  }
+}
@@ -0,0 +1,185 @@
+use crate::errors::OrchestratorError;
+
+use std::error::Error;
+use std::ffi::OsStr;
+use std::os::windows::ffi::OsStrExt;
+use std::ptr::null_mut;
+use winapi::shared::winerror::ERROR_NOT_FOUND;
+use winapi::um::wincred::{CredReadW, CredFree, CRED_TYPE_GENERIC, PCREDENTIALW};
+use winapi::um::dpapi::{CryptProtectData, CryptUnprotectData, CRYPTPROTECT_UI_FORBIDDEN, CRYPTPROTECT_LOCAL_MACHINE};
+use winapi::um::wincrypt::DATA_BLOB;
+use winapi::um::winbase::LocalFree;
+
+/// Prefix of config values holding a DPAPI encrypted, base64 encoded secret. It can be written as
+/// a YAML tag (`!encrypted AQAAANCM...`) or as a plain string (`"!encrypted AQAAANCM..."`).
+pub const ENCRYPTED_PREFIX: &str = "!encrypted ";
+
+/// Prefix of config values looked up from a generic credential in the Windows Credential Manager
+/// (`cred://my-target`).
+pub const CREDENTIAL_PREFIX: &str = "cred://";
+
+/// Returns the plain text of a config value, decrypting it when it holds an encrypted secret, or
+/// reading it from the Credential Manager when it references a credential.
+pub fn resolve_secret(value: &str) -> Result<String, Box<dyn Error>> {
+  if let Some(encrypted_value) = value.strip_prefix(ENCRYPTED_PREFIX) {
+    return decrypt_value(encrypted_value.trim());
+  }
+
+  if let Some(credential_target) = value.strip_prefix(CREDENTIAL_PREFIX) {
+    return read_credential(credential_target);
+  }
+
+  Ok(value.to_string())
+}
+
+/// Reads the secret of a generic credential, stored as UTF-16 by Credential Manager and `cmdkey`,
+/// or as UTF-8 by tools calling `CredWrite` themselves, e.g. from PowerShell or Go.
+pub fn read_credential(credential_target: &str) -> Result<String, Box<dyn Error>> {
+  let credential_target_wide = OsStr::new(credential_target)
+    .encode_wide()
+    .chain(std::iter::once(0))
+    .collect::<Vec<u16>>();
+
+  unsafe {
+    let mut credential: PCREDENTIALW = null_mut();
+
+    if CredReadW(credential_target_wide.as_ptr(), CRED_TYPE_GENERIC, 0, &mut credential) == 0 {
+      let read_error = std::io::Error::last_os_error();
+      if read_error.raw_os_error() == Some(ERROR_NOT_FOUND as i32) {
+        return Err(Box::new(OrchestratorError::CredentialNotFound(credential_target.to_string())));
+      }
+
+      return Err(Box::new(read_error));
+    }
+
+    let blob = std::slice::from_raw_parts(
+      (*credential).CredentialBlob,
+      (*credential).CredentialBlobSize as usize).to_vec();
+
+    CredFree(credential as *mut _);
+
+    decode_credential_blob(blob)
+  }
+}
+
+/// The secret of a credential blob. UTF-8 text has no NUL bytes, while ASCII text in UTF-16 has
+/// one in every character, so the blob is only read as UTF-16 when it is not NUL free UTF-8 and
+/// decodes to UTF-16 without interior NULs.
+fn decode_credential_blob(blob: Vec<u8>) -> Result<String, Box<dyn Error>> {
+  if !blob.contains(&0) {
+    if let Ok(secret) = std::str::from_utf8(&blob) {
+      return Ok(secret.to_string());
+    }
+  }
+
+  if blob.len().is_multiple_of(2) {
+    let mut blob_wide = blob.chunks(2).map(|x| u16::from_le_bytes([x[0], x[1]])).collect::<Vec<u16>>();
+    if blob_wide.last() == Some(&0) {
+      blob_wide.pop();
+    }
+
+    if !blob_wide.contains(&0) {
+      if let Ok(secret) = String::from_utf16(&blob_wide) {
+        return Ok(secret);
+      }
+    }
+  }
+
+  Ok(String::from_utf8(blob)?)
+}
+
+/// Encrypts a value with DPAPI. With `machine_scope` any account on this machine can decrypt it,
+/// otherwise only the current account can (run it as the service account).
+pub fn encrypt_value(value: &str, machine_scope: bool) -> Result<String, Box<dyn Error>> {
+  let mut flags = CRYPTPROTECT_UI_FORBIDDEN;
+  if machine_scope {
+    flags |= CRYPTPROTECT_LOCAL_MACHINE;
+  }
+
+  let mut input = value.as_bytes().to_vec();
+
+  unsafe {
+    let mut input_blob = DATA_BLOB { cbData: input.len() as u32, pbData: input.as_mut_ptr() };
+    let mut output_blob = std::mem::zeroed::<DATA_BLOB>();
+
+    if CryptProtectData(
+      &mut input_blob,
+      null_mut(),
+      null_mut(),
+      null_mut(),
+      null_mut(),
+      flags,
+      &mut output_blob) == 0 {
+      return Err(Box::new(std::io::Error::last_os_error()));
+    }
+
+    let encrypted = take_data_blob(output_blob);
+
+    Ok(format!("{}{}", ENCRYPTED_PREFIX, base64::encode(encrypted)))
+  }
+}
+
+pub fn decrypt_value(encrypted_value: &str) -> Result<String, Box<dyn Error>> {
+  let mut input = base64::decode(encrypted_value)?;
+
+  unsafe {
+    let mut input_blob = DATA_BLOB { cbData: input.len() as u32, pbData: input.as_mut_ptr() };
+    let mut output_blob = std::mem::zeroed::<DATA_BLOB>();
+
+    if CryptUnprotectData(
+      &mut input_blob,
+      null_mut(),
+      null_mut(),
+      null_mut(),
+      null_mut(),
+      CRYPTPROTECT_UI_FORBIDDEN,
+      &mut output_blob) == 0 {
+      return Err(Box::new(std::io::Error::last_os_error()));
+    }
+
+    let decrypted = take_data_blob(output_blob);
+
+    Ok(String::from_utf8(decrypted)?)
+  }
+}
+
+/// Copies the contents of a blob allocated by DPAPI, and frees it.
+unsafe fn take_data_blob(data_blob: DATA_BLOB) -> Vec<u8> {
+  if data_blob.pbData.is_null() {
+    return Vec::new();
+  }
+
+  let contents = std::slice::from_raw_parts(data_blob.pbData, data_blob.cbData as usize).to_vec();
+  LocalFree(data_blob.pbData as *mut _);
+
+  contents
+}
+
+#[cfg(test)]
+mod tests {
+  use super::decode_credential_blob;
+
+  fn encode_utf16(value: &str) -> Vec<u8> {
+    value.encode_utf16().flat_map(|x| x.to_le_bytes()).collect()
+  }
+
+  #[test]
+  fn decode_credential_blob_reads_utf16() {
+    assert_eq!(decode_credential_blob(encode_utf16("abcd")).unwrap(), "abcd");
+    assert_eq!(decode_credential_blob(encode_utf16("pässwörd")).unwrap(), "pässwörd");
+  }
+
+  #[test]
+  fn decode_credential_blob_reads_utf8_of_any_length() {
+    assert_eq!(decode_credential_blob(b"abcd".to_vec()).unwrap(), "abcd");
+    assert_eq!(decode_credential_blob(b"abc".to_vec()).unwrap(), "abc");
+    assert_eq!(decode_credential_blob("pässwörd".as_bytes().to_vec()).unwrap(), "pässwörd");
+  }
+
+  #[test]
+  fn decode_credential_blob_ignores_a_trailing_utf16_nul() {
+    let mut blob = encode_utf16("abcd");
+    blob.extend_from_slice(&[0, 0]);
+    assert_eq!(decode_credential_blob(blob).unwrap(), "abcd");
+  }
+}
@@ -0,0 +1,232 @@
+use crate::errors::OrchestratorError;
+
+use serde::{Deserialize, Serialize};
+
+/// Characters, besides letters and digits, cmd leaves alone in an argument that is not quoted.
+const CMD_UNQUOTED_CHARACTERS: &str = r"#$*+-./:?@\_";
+
+/// Shell, or interpreter, a process runs a command, or a script, through.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum Shell {
+  Cmd,
+  /// Windows PowerShell.
+  Powershell,
+  /// PowerShell 7, found on the `PATH`.
+  Pwsh,
+  /// Python, found on the `PATH`.
+  Python,
+}
+
+impl Shell {
+  /// The shell running the script, by its extension, e.g. `cmd` for `backup.bat`.
+  pub fn detect(executable: &str) -> Option<Self> {
+    [Shell::Cmd, Shell::Powershell, Shell::Python].iter().copied().find(|x| x.is_script(executable))
+  }
+
+  pub fn get_interpreter(&self) -> &'static str {
+    match self {
+      Shell::Cmd => "cmd.exe",
+      Shell::Powershell => "powershell.exe",
+      Shell::Pwsh => "pwsh.exe",
+      Shell::Python => "python.exe",
+    }
+  }
+
+  /// Whether the executable is a script of the shell, e.g. `deploy.ps1`, rather than a command.
+  pub fn is_script(&self, executable: &str) -> bool {
+    let extensions: &[&str] = match self {
+      Shell::Cmd => &[".bat", ".cmd"],
+      Shell::Powershell | Shell::Pwsh => &[".ps1"],
+      Shell::Python => &[".py"],
+    };
+
+    let executable = executable.trim().to_lowercase();
+    extensions.iter().any(|x| executable.ends_with(x))
+  }
+
+  /// The command line running the script, or the command, through the shell, followed by the
+  /// arguments, already quoted.
+  pub fn get_command_line(&self, executable: &str, arguments: &str) -> String {
+    let is_script = self.is_script(executable);
+
+    match self {
+      // With `/s`, cmd removes the outer quotes and runs what is between them as it is.
+      Shell::Cmd if is_script => format!("cmd.exe /d /s /c \"\"{}\"{}\"", executable, arguments),
+      Shell::Cmd => format!("cmd.exe /d /s /c \"{}{}\"", executable, arguments),
+      Shell::Powershell | Shell::Pwsh if is_script => format!(
+        "{} -NoLogo -NoProfile -NonInteractive -ExecutionPolicy Bypass -File \"{}\"{}",
+        self.get_interpreter(),
+        executable,
+        arguments),
+      Shell::Powershell | Shell::Pwsh => format!(
+        "{} -NoLogo -NoProfile -NonInteractive -Command {}{}",
+        self.get_interpreter(),
+        quote_argument(executable),
+        arguments),
+      // Unbuffered, so the output is captured as it is written.
+      Shell::Python if is_script => format!("python.exe -u \"{}\"{}", executable, arguments),
+      Shell::Python => format!("python.exe -u -c {}{}", quote_argument(executable), arguments),
+    }
+  }
+}
+
+/// The argument quoted as `CommandLineToArgvW` and the C runtime parse it back: left as it is
+/// unless it is empty or holds spaces or quotes, otherwise in quotes, with its quotes, and the
+/// backslashes before them or the closing quote, escaped with backslashes.
+pub fn quote_argument(argument: &str) -> String {
+  if !argument.is_empty() && !argument.contains(&[' ', '\t', '\n', '\x0b', '"'][..]) {
+    return argument.to_string();
+  }
+
+  let mut quoted_argument = String::from("\"");
+  let mut backslash_count = 0usize;
+
+  for character in argument.chars() {
+    match character {
+      '\\' => {
+        backslash_count += 1;
+        continue;
+      }
+      '"' => quoted_argument.push_str(&"\\".repeat(backslash_count * 2 + 1)),
+      _ => quoted_argument.push_str(&"\\".repeat(backslash_count)),
+    }
+
+    quoted_argument.push(character);
+    backslash_count = 0;
+  }
+
+  quoted_argument.push_str(&"\\".repeat(backslash_count * 2));
+  quoted_argument.push('"');
+  quoted_argument
+}
+
+/// The argument quoted for cmd, which parses the command line of a batch file before the script
+/// gets it, as the standard library does since Rust 1.77: in quotes, where `&`, `|`, `<`, `>` and
+/// `^` lose their meaning, unless it only holds letters, digits and a few safe symbols, with its
+/// quotes doubled and `%` broken up so cmd does not expand variables in it. A line break would end
+/// the command, so arguments holding one are refused.
+pub fn quote_cmd_argument(argument: &str) -> Result<String, OrchestratorError> {
+  if argument.contains(&['\r', '\n', '\0'][..]) {
+    return Err(OrchestratorError::CmdArgumentInvalid(argument.to_string()));
+  }
+
+  // A trailing backslash is quoted too, so it can't escape the quote of a script using `"%~1"`.
+  let is_quoted = argument.is_empty()
+    || argument.ends_with('\\')
+    || argument.chars().any(|x| x.is_control() || (x.is_ascii() && !x.is_ascii_alphanumeric() && !CMD_UNQUOTED_CHARACTERS.contains(x)));
+
+  let mut quoted_argument = String::new();
+  if is_quoted {
+    quoted_argument.push('"');
+  }
+
+  let mut backslash_count = 0usize;
+
+  for character in argument.chars() {
+    match character {
+      '\\' => {
+        backslash_count += 1;
+        quoted_argument.push(character);
+        continue;
+      }
+      // Backslashes before a quote are doubled, and the quote escaped by another.
+      '"' => {
+        quoted_argument.push_str(&"\\".repeat(backslash_count));
+        quoted_argument.push('"');
+      }
+      // Expands to nothing, keeping cmd from reading `%NAME%` as a variable.
+      '%' => quoted_argument.push_str("%%cd:~,"),
+      _ => {}
+    }
+
+    quoted_argument.push(character);
+    backslash_count = 0;
+  }
+
+  if is_quoted {
+    quoted_argument.push_str(&"\\".repeat(backslash_count));
+    quoted_argument.push('"');
+  }
+
+  Ok(quoted_argument)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::{quote_argument, quote_cmd_argument};
+
+  #[test]
+  fn quote_argument_leaves_plain_arguments() {
+    assert_eq!(quote_argument("--port=8080"), "--port=8080");
+    assert_eq!(quote_argument(r"C:\apps\worker\"), r"C:\apps\worker\");
+  }
+
+  #[test]
+  fn quote_argument_quotes_empty_arguments() {
+    assert_eq!(quote_argument(""), r#""""#);
+  }
+
+  #[test]
+  fn quote_argument_quotes_arguments_with_spaces() {
+    assert_eq!(quote_argument("hello world"), r#""hello world""#);
+    assert_eq!(quote_argument("tab\there"), "\"tab\there\"");
+  }
+
+  #[test]
+  fn quote_argument_escapes_embedded_quotes() {
+    assert_eq!(quote_argument(r#"say "hi""#), r#""say \"hi\"""#);
+    assert_eq!(quote_argument(r#"""#), r#""\"""#);
+  }
+
+  #[test]
+  fn quote_argument_doubles_trailing_backslashes() {
+    assert_eq!(quote_argument(r"C:\Program Files\"), r#""C:\Program Files\\""#);
+    assert_eq!(quote_argument(r"C:\Program Files\\"), r#""C:\Program Files\\\\""#);
+  }
+
+  #[test]
+  fn quote_argument_escapes_backslashes_before_quotes() {
+    assert_eq!(quote_argument(r#"a\"b"#), r#""a\\\"b""#);
+    assert_eq!(quote_argument(r#"a\\"b c"#), r#""a\\\\\"b c""#);
+  }
+
+  #[test]
+  fn quote_argument_leaves_other_backslashes() {
+    assert_eq!(quote_argument(r"C:\Program Files\app"), r#""C:\Program Files\app""#);
+  }
+
+  #[test]
+  fn quote_cmd_argument_leaves_plain_arguments() {
+    assert_eq!(quote_cmd_argument(r"--out=C:\logs\app.log").unwrap(), r#""--out=C:\logs\app.log""#);
+    assert_eq!(quote_cmd_argument(r"C:\logs\app.log").unwrap(), r"C:\logs\app.log");
+  }
+
+  #[test]
+  fn quote_cmd_argument_quotes_metacharacters() {
+    assert_eq!(quote_cmd_argument("a&calc").unwrap(), r#""a&calc""#);
+    assert_eq!(quote_cmd_argument("a|b<c>d^e").unwrap(), r#""a|b<c>d^e""#);
+    assert_eq!(quote_cmd_argument("").unwrap(), r#""""#);
+  }
+
+  #[test]
+  fn quote_cmd_argument_doubles_quotes() {
+    assert_eq!(quote_cmd_argument(r#"" & calc & ""#).unwrap(), r#"""" & calc & """"#);
+    assert_eq!(quote_cmd_argument(r#"a\"b"#).unwrap(), r#""a\\""b""#);
+  }
+
+  #[test]
+  fn quote_cmd_argument_breaks_up_variables() {
+    assert_eq!(quote_cmd_argument("%PATH%").unwrap(), r#""%%cd:~,%PATH%%cd:~,%""#);
+  }
+
+  #[test]
+  fn quote_cmd_argument_doubles_trailing_backslashes() {
+    assert_eq!(quote_cmd_argument(r"C:\logs\").unwrap(), r#""C:\logs\\""#);
+  }
+
+  #[test]
+  fn quote_cmd_argument_refuses_line_breaks() {
+    assert!(quote_cmd_argument("a\r\ncalc").is_err());
+  }
+}
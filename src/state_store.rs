@@ -0,0 +1,54 @@
+use serde::{Serialize, Deserialize};
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+/// Runtime state of the orchestrator that survives restarts.
+#[derive(Debug, Default, Serialize, Deserialize, Clone, PartialEq)]
+pub struct OrchestratorState {
+  /// Processes enabled (`true`) or disabled (`false`) at runtime, overriding their config's
+  /// `enabled` field. Keyed by process name.
+  #[serde(default)]
+  pub enabled_overrides: BTreeMap<String, bool>,
+}
+
+impl OrchestratorState {
+  pub fn is_enabled(&self, process_name: &str, config_enabled: Option<bool>) -> bool {
+    match self.enabled_overrides.get(process_name) {
+      Some(enabled) => *enabled,
+      None => config_enabled.unwrap_or(true),
+    }
+  }
+}
+
+/// The state is kept next to the executable, named after it (e.g. `process-orchestrator.state.json`),
+/// or after the instance when it has a service name.
+pub fn get_state_file_path(service_name: Option<&str>) -> PathBuf {
+  let executable_path = std::env::current_exe().unwrap();
+  let executable_stem = executable_path.file_stem().unwrap().to_str().unwrap();
+  let state_file_stem = service_name.unwrap_or(executable_stem);
+  executable_path.with_file_name(format!("{}.state.json", state_file_stem))
+}
+
+pub fn load_state(state_file_path: &Path) -> Result<OrchestratorState, Box<dyn Error>> {
+  if !state_file_path.exists() {
+    return Ok(OrchestratorState::default());
+  }
+
+  let state_file_contents = std::fs::read_to_string(state_file_path)?;
+  let state = serde_json::from_str::<OrchestratorState>(state_file_contents.as_str())?;
+  Ok(state)
+}
+
+/// Writes the state to a temporary file first, so a crash can't leave a half written state.
+pub fn save_state(state_file_path: &Path, state: &OrchestratorState) -> Result<(), Box<dyn Error>> {
+  let temporary_file_path = state_file_path.with_extension("json.tmp");
+  std::fs::write(&temporary_file_path, serde_json::to_string_pretty(state)?)?;
+  std::fs::rename(&temporary_file_path, state_file_path)?;
+  Ok(())
+}
+
+pub fn get_state_file_modified(state_file_path: &Path) -> Option<SystemTime> {
+  std::fs::metadata(state_file_path).and_then(|x| x.modified()).ok()
+}
@@ -1,58 +1,163 @@
 use crate::event_pump::{Event, VoidResult};
 use crate::errors::OrchestratorError;
+use crate::config::{load_env_file, find_executable};
+use crate::secrets::resolve_secret;
+use crate::app_container::{AppContainer, AppContainerCapability};
+use crate::process_job::{JobBreakaway, ProcessJob};
+use crate::shell::{Shell, quote_argument, quote_cmd_argument};
+use crate::file_watcher::FileWatcher;
+use crate::process_attributes::{
+  CHILD_PROCESS_RESTRICTED, Mitigation, PROC_THREAD_ATTRIBUTE_CHILD_PROCESS_POLICY, PROC_THREAD_ATTRIBUTE_MITIGATION_POLICY,
+  PROC_THREAD_ATTRIBUTE_GROUP_AFFINITY, PROC_THREAD_ATTRIBUTE_PREFERRED_NODE, PROC_THREAD_ATTRIBUTE_SECURITY_CAPABILITIES,
+  ProcessAttributes, get_numa_node_affinity, get_processor_group_affinity,
+};
+use crate::process_token::{INTERACTIVE_DESKTOP, IntegrityLevel, ProcessToken, UserProfile, get_active_console_session_id};
+use crate::log_shipper::ProcessLogShipper;
+use crate::event_log::EventLogLevel;
+use crate::log_capture::{DEFAULT_LOG_MAX_FILES, LogCaptureOptions, LogPipe, OutputBuffer, OutputEvents, LogRotation, LogRotationPeriod, OutputEncoding, RotatingLogFile};
 
 use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::os::windows::io::{FromRawHandle, IntoRawHandle, RawHandle};
+use glob::{Pattern, PatternError};
+use std::error::Error;
+use std::path::{Path, PathBuf};
 use std::fs::File;
 use std::pin::Pin;
 use std::process::{Child, Command, Stdio};
+use std::sync::Arc;
+use std::sync::atomic::AtomicBool;
 use std::sync::mpsc::Sender;
 use log::{info, error};
 use serde::{Serialize, Deserialize};
 use nanoid::nanoid;
-use chrono::{Utc, TimeZone};
-use winapi::um::processthreadsapi::{TerminateProcess, OpenProcess, GetExitCodeProcess, GetProcessTimes, CreateProcessW, CreateProcessA, PROCESS_INFORMATION, STARTUPINFOA, GetCurrentProcess, GetCurrentProcessId};
-use winapi::shared::ntdef::{HANDLE};
-use winapi::um::winnt::{WT_EXECUTEONLYONCE, PVOID, BOOLEAN, SYNCHRONIZE, PROCESS_TERMINATE, PROCESS_VM_READ, PROCESS_QUERY_INFORMATION, LPCSTR, DUPLICATE_SAME_ACCESS, PROCESS_DUP_HANDLE, FILE_APPEND_DATA, FILE_SHARE_WRITE, FILE_SHARE_READ, FILE_ATTRIBUTE_NORMAL, GENERIC_WRITE};
-use winapi::um::winbase::{RegisterWaitForSingleObject, INFINITE, UnregisterWait, DETACHED_PROCESS, CREATE_NEW_CONSOLE, FORMAT_MESSAGE_FROM_HMODULE, FORMAT_MESSAGE_IGNORE_INSERTS, CREATE_NO_WINDOW, STD_OUTPUT_HANDLE, STD_ERROR_HANDLE, STARTF_USESTDHANDLES, STD_INPUT_HANDLE};
-use winapi::um::minwinbase::{STILL_ACTIVE, SYSTEMTIME, LPSECURITY_ATTRIBUTES, SECURITY_ATTRIBUTES};
+use chrono::{Local, Utc, TimeZone};
+use winapi::um::processthreadsapi::{TerminateProcess, OpenProcess, GetExitCodeProcess, GetProcessTimes, CreateProcessW, PROCESS_INFORMATION, STARTUPINFOW, GetCurrentProcess, GetCurrentProcessId, ResumeThread, SetPriorityClass};
+use winapi::shared::ntdef::{HANDLE, NTSTATUS, ULONG};
+use winapi::um::winnt::{WT_EXECUTEONLYONCE, PVOID, BOOLEAN, SYNCHRONIZE, PROCESS_TERMINATE, PROCESS_VM_READ, PROCESS_QUERY_INFORMATION, DUPLICATE_SAME_ACCESS, PROCESS_DUP_HANDLE, GENERIC_WRITE, QUOTA_LIMITS_HARDWS_MIN_ENABLE, QUOTA_LIMITS_HARDWS_MIN_DISABLE, QUOTA_LIMITS_HARDWS_MAX_ENABLE, QUOTA_LIMITS_HARDWS_MAX_DISABLE};
+use winapi::um::winbase::{RegisterWaitForSingleObject, INFINITE, UnregisterWait, DETACHED_PROCESS, CREATE_NEW_CONSOLE, FORMAT_MESSAGE_FROM_HMODULE, FORMAT_MESSAGE_IGNORE_INSERTS, CREATE_NO_WINDOW, STD_OUTPUT_HANDLE, STD_ERROR_HANDLE, STARTF_USESTDHANDLES, STARTF_USESHOWWINDOW, STD_INPUT_HANDLE, EXTENDED_STARTUPINFO_PRESENT, STARTUPINFOEXW, CREATE_SUSPENDED, CREATE_UNICODE_ENVIRONMENT, HANDLE_FLAG_INHERIT, IDLE_PRIORITY_CLASS, BELOW_NORMAL_PRIORITY_CLASS, NORMAL_PRIORITY_CLASS, ABOVE_NORMAL_PRIORITY_CLASS, HIGH_PRIORITY_CLASS};
+use winapi::um::minwinbase::{STILL_ACTIVE, SYSTEMTIME, LPSECURITY_ATTRIBUTES};
 use winapi::um::wincon::{AttachConsole, GenerateConsoleCtrlEvent, CTRL_C_EVENT, FreeConsole};
 use winapi::um::consoleapi::SetConsoleCtrlHandler;
-use winapi::shared::minwindef::{FILETIME, LPVOID, TRUE, FALSE};
+use winapi::um::winuser::{SW_HIDE, SW_SHOWNORMAL};
+use winapi::shared::minwindef::{BOOL, DWORD, WORD, FILETIME, LPVOID, TRUE, FALSE};
+use winapi::shared::basetsd::SIZE_T;
 use winapi::um::timezoneapi::FileTimeToSystemTime;
 use winapi::um::psapi::{GetProcessMemoryInfo, PROCESS_MEMORY_COUNTERS};
-use std::time::Duration;
-use std::ptr::null;
-use std::ffi::{CString, CStr, c_void};
-use std::os::raw::c_char;
+use std::time::{Duration, Instant, SystemTime};
+use std::ptr::{null, null_mut};
+use std::ffi::{CStr, c_void};
 use std::borrow::BorrowMut;
 use winapi::um::errhandlingapi::GetLastError;
 use std::io::{stdin, Stdin, Stdout};
-use winapi::um::handleapi::{CloseHandle, DuplicateHandle};
+use winapi::um::handleapi::{CloseHandle, DuplicateHandle, SetHandleInformation};
+use winapi::um::namedpipeapi::CreatePipe;
+use winapi::um::memoryapi::SetProcessWorkingSetSizeEx;
 use winapi::um::processenv::{SetStdHandle, GetStdHandle};
-use winapi::um::fileapi::{CreateFileA, OPEN_ALWAYS, CREATE_ALWAYS};
 
 pub struct StatefulProcess {
   pub id: String,
   pub config: StatefulProcessConfig,
+  pub instance_index: usize,
   pub memory_usage_mbs: Option<f64>,
   pub duration_secs: Option<f64>,
+  /// Share of all the processors the process used since the previous poll.
+  pub cpu_percent: Option<f64>,
+  /// Whether the orchestrator stopped the process, rather than it exiting by itself.
+  pub is_stop_requested: bool,
+  pub stop_requested_at: Option<SystemTime>,
+  /// Whether the output of a process without a log file is written to the orchestrator's console.
+  pub is_output_mirrored: bool,
+  /// Keeps the end of the output in memory, when `output_buffer_kbs` is set.
+  pub output_buffer: Option<OutputBuffer>,
+  /// Forwards the output to the `log_shipping` collector.
+  pub log_shipper: Option<ProcessLogShipper>,
+  /// Writes the lines matching the `event_log_rules` to the event log.
+  pub output_events: Option<OutputEvents>,
+  /// Set while the captured output is not written to the log file, e.g. while its volume is low on
+  /// space.
+  pub is_log_write_paused: Arc<AtomicBool>,
   os_handler_context: Pin<Box<StatefulProcessOsHandlerContext>>,
   process_handle: Option<HANDLE>,
   pid: Option<u32>,
-  log_file_handle: Option<HANDLE>,
+  /// Log file the process was last started with.
+  log_file_path: Option<String>,
+  /// Watches the `restart_on_change` paths.
+  file_watcher: Option<FileWatcher>,
+  watched_files_changed_at: Option<Instant>,
+  cpu_time_sample: Option<(Instant, u64)>,
+  /// Profile of the `run_as` account, loaded while the process runs.
+  user_profile: Option<UserProfile>,
+  /// Job object enforcing the resource limits of the process, while it runs.
+  job: Option<ProcessJob>,
 }
 
-#[derive(Debug, Serialize, Deserialize, Clone)]
+/// How long the `restart_on_change` paths must stay unchanged before the process is restarted, so
+/// a deployment copying many files restarts it once.
+const DEFAULT_RESTART_ON_CHANGE_DEBOUNCE_SECS: f64 = 2f64;
+
+/// Alternate log files tried when the log file can't be opened.
+const MAX_LOG_FILE_FALLBACKS: usize = 3;
+
+/// Placeholder of the `log_file` replaced with the PID of the started process.
+const PID_PLACEHOLDER: &str = "{pid}";
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+#[serde(deny_unknown_fields)]
 pub struct StatefulProcessConfig {
   pub name: String,
+  pub enabled: Option<bool>,
+  pub tags: Option<Vec<String>>,
   pub executable: String,
   pub arguments: Option<Vec<String>>,
+  pub raw_arguments: Option<String>,
+  pub shell: Option<Shell>,
   pub working_directory: Option<String>,
+  pub create_working_directory: Option<bool>,
+  pub stdin: Option<StdinSource>,
+  pub console: Option<ConsoleMode>,
   pub log_file: Option<String>,
+  pub log_mode: Option<LogMode>,
+  pub log_max_size_mbs: Option<f64>,
+  pub log_max_files: Option<usize>,
+  pub log_rotation: Option<LogRotationPeriod>,
+  pub log_max_age_days: Option<f64>,
+  pub log_compress: Option<bool>,
+  pub log_timestamps: Option<bool>,
+  pub log_strip_ansi: Option<bool>,
+  pub output_encoding: Option<OutputEncoding>,
+  pub output_buffer_kbs: Option<usize>,
+  pub event_log_rules: Option<Vec<EventLogRule>>,
+  pub run_as: Option<RunAsConfig>,
+  pub restricted_token: Option<bool>,
+  pub integrity_level: Option<IntegrityLevel>,
+  pub interactive_session: Option<bool>,
+  pub app_container: Option<AppContainerConfig>,
+  pub mitigations: Option<Vec<Mitigation>>,
+  pub cpu_affinity: Option<CpuAffinity>,
+  pub priority: Option<ProcessPriority>,
+  pub numa_node: Option<u16>,
+  pub processor_group: Option<u16>,
+  pub working_set: Option<WorkingSetConfig>,
+  pub cpu_limit_percent: Option<f64>,
+  pub memory_limit_mbs: Option<f64>,
+  pub max_child_processes: Option<u32>,
+  pub job_breakaway: Option<JobBreakaway>,
   pub stop_method: Option<StatefulProcessStopMethod>,
   pub environment_variables: Option<HashMap<String, String>>,
+  pub inherit_environment: Option<bool>,
+  pub env_file: Option<String>,
   pub recycle_on_memory_mbs: Option<f64>,
   pub recycle_on_duration_secs: Option<f64>,
+  pub replicas: Option<usize>,
+  pub base_port: Option<u16>,
+  pub restart_on_change: Option<Vec<String>>,
+  pub restart_on_change_debounce_secs: Option<f64>,
+  pub restart_on_resume: Option<bool>,
+  pub on_session_change: Option<HashMap<SessionChangeEvent, SessionChangeAction>>,
+  /// Directory of the config file defining the process, for `{config_dir}`.
+  #[serde(skip)]
+  pub config_directory: Option<String>,
 }
 
 #[serde(rename_all = "snake_case")]
@@ -62,15 +167,328 @@ pub enum StatefulProcessStopMethod {
   Terminate
 }
 
+/// How the log file is opened each time the process starts.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum LogMode {
+  /// Keep writing at the end of the file.
+  Append,
+  /// Empty the file first.
+  Truncate,
+  /// Write to a new file stamped with the start time, e.g. `worker.20261016-091203.log`.
+  NewFilePerStart,
+}
+
+/// Writes the lines of output containing the pattern to the event log at the level.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct EventLogRule {
+  /// Text the line contains, where `*` matches any characters, `?` any one character and `[...]`
+  /// one of the characters.
+  pub pattern: String,
+  pub level: EventLogLevel,
+}
+
+impl EventLogRule {
+  /// The pattern matching whole lines containing it.
+  pub fn get_line_pattern(&self) -> Result<Pattern, PatternError> {
+    Pattern::new(&format!("*{}*", &self.pattern))
+  }
+}
+
+/// Account the process is run as, rather than the one the orchestrator runs as.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct RunAsConfig {
+  pub username: String,
+  pub domain: Option<String>,
+  /// The password, as an `!encrypted` value or a `cred://` reference.
+  pub password_ref: String,
+  /// Load the profile of the account for the process, for its `HKEY_CURRENT_USER` and
+  /// environment.
+  pub load_profile: Option<bool>,
+}
+
+impl RunAsConfig {
+  /// The account name, with its domain when configured, e.g. `CORP\worker`.
+  pub fn get_account_name(&self) -> String {
+    match &self.domain {
+      Some(domain) => format!("{}\\{}", domain, &self.username),
+      None => self.username.clone(),
+    }
+  }
+
+  pub fn logon(&self) -> Result<ProcessToken, Box<dyn Error>> {
+    let password = resolve_secret(&self.password_ref)?;
+
+    ProcessToken::logon(&self.username, self.domain.as_deref(), &password)
+      .map_err(|logon_error| Box::new(OrchestratorError::RunAsLogonFailed(self.get_account_name(), logon_error)).into())
+  }
+}
+
+/// Logical processors the process is allowed to run on.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+#[serde(untagged)]
+pub enum CpuAffinity {
+  /// Indexes of the processors, e.g. `[0, 1, 2, 3]`.
+  Processors(Vec<u32>),
+  /// Hexadecimal mask of the processors, e.g. `"0xF0"`.
+  Mask(String),
+}
+
+impl CpuAffinity {
+  /// The affinity mask, unless no processor, or one beyond those of a processor group, is given.
+  pub fn get_mask(&self) -> Option<usize> {
+    let mask = match self {
+      CpuAffinity::Processors(processors) => processors.iter().try_fold(0usize, |mask, x| 1usize.checked_shl(*x).map(|bit| mask | bit)),
+      CpuAffinity::Mask(mask) => usize::from_str_radix(mask.trim_start_matches("0x").trim_start_matches("0X"), 16).ok(),
+    };
+
+    mask.filter(|x| *x != 0)
+  }
+}
+
+/// Priority class the threads of the process are scheduled with.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum ProcessPriority {
+  Idle,
+  BelowNormal,
+  Normal,
+  AboveNormal,
+  High,
+}
+
+impl ProcessPriority {
+  pub fn get_priority_class(&self) -> DWORD {
+    match self {
+      ProcessPriority::Idle => IDLE_PRIORITY_CLASS,
+      ProcessPriority::BelowNormal => BELOW_NORMAL_PRIORITY_CLASS,
+      ProcessPriority::Normal => NORMAL_PRIORITY_CLASS,
+      ProcessPriority::AboveNormal => ABOVE_NORMAL_PRIORITY_CLASS,
+      ProcessPriority::High => HIGH_PRIORITY_CLASS,
+    }
+  }
+}
+
+/// Memory the process keeps resident, which Windows otherwise trims and grows as it sees fit.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct WorkingSetConfig {
+  pub min_mbs: f64,
+  pub max_mbs: f64,
+  /// Whether the process is never trimmed below `min_mbs`, rather than only when memory is low.
+  pub hard_min: Option<bool>,
+  /// Whether the process never holds more than `max_mbs`, rather than only when memory is low.
+  pub hard_max: Option<bool>,
+}
+
+impl WorkingSetConfig {
+  pub fn get_flags(&self) -> DWORD {
+    let min_flag = if self.hard_min.unwrap_or(false) { QUOTA_LIMITS_HARDWS_MIN_ENABLE } else { QUOTA_LIMITS_HARDWS_MIN_DISABLE };
+    let max_flag = if self.hard_max.unwrap_or(false) { QUOTA_LIMITS_HARDWS_MAX_ENABLE } else { QUOTA_LIMITS_HARDWS_MAX_DISABLE };
+
+    min_flag | max_flag
+  }
+}
+
+/// Console the process is created with.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum ConsoleMode {
+  /// A console without a window.
+  Hidden,
+  /// A console of its own, with its window hidden.
+  NewConsole,
+  /// A console of its own, in a window shown on the desktop.
+  NewWindow,
+  /// The orchestrator's console, when it has one.
+  Inherit,
+}
+
+impl ConsoleMode {
+  pub fn get_creation_flags(&self) -> DWORD {
+    match self {
+      ConsoleMode::Hidden => CREATE_NO_WINDOW,
+      ConsoleMode::NewConsole | ConsoleMode::NewWindow => CREATE_NEW_CONSOLE,
+      ConsoleMode::Inherit => 0,
+    }
+  }
+
+  /// How the window of the console is shown, when it isn't left to the process.
+  pub fn get_show_window(&self) -> Option<WORD> {
+    match self {
+      ConsoleMode::NewConsole => Some(SW_HIDE as WORD),
+      ConsoleMode::NewWindow => Some(SW_SHOWNORMAL as WORD),
+      ConsoleMode::Hidden | ConsoleMode::Inherit => None,
+    }
+  }
+}
+
+/// What the process reads as its standard input.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+#[serde(try_from = "String", into = "String")]
+pub enum StdinSource {
+  /// The `NUL` device, read as empty.
+  Null,
+  /// A pipe closed right away, read as ended.
+  Closed,
+  /// A file, configured as `file:<path>`.
+  File(String),
+}
+
+impl TryFrom<String> for StdinSource {
+  type Error = String;
+
+  fn try_from(value: String) -> Result<Self, Self::Error> {
+    match value.as_str() {
+      "null" => Ok(StdinSource::Null),
+      "closed" => Ok(StdinSource::Closed),
+      _ => match value.strip_prefix("file:") {
+        Some(path) if !path.is_empty() => Ok(StdinSource::File(path.to_string())),
+        _ => Err(format!("stdin must be `null`, `closed` or `file:<path>`, not [{}]", value)),
+      },
+    }
+  }
+}
+
+impl From<StdinSource> for String {
+  fn from(value: StdinSource) -> Self {
+    match value {
+      StdinSource::Null => String::from("null"),
+      StdinSource::Closed => String::from("closed"),
+      StdinSource::File(path) => format!("file:{}", path),
+    }
+  }
+}
+
+/// AppContainer the process is sandboxed in.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct AppContainerConfig {
+  /// Name of the AppContainer profile, shared by the processes configured with it.
+  pub name: Option<String>,
+  pub capabilities: Option<Vec<AppContainerCapability>>,
+}
+
+impl AppContainerConfig {
+  /// The configured name, or `process-orchestrator.<process name>`.
+  pub fn get_name(&self, process_name: &str) -> String {
+    self.name.clone().unwrap_or_else(|| format!("process-orchestrator.{}", process_name))
+  }
+}
+
+// winapi declares the mask as a `DWORD`, which leaves out the processors past 31.
+#[link(name = "kernel32")]
+extern "system" {
+  fn SetProcessAffinityMask(process_handle: HANDLE, process_affinity_mask: usize) -> BOOL;
+}
+
+#[link(name = "ntdll")]
+extern "system" {
+  fn NtSuspendProcess(process_handle: HANDLE) -> NTSTATUS;
+  fn NtResumeProcess(process_handle: HANDLE) -> NTSTATUS;
+  fn RtlNtStatusToDosError(status: NTSTATUS) -> ULONG;
+}
+
+/// Changes to the Windows sessions on the machine, e.g. a user logging on to the console.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Hash)]
+#[serde(rename_all = "snake_case")]
+pub enum SessionChangeEvent {
+  ConsoleConnect,
+  ConsoleDisconnect,
+  RemoteConnect,
+  RemoteDisconnect,
+  Logon,
+  Logoff,
+  Lock,
+  Unlock,
+}
+
+/// What happens to a process on a session change. A process stopped this way stays stopped until
+/// a session change starts it again.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum SessionChangeAction {
+  Start,
+  Stop,
+  Restart,
+}
+
 struct StatefulProcessOsHandlerContext {
   register_handle: Option<HANDLE>,
   process_id: String,
   sender: Sender<Event>,
 }
 
+impl StatefulProcessConfig {
+  pub fn get_replicas(&self) -> usize {
+    self.replicas.unwrap_or(1)
+  }
+
+  /// How the log file is rotated, when it is rotated at all, in which case the orchestrator
+  /// writes it rather than the process.
+  pub fn get_log_rotation(&self) -> Option<LogRotation> {
+    if self.log_max_size_mbs.is_none() && self.log_rotation.is_none() {
+      return None;
+    }
+
+    Some(LogRotation {
+      max_size_bytes: self.log_max_size_mbs.map(|x| (x * 1024f64 * 1024f64) as u64),
+      period: self.log_rotation,
+      max_files: self.log_max_files.unwrap_or(DEFAULT_LOG_MAX_FILES),
+      max_age: self.log_max_age_days.map(|x| Duration::from_secs_f64(x * 86400f64)),
+      compress: self.log_compress.unwrap_or(false),
+    })
+  }
+
+  /// Patterns of the `event_log_rules` with their level, leaving out those that are not valid,
+  /// which fail the validation of the config.
+  pub fn get_event_log_patterns(&self) -> Vec<(Pattern, EventLogLevel)> {
+    self.event_log_rules
+      .iter()
+      .flatten()
+      .filter_map(|x| x.get_line_pattern().ok().map(|pattern| (pattern, x.level)))
+      .collect()
+  }
+
+  /// Whether the executable is the path of a program or script, rather than a command run through
+  /// the `shell`.
+  pub fn is_executable_a_path(&self) -> bool {
+    self.get_shell().map(|x| x.is_script(&self.executable)).unwrap_or(true)
+  }
+
+  /// The configured `shell`, or the one running the executable when it is a script.
+  pub fn get_shell(&self) -> Option<Shell> {
+    self.shell.or_else(|| Shell::detect(&self.executable))
+  }
+
+  /// Finds the file the executable refers to, the shell's interpreter for a command, failing with
+  /// the path that was looked for when it does not exist.
+  pub fn resolve_executable(&self) -> Result<PathBuf, OrchestratorError> {
+    let executable = match self.get_shell() {
+      Some(shell) if !self.is_executable_a_path() => shell.get_interpreter(),
+      _ => self.executable.as_str(),
+    };
+
+    find_executable(executable).ok_or_else(|| {
+      let executable_path = Path::new(executable);
+      if executable_path.is_relative() && executable_path.components().count() > 1 {
+        OrchestratorError::ExecutableNotFound(std::env::current_dir().unwrap_or_default().join(executable_path))
+      }
+      else {
+        OrchestratorError::ExecutableNotFound(executable_path.to_path_buf())
+      }
+    })
+  }
+}
+
 impl StatefulProcess {
-  pub fn new(config: StatefulProcessConfig, sender: Sender<Event>) -> Self {
-    let process_id = StatefulProcess::create_process_id(config.name.as_str());
+  pub fn new(config: StatefulProcessConfig, instance_index: usize, sender: Sender<Event>) -> Self {
+    let process_id = match config.get_replicas() {
+      1 => StatefulProcess::create_process_id(config.name.as_str()),
+      _ => StatefulProcess::create_process_id(format!("{}-{}", &config.name, instance_index).as_str()),
+    };
 
     let os_handler_context = Pin::new(Box::new(StatefulProcessOsHandlerContext {
       register_handle: None,
@@ -80,18 +498,34 @@ impl StatefulProcess {
 
     Self {
       id: process_id.clone(),
-      config,
+      instance_index,
       os_handler_context,
       pid: None,
       process_handle: None,
-      log_file_handle: None,
+      log_file_path: None,
       memory_usage_mbs: None,
       duration_secs: None,
+      cpu_percent: None,
+      is_stop_requested: false,
+      stop_requested_at: None,
+      is_output_mirrored: false,
+      output_buffer: None,
+      log_shipper: None,
+      output_events: None,
+      is_log_write_paused: Arc::new(AtomicBool::new(false)),
+      file_watcher: config.restart_on_change.as_deref().map(FileWatcher::new),
+      watched_files_changed_at: None,
+      cpu_time_sample: None,
+      user_profile: None,
+      job: None,
+      config,
     }
   }
 
   pub fn request_stop(&mut self) {
     info!("Process [{}]: Requesting stop", &self.id);
+    self.is_stop_requested = true;
+    self.stop_requested_at = Some(SystemTime::now());
 
     if self.process_handle.is_none() || self.pid.is_none() {
       return;
@@ -108,77 +542,310 @@ impl StatefulProcess {
   #[cfg(windows)]
   pub fn start_instance(&mut self) -> VoidResult {
     let config = &self.config;
+    let executable_path = config.resolve_executable()?;
+    info!("Process [{}]: Starting [{}]", &self.id, executable_path.display());
 
-    let mut command_line = CString::new(config.executable.as_str())?;
-
-    if let Some(arguments) = config.arguments.clone() {
-      command_line = CString::new(format!("{} {}", &config.executable, arguments.iter().map(|x| format!("\"{}\"", x)).collect::<Vec<String>>().join(" ")))?;
+    if let Some(working_directory) = &config.working_directory {
+      if config.create_working_directory.unwrap_or(false) {
+        std::fs::create_dir_all(working_directory)
+          .map_err(|create_error| OrchestratorError::WorkingDirectoryCreateFailed(working_directory.clone(), create_error))?;
+      }
+      else if !Path::new(working_directory).is_dir() {
+        return Err(Box::new(OrchestratorError::WorkingDirectoryNotFound(working_directory.clone())));
+      }
     }
 
-    let mut environment_cstring: *mut c_char = 0 as *mut c_char;
-    if let Some(environment_variables) = config.environment_variables.clone() {
-      let mut environment_string = String::new();
+    let (process_token, user_profile) = self.get_process_token()?;
 
-      for environment_variable in environment_variables {
-        let pair = format!("{}={}\0", environment_variable.0, environment_variable.1);
-        environment_string.push_str(pair.as_str());
-      }
+    // The environment Windows gives the account at logon, rather than the orchestrator's. Without its
+    // profile loaded, it has the machine's variables and those of the account's profile folders, but
+    // not the ones of its registry hive.
+    let is_environment_inherited = self.config.inherit_environment.unwrap_or(true);
+    let is_another_account = self.config.run_as.is_some() || self.config.interactive_session.unwrap_or(false);
 
-      environment_string.push_str("\0");
-      unsafe {
-        environment_cstring = environment_string.as_mut_ptr() as *mut c_char;
+    let account_environment_variables = match &user_profile {
+      Some(user_profile) if is_environment_inherited => Some(user_profile.environment_variables.clone()),
+      None if is_environment_inherited && is_another_account => {
+        process_token.as_ref().map(|x| x.get_environment_variables()).transpose()?
       }
-    }
+      _ => None,
+    };
 
-    let mut working_directory_cstring= 0 as *mut c_char;
-    if let Some(work) = &config.working_directory {
-      working_directory_cstring = CString::new(work.as_str())?.into_raw();
-    }
+    let configured_environment_variables = self.get_environment_variables()?;
+    let instance_environment_variables = self.get_instance_environment_variables();
+
+    // Only an inherited orchestrator environment without any variable on top lets Windows pass it on.
+    let is_environment_unchanged = is_environment_inherited
+      && account_environment_variables.is_none()
+      && configured_environment_variables.is_empty()
+      && instance_environment_variables.is_empty();
+
+    // The account's own environment, or the orchestrator's, with the configured variables on top.
+    let mut environment_variables = match account_environment_variables {
+      Some(account_environment_variables) => account_environment_variables,
+      None if is_environment_inherited => std::env::vars().collect(),
+      None => HashMap::new(),
+    };
+    merge_environment_variables(&mut environment_variables, configured_environment_variables);
+    merge_environment_variables(&mut environment_variables, instance_environment_variables);
+
+    let command_line = self.get_command_line(&executable_path, &environment_variables)?;
+
+    let mut environment_block = match is_environment_unchanged {
+      true => None,
+      false => Some(get_environment_block(&environment_variables)),
+    };
+
+    let mut command_line_wide = to_wide(&command_line);
+    let working_directory_wide = config.working_directory.as_deref().map(to_wide);
 
     unsafe {
       let mut process_information = std::mem::zeroed::<PROCESS_INFORMATION>();
-      let mut startup_information = std::mem::zeroed::<STARTUPINFOA>();
-      startup_information.cb = std::mem::size_of::<STARTUPINFOA>() as u32;
+      let mut startup_information = std::mem::zeroed::<STARTUPINFOEXW>();
+      startup_information.StartupInfo.cb = std::mem::size_of::<STARTUPINFOW>() as u32;
+      let console_mode = self.config.console.unwrap_or(ConsoleMode::Hidden);
+      let mut creation_flags = console_mode.get_creation_flags() | CREATE_UNICODE_ENVIRONMENT;
+
+      if let Some(show_window) = console_mode.get_show_window() {
+        startup_information.StartupInfo.dwFlags |= STARTF_USESHOWWINDOW;
+        startup_information.StartupInfo.wShowWindow = show_window;
+      }
+
+      // Kept until the process is created, as the attributes point into them.
+      let mut app_container = self.get_app_container()?;
+      let mut process_attributes = ProcessAttributes::default();
+
+      if let Some(app_container) = app_container.as_mut() {
+        process_attributes.add(PROC_THREAD_ATTRIBUTE_SECURITY_CAPABILITIES, app_container.get_security_capabilities());
+      }
+
+      let mitigations = self.config.mitigations.clone().unwrap_or_default();
+      let mitigation_policy = mitigations.iter().filter_map(|x| x.get_policy_flag()).fold(0u64, |policy, flag| policy | flag);
+
+      if mitigation_policy != 0 {
+        process_attributes.add(PROC_THREAD_ATTRIBUTE_MITIGATION_POLICY, mitigation_policy);
+      }
+
+      if mitigations.contains(&Mitigation::NoChildProcesses) {
+        process_attributes.add(PROC_THREAD_ATTRIBUTE_CHILD_PROCESS_POLICY, CHILD_PROCESS_RESTRICTED);
+      }
+
+      if let Some(numa_node) = self.config.numa_node {
+        let affinity = get_numa_node_affinity(numa_node).ok_or(OrchestratorError::NumaNodeNotFound(numa_node))?;
+        process_attributes.add(PROC_THREAD_ATTRIBUTE_GROUP_AFFINITY, affinity);
+        process_attributes.add(PROC_THREAD_ATTRIBUTE_PREFERRED_NODE, numa_node);
+      }
+
+      if let Some(processor_group) = self.config.processor_group {
+        let affinity = get_processor_group_affinity(processor_group).ok_or(OrchestratorError::ProcessorGroupNotFound(processor_group))?;
+        process_attributes.add(PROC_THREAD_ATTRIBUTE_GROUP_AFFINITY, affinity);
+      }
+
+      if !process_attributes.is_empty() {
+        startup_information.StartupInfo.cb = std::mem::size_of::<STARTUPINFOEXW>() as u32;
+        startup_information.lpAttributeList = process_attributes.create_list()?;
+        creation_flags |= EXTENDED_STARTUPINFO_PRESENT;
+      }
+
+      // The process is only resumed once it is in its job and set up, so it can't start anything,
+      // or allocate any memory, before its limits apply.
+      let job = self.get_process_job()?;
+      creation_flags |= CREATE_SUSPENDED;
 
-      if let Some(log_file) = &config.log_file {
-        let mut security_attributes: SECURITY_ATTRIBUTES = std::mem::zeroed();
-        security_attributes.nLength = std::mem::size_of::<SECURITY_ATTRIBUTES>() as u32;
-        security_attributes.bInheritHandle = TRUE;
+      let mut log_pipe = None::<LogPipe>;
+      let mut log_file = None::<RotatingLogFile>;
+      let log_mode = self.config.log_mode.unwrap_or(LogMode::Append);
+      let log_rotation = self.config.get_log_rotation().unwrap_or_default();
 
-        let log_file_cstring = CString::new(log_file.as_str())?.into_raw();
+      self.log_file_path = self.resolve_log_file();
+      let is_output_mirrored = self.is_output_mirrored && self.log_file_path.is_none();
 
-        let log_file_handle = CreateFileA(
-          log_file_cstring as LPCSTR,
-          FILE_APPEND_DATA,
-          FILE_SHARE_WRITE | FILE_SHARE_READ,
-          &mut security_attributes,
-          OPEN_ALWAYS,
-          FILE_ATTRIBUTE_NORMAL,
-          0 as HANDLE);
+      // The PID is only known once the process is created, after it has been given its output
+      // handle, so a log file named after it is opened then.
+      if let Some(log_file_path) = self.log_file_path.clone().filter(|x| !x.contains(PID_PLACEHOLDER)) {
+        let (opened_log_file_path, opened_log_file) = self.open_log_file_with_fallback(&log_file_path, log_mode, &log_rotation)?;
 
-        startup_information.dwFlags = STARTF_USESTDHANDLES;
-        startup_information.hStdOutput = log_file_handle;
-        startup_information.hStdError = log_file_handle;
+        self.log_file_path = Some(opened_log_file_path);
+        log_file = Some(opened_log_file);
+      }
+
+      // The process writes its output to a pipe, which the orchestrator reads to write it to the
+      // log file and to whatever else consumes it.
+      if self.log_file_path.is_some()
+        || is_output_mirrored
+        || self.output_buffer.is_some()
+        || self.log_shipper.is_some()
+        || self.output_events.is_some() {
+        let created_log_pipe = LogPipe::create()?;
+
+        startup_information.StartupInfo.dwFlags |= STARTF_USESTDHANDLES;
+        startup_information.StartupInfo.hStdOutput = created_log_pipe.write_handle;
+        startup_information.StartupInfo.hStdError = created_log_pipe.write_handle;
+
+        log_pipe = Some(created_log_pipe);
+      }
+
+      // Once the standard handles are given, the process has no input unless it is given one too, so
+      // it reads `NUL` rather than failing on a missing handle.
+      let stdin_source = match (&self.config.stdin, &log_pipe) {
+        (Some(stdin_source), _) => Some(stdin_source.clone()),
+        (None, Some(_)) => Some(StdinSource::Null),
+        (None, None) => None,
+      };
+
+      // The orchestrator's own copy, closed once the process is created with its own.
+      let mut stdin_handle = None::<HANDLE>;
+
+      if let Some(stdin_source) = &stdin_source {
+        let opened_stdin_handle = match open_stdin_handle(stdin_source) {
+          Ok(opened_stdin_handle) => opened_stdin_handle,
+          Err(open_error) => {
+            if let Some(log_pipe) = log_pipe {
+              log_pipe.close();
+            }
+
+            return Err(Box::new(OrchestratorError::StdinOpenFailed(String::from(stdin_source.clone()), open_error)));
+          }
+        };
+
+        if log_pipe.is_none() {
+          startup_information.StartupInfo.hStdOutput = GetStdHandle(STD_OUTPUT_HANDLE);
+          startup_information.StartupInfo.hStdError = GetStdHandle(STD_ERROR_HANDLE);
+        }
+
+        startup_information.StartupInfo.dwFlags |= STARTF_USESTDHANDLES;
+        startup_information.StartupInfo.hStdInput = opened_stdin_handle;
+        stdin_handle = Some(opened_stdin_handle);
+      }
+
+      let environment_block_ptr = environment_block.as_mut().map(|x| x.as_mut_ptr() as LPVOID).unwrap_or(null_mut());
+      let working_directory_ptr = working_directory_wide.as_ref().map(|x| x.as_ptr()).unwrap_or(null());
+
+      let mut desktop_wide = match self.config.interactive_session.unwrap_or(false) {
+        true => Some(to_wide(INTERACTIVE_DESKTOP)),
+        false => None,
+      };
+      if let Some(desktop_wide) = desktop_wide.as_mut() {
+        startup_information.StartupInfo.lpDesktop = desktop_wide.as_mut_ptr();
+      }
 
-        self.log_file_handle = Some(log_file_handle);
+      let is_created = match &process_token {
+        Some(process_token) => process_token.create_process(
+          &mut command_line_wide,
+          environment_block_ptr,
+          working_directory_ptr,
+          creation_flags,
+          &mut startup_information,
+          &mut process_information),
+        None => CreateProcessW(
+          null(),
+          command_line_wide.as_mut_ptr(),
+          0 as LPSECURITY_ATTRIBUTES,
+          0 as LPSECURITY_ATTRIBUTES,
+          TRUE,
+          creation_flags,
+          environment_block_ptr,
+          working_directory_ptr,
+          &mut startup_information.StartupInfo,
+          &mut process_information) != 0,
+      };
+      let create_error = std::io::Error::last_os_error();
+
+      if let Some(stdin_handle) = stdin_handle {
+        CloseHandle(stdin_handle);
       }
 
-      if CreateProcessA(
-        0 as LPCSTR,
-        command_line.into_raw(),
-        0 as LPSECURITY_ATTRIBUTES,
-        0 as LPSECURITY_ATTRIBUTES,
-        TRUE,
-        CREATE_NO_WINDOW,
-        environment_cstring as LPVOID,
-        working_directory_cstring as LPCSTR,
-        &mut startup_information,
-        &mut process_information) == 0 {
+      if !is_created {
+        if let Some(log_pipe) = log_pipe {
+          log_pipe.close();
+        }
 
-        return Err(Box::new(std::io::Error::last_os_error()));
+        return Err(Box::new(create_error));
       }
 
+      if let Some(job) = &job {
+        if let Err(assign_error) = job.assign(process_information.hProcess) {
+          TerminateProcess(process_information.hProcess, 1);
+          CloseHandle(process_information.hThread);
+          CloseHandle(process_information.hProcess);
+
+          if let Some(log_pipe) = log_pipe {
+            log_pipe.close();
+          }
+
+          return Err(Box::new(OrchestratorError::JobAssignFailed(assign_error)));
+        }
+      }
+
+      if let Some(affinity_mask) = self.config.cpu_affinity.as_ref().and_then(|x| x.get_mask()) {
+        if SetProcessAffinityMask(process_information.hProcess, affinity_mask) == 0 {
+          error!("Process [{}]: Could not set the CPU affinity to {:#x}: {}", &self.id, affinity_mask, std::io::Error::last_os_error());
+        }
+      }
+
+      if let Some(working_set) = &self.config.working_set {
+        if SetProcessWorkingSetSizeEx(
+          process_information.hProcess,
+          (working_set.min_mbs * 1024f64 * 1024f64) as SIZE_T,
+          (working_set.max_mbs * 1024f64 * 1024f64) as SIZE_T,
+          working_set.get_flags()) == 0 {
+          error!("Process [{}]: Could not set the working set to {} - {} MB: {}", &self.id, working_set.min_mbs, working_set.max_mbs, std::io::Error::last_os_error());
+        }
+      }
+
+      if let Some(priority) = self.config.priority {
+        if SetPriorityClass(process_information.hProcess, priority.get_priority_class()) == 0 {
+          error!("Process [{}]: Could not set the priority to {:?}: {}", &self.id, priority, std::io::Error::last_os_error());
+        }
+      }
+
+      if ResumeThread(process_information.hThread) == DWORD::MAX {
+        let resume_error = std::io::Error::last_os_error();
+        TerminateProcess(process_information.hProcess, 1);
+        CloseHandle(process_information.hThread);
+        CloseHandle(process_information.hProcess);
+
+        if let Some(log_pipe) = log_pipe {
+          log_pipe.close();
+        }
+
+        return Err(Box::new(resume_error));
+      }
+
+      CloseHandle(process_information.hThread);
+
       self.pid = Some(process_information.dwProcessId);
+      self.user_profile = user_profile;
+      self.job = job;
+
+      if let Some(log_pipe) = log_pipe {
+        if let Some(log_file_path) = self.log_file_path.clone().filter(|x| x.contains(PID_PLACEHOLDER)) {
+          let log_file_path = log_file_path.replace(PID_PLACEHOLDER, &process_information.dwProcessId.to_string());
+
+          // The process is already running, so its output is still captured for the rest.
+          match self.open_log_file_with_fallback(&log_file_path, log_mode, &log_rotation) {
+            Ok((opened_log_file_path, opened_log_file)) => {
+              self.log_file_path = Some(opened_log_file_path);
+              log_file = Some(opened_log_file);
+            }
+            Err(open_error) => {
+              error!("Process [{}]: {}", &self.id, open_error);
+              self.log_file_path = Some(log_file_path);
+            }
+          }
+        }
+
+        log_pipe.start_capture(&self.id, log_file, LogCaptureOptions {
+          output_encoding: self.config.output_encoding,
+          is_timestamped: self.config.log_timestamps.unwrap_or(false),
+          is_ansi_stripped: self.config.log_strip_ansi.unwrap_or(false),
+          console_name: if is_output_mirrored { Some(self.get_console_name()) } else { None },
+          output_buffer: self.output_buffer.clone(),
+          log_shipper: self.log_shipper.clone(),
+          output_events: self.output_events.clone(),
+          is_write_paused: self.is_log_write_paused.clone(),
+        });
+      }
       self.process_handle = Some(process_information.hProcess);
 
       let os_handler_context_ptr = self.os_handler_context.as_mut().get_mut() as *mut StatefulProcessOsHandlerContext;
@@ -203,6 +870,7 @@ impl StatefulProcess {
   #[cfg(not(windows))]
   pub fn start_instance(&mut self) -> VoidResult {
     let config = &self.config;
+    config.resolve_executable()?;
     let mut command = Command::new(config.executable.as_str());
 
     if let Some(working_directory) = &config.working_directory {
@@ -255,6 +923,275 @@ impl StatefulProcess {
     Ok(())
   }
 
+  /// Environment variables passed to the process: those loaded from the `env_file`, overridden
+  /// by the configured `environment_variables`, with encrypted secrets decrypted.
+  pub fn get_environment_variables(&self) -> Result<HashMap<String, String>, Box<dyn Error>> {
+    let mut environment_variables = HashMap::<String, String>::new();
+
+    if let Some(env_file) = &self.config.env_file {
+      environment_variables.extend(load_env_file(Path::new(env_file))?);
+    }
+
+    if let Some(configured_environment_variables) = &self.config.environment_variables {
+      environment_variables.extend(configured_environment_variables.clone());
+    }
+
+    for value in environment_variables.values_mut() {
+      *value = resolve_secret(value)?;
+    }
+
+    Ok(environment_variables)
+  }
+
+  /// The log file this instance was last started with.
+  pub fn get_log_file(&self) -> Option<String> {
+    self.log_file_path.clone()
+  }
+
+  /// The log file to start this instance with, with the `{name}`, `{date}` and instance
+  /// placeholders replaced so each start and replica can write to its own file, and stamped with
+  /// the start time for `new_file_per_start`. `{pid}` is left for once the process is created.
+  fn resolve_log_file(&self) -> Option<String> {
+    let log_file = self.apply_instance_template(self.config.log_file.as_ref()?)
+      .replace("{date}", &Local::now().format("%Y-%m-%d").to_string());
+
+    if self.config.log_mode != Some(LogMode::NewFilePerStart) {
+      return Some(log_file);
+    }
+
+    let log_file_path = Path::new(&log_file);
+    let start_stamp = Local::now().format("%Y%m%d-%H%M%S");
+    let file_stem = log_file_path.file_stem().unwrap_or_default().to_string_lossy();
+
+    let stamped_file_name = match log_file_path.extension() {
+      Some(extension) => format!("{}.{}.{}", file_stem, start_stamp, extension.to_string_lossy()),
+      None => format!("{}.{}", file_stem, start_stamp),
+    };
+
+    Some(log_file_path.with_file_name(stamped_file_name).to_string_lossy().to_string())
+  }
+
+  /// Port allocated to this instance: the configured `base_port` offset by the instance index,
+  /// none when that is beyond the last port, which validation rejects.
+  pub fn get_port(&self) -> Option<u16> {
+    let instance_index = u16::try_from(self.instance_index).ok()?;
+    self.config.base_port?.checked_add(instance_index)
+  }
+
+  /// Variables identifying the instance, so replicas can tell themselves apart. A process without
+  /// replicas gets none.
+  pub fn get_instance_environment_variables(&self) -> HashMap<String, String> {
+    let mut environment_variables = HashMap::<String, String>::new();
+    if self.config.get_replicas() <= 1 {
+      return environment_variables;
+    }
+
+    environment_variables.insert(String::from("ORCH_INSTANCE_INDEX"), self.instance_index.to_string());
+    environment_variables.insert(String::from("ORCH_INSTANCE_ID"), self.id.clone());
+
+    if let Some(port) = self.get_port() {
+      environment_variables.insert(String::from("ORCH_INSTANCE_PORT"), port.to_string());
+    }
+
+    environment_variables
+  }
+
+  /// Opens the log file the output of the process is written to, or, when it can't be, e.g. because
+  /// a stale handle or an antivirus has it locked, the first of its fallbacks that can be,
+  /// reporting it.
+  fn open_log_file_with_fallback(&self, log_file: &str, log_mode: LogMode, log_rotation: &LogRotation) -> Result<(String, RotatingLogFile), Box<dyn Error>> {
+    let is_truncated = log_mode == LogMode::Truncate;
+
+    let open_error = match RotatingLogFile::open(Path::new(log_file), log_rotation.clone(), is_truncated) {
+      Ok(opened_log_file) => return Ok((log_file.to_string(), opened_log_file)),
+      Err(open_error) => open_error,
+    };
+
+    for fallback_index in 1..=MAX_LOG_FILE_FALLBACKS {
+      let fallback_log_file = get_fallback_log_file(log_file, fallback_index);
+
+      if let Ok(opened_log_file) = RotatingLogFile::open(Path::new(&fallback_log_file), log_rotation.clone(), is_truncated) {
+        self.os_handler_context.sender.send(Event::ProcessLogFileFallback(
+          self.id.clone(),
+          log_file.to_string(),
+          fallback_log_file.clone(),
+          open_error.to_string())).unwrap();
+
+        return Ok((fallback_log_file, opened_log_file));
+      }
+    }
+
+    Err(Box::new(OrchestratorError::LogFileOpenFailed(log_file.to_string(), open_error)))
+  }
+
+  /// Token to start the process with, when it isn't to run with the orchestrator's own: that of
+  /// the `run_as` account, restricted with `restricted_token` and labeled with `integrity_level`,
+  /// with the profile of the account when it is to be loaded. In the `interactive_session`, it is
+  /// that of the user logged on to the console, unless the process is to run as another account.
+  fn get_process_token(&self) -> Result<(Option<ProcessToken>, Option<UserProfile>), Box<dyn Error>> {
+    let mut process_token = self.config.run_as.as_ref().map(|x| x.logon()).transpose()?;
+    let mut user_profile = None::<UserProfile>;
+
+    if self.config.interactive_session.unwrap_or(false) {
+      let session_id = get_active_console_session_id().ok_or(OrchestratorError::NoInteractiveSession())?;
+
+      match &process_token {
+        Some(process_token) => process_token.set_session(session_id)?,
+        None => {
+          process_token = Some(ProcessToken::query_session_user(session_id).map_err(|_| OrchestratorError::NoInteractiveSession())?);
+        }
+      }
+    }
+
+    if let (Some(run_as), Some(logon_token)) = (&self.config.run_as, &process_token) {
+      // In the interactive session, the process runs on the desktop of the user logged on to it.
+      if !self.config.interactive_session.unwrap_or(false) {
+        logon_token.grant_desktop_access()
+          .map_err(|grant_error| OrchestratorError::DesktopAccessGrantFailed(run_as.get_account_name(), grant_error))?;
+      }
+
+      if run_as.load_profile.unwrap_or(false) {
+        user_profile = Some(UserProfile::load(logon_token, &run_as.username)
+          .map_err(|load_error| OrchestratorError::UserProfileLoadFailed(run_as.get_account_name(), load_error))?);
+      }
+    }
+
+    if self.config.restricted_token.unwrap_or(false) {
+      let unrestricted_token = match process_token {
+        Some(process_token) => process_token,
+        None => ProcessToken::open_current()?,
+      };
+
+      process_token = Some(unrestricted_token.restrict()?);
+    }
+
+    if let Some(integrity_level) = self.config.integrity_level {
+      let unlabeled_token = match process_token {
+        Some(process_token) => process_token,
+        None => ProcessToken::open_current()?,
+      };
+
+      process_token = Some(unlabeled_token.with_integrity_level(integrity_level)?);
+    }
+
+    Ok((process_token, user_profile))
+  }
+
+  /// AppContainer to sandbox the process in, named after it unless configured.
+  fn get_app_container(&self) -> Result<Option<AppContainer>, Box<dyn Error>> {
+    let app_container_config = match &self.config.app_container {
+      Some(app_container_config) => app_container_config,
+      None => return Ok(None),
+    };
+
+    let name = app_container_config.get_name(&self.config.name);
+    let capabilities = app_container_config.capabilities.clone().unwrap_or_default();
+
+    AppContainer::create(&name, &capabilities)
+      .map(Some)
+      .map_err(|create_error| Box::new(OrchestratorError::AppContainerCreateFailed(name, create_error)).into())
+  }
+
+  /// The executable followed by the arguments, each quoted so the process parses it back as it is
+  /// configured, and by the `raw_arguments` as they are, run through the `shell` when there is one.
+  /// `{env:NAME}` is replaced with the variables the process is started with.
+  fn get_command_line(&self, executable_path: &Path, environment_variables: &HashMap<String, String>) -> Result<String, Box<dyn Error>> {
+    let mut arguments = String::new();
+    let is_run_through_cmd = self.config.get_shell() == Some(Shell::Cmd);
+
+    if let Some(configured_arguments) = &self.config.arguments {
+      for argument in configured_arguments {
+        arguments.push(' ');
+        let argument = resolve_secret(&apply_environment_template(&self.apply_instance_template(argument), environment_variables))?;
+
+        // cmd parses the arguments itself, so they are quoted as it expects rather than as programs do.
+        match is_run_through_cmd {
+          true => arguments.push_str(&quote_cmd_argument(&argument)?),
+          false => arguments.push_str(&quote_argument(&argument)),
+        }
+      }
+    }
+
+    if let Some(raw_arguments) = &self.config.raw_arguments {
+      arguments.push(' ');
+      let raw_arguments = apply_environment_template(&self.apply_instance_template(raw_arguments), environment_variables);
+      arguments.push_str(&resolve_secret(&raw_arguments)?);
+    }
+
+    match self.config.get_shell() {
+      Some(shell) if self.config.is_executable_a_path() => Ok(shell.get_command_line(&executable_path.to_string_lossy(), &arguments)),
+      Some(shell) => {
+        let command = apply_environment_template(&self.apply_instance_template(&self.config.executable), environment_variables);
+        Ok(shell.get_command_line(&command, &arguments))
+      }
+      // Paths can't contain quotes, so the executable is quoted without escaping.
+      None => Ok(format!("\"{}\"{}", executable_path.display(), arguments)),
+    }
+  }
+
+  /// Job object enforcing the configured resource limits, when there are any, or holding the
+  /// processes the `shell` starts, so they are stopped along with it.
+  fn get_process_job(&self) -> Result<Option<ProcessJob>, Box<dyn Error>> {
+    if self.config.get_shell().is_none()
+      && self.config.cpu_limit_percent.is_none()
+      && self.config.memory_limit_mbs.is_none()
+      && self.config.max_child_processes.is_none() {
+      return Ok(None);
+    }
+
+    let job = ProcessJob::create().map_err(OrchestratorError::JobCreateFailed)?;
+
+    if let Some(cpu_limit_percent) = self.config.cpu_limit_percent {
+      job.set_cpu_rate_limit(cpu_limit_percent).map_err(OrchestratorError::JobCreateFailed)?;
+    }
+
+    if let Some(memory_limit_mbs) = self.config.memory_limit_mbs {
+      job.set_memory_limit(memory_limit_mbs).map_err(OrchestratorError::JobCreateFailed)?;
+    }
+
+    if let Some(max_child_processes) = self.config.max_child_processes {
+      // The process itself counts as one of the job's.
+      job.set_active_process_limit(max_child_processes.saturating_add(1)).map_err(OrchestratorError::JobCreateFailed)?;
+    }
+
+    if let Some(job_breakaway) = self.config.job_breakaway {
+      job.set_breakaway(job_breakaway).map_err(OrchestratorError::JobCreateFailed)?;
+    }
+
+    Ok(Some(job))
+  }
+
+  /// Name the output of the process is prefixed with on the orchestrator's console, with the
+  /// instance index when there are replicas, e.g. `api#2`.
+  fn get_console_name(&self) -> String {
+    match self.config.get_replicas() {
+      1 => self.config.name.clone(),
+      _ => format!("{}#{}", &self.config.name, self.instance_index),
+    }
+  }
+
+  /// Replaces `{name}`, `{instance}`, `{id}`, `{port}` and `{config_dir}` with the values of this
+  /// instance.
+  pub fn apply_instance_template(&self, value: &str) -> String {
+    let mut result = value
+      .replace("{name}", &self.config.name)
+      .replace("{instance}", self.instance_index.to_string().as_str())
+      .replace("{id}", self.id.as_str());
+
+    if result.contains("{config_dir}") {
+      // Configs from a remote bundle are relative to the orchestrator's directory, its current one.
+      let config_directory = self.config.config_directory.clone()
+        .unwrap_or_else(|| std::env::current_dir().unwrap_or_default().to_string_lossy().to_string());
+      result = result.replace("{config_dir}", &config_directory);
+    }
+
+    if let Some(port) = self.get_port() {
+      result = result.replace("{port}", port.to_string().as_str());
+    }
+
+    result
+  }
+
   pub fn is_running(&self) -> bool {
     if let Some(process_handle) = self.process_handle {
       let mut exit_code = 0u32;
@@ -306,6 +1243,13 @@ impl StatefulProcess {
 
     info!("Process [{}]: Terminating process", &self.id);
 
+    // Along with the processes it started, unless they left the job.
+    if let Some(job) = &self.job {
+      if let Err(terminate_error) = job.terminate(0) {
+        error!("Process [{}]: Could not terminate its job object: {}", &self.id, terminate_error);
+      }
+    }
+
     unsafe {
       TerminateProcess(process_handle, 0);
       CloseHandle(process_handle);
@@ -315,17 +1259,44 @@ impl StatefulProcess {
     Ok(())
   }
 
-  pub fn on_stopped(&mut self) -> VoidResult {
-    if let Some(log_file_handle) = self.log_file_handle {
+  /// Suspends every thread of the process, until it is resumed.
+  pub fn suspend(&mut self) -> VoidResult {
+    if let Some(process_handle) = self.process_handle {
+      info!("Process [{}]: Suspending process", &self.id);
+
+      unsafe {
+        let status = NtSuspendProcess(process_handle);
+        if status < 0 {
+          return Err(Box::new(std::io::Error::from_raw_os_error(RtlNtStatusToDosError(status) as i32)));
+        }
+      }
+    }
+
+    Ok(())
+  }
+
+  pub fn resume(&mut self) -> VoidResult {
+    if let Some(process_handle) = self.process_handle {
+      info!("Process [{}]: Resuming process", &self.id);
+
       unsafe {
-        CloseHandle(log_file_handle);
-        self.log_file_handle = None;
+        let status = NtResumeProcess(process_handle);
+        if status < 0 {
+          return Err(Box::new(std::io::Error::from_raw_os_error(RtlNtStatusToDosError(status) as i32)));
+        }
       }
     }
 
     Ok(())
   }
 
+  pub fn on_stopped(&mut self) -> VoidResult {
+    self.user_profile = None;
+    self.job = None;
+
+    Ok(())
+  }
+
   pub fn poll(&mut self) -> VoidResult {
     let duration = self.get_duration_in_seconds();
     if let Some(duration_seconds) = duration {
@@ -339,15 +1310,36 @@ impl StatefulProcess {
       // info!("Process [{}]: Memory {}", self.id, memory_usage_mbs);
     }
 
+    if let Some(cpu_time) = self.get_cpu_time() {
+      let sampled_at = Instant::now();
+
+      if let Some((previous_sampled_at, previous_cpu_time)) = self.cpu_time_sample {
+        let elapsed_time = sampled_at.duration_since(previous_sampled_at).as_secs_f64() * 10_000_000f64;
+        let processor_count = std::thread::available_parallelism().map(|x| x.get()).unwrap_or(1) as f64;
+
+        if elapsed_time > 0f64 {
+          self.cpu_percent = Some(cpu_time.saturating_sub(previous_cpu_time) as f64 / elapsed_time / processor_count * 100f64);
+        }
+      }
+
+      self.cpu_time_sample = Some((sampled_at, cpu_time));
+    }
+
+    if let Some(file_watcher) = &mut self.file_watcher {
+      if file_watcher.poll() {
+        self.watched_files_changed_at = Some(Instant::now());
+      }
+    }
+
     Ok(())
   }
 
-  pub fn is_recycle_required(&self) -> bool {
+  /// Why the process needs recycling, when one of its recycle conditions has been reached.
+  pub fn get_recycle_reason(&self) -> Option<String> {
     if let Some(limit_memory_mbs) = self.config.recycle_on_memory_mbs {
       if let Some(current_memory_mbs) = self.memory_usage_mbs {
         if current_memory_mbs > limit_memory_mbs {
-          info!("Process [{}]: Memory {}MB has reached recycle threshold {}MB", &self.id, current_memory_mbs, limit_memory_mbs);
-          return true
+          return Some(format!("Memory {}MB has reached recycle threshold {}MB", current_memory_mbs, limit_memory_mbs))
         }
       }
     }
@@ -355,13 +1347,37 @@ impl StatefulProcess {
     if let Some(limit_duration_secs) = self.config.recycle_on_duration_secs {
       if let Some(current_duration_secs) = self.duration_secs {
         if current_duration_secs > limit_duration_secs {
-          info!("Process [{}]: Uptime of {} seconds has reached recycle threshold of {} seconds", &self.id, current_duration_secs, limit_duration_secs);
-          return true
+          return Some(format!("Uptime of {} seconds has reached recycle threshold of {} seconds", current_duration_secs, limit_duration_secs))
         }
       }
     }
 
-    false
+    if let Some(watched_files_changed_at) = self.watched_files_changed_at {
+      let debounce_secs = self.config.restart_on_change_debounce_secs.unwrap_or(DEFAULT_RESTART_ON_CHANGE_DEBOUNCE_SECS);
+      if watched_files_changed_at.elapsed() >= Duration::from_secs_f64(debounce_secs) {
+        return Some(String::from("Watched files have changed"))
+      }
+    }
+
+    None
+  }
+
+  pub fn get_pid(&self) -> Option<u32> {
+    self.pid
+  }
+
+  /// Exit code of the process once it has exited.
+  pub fn get_exit_code(&self) -> Option<u32> {
+    let process_handle = self.process_handle?;
+    let mut exit_code = 0u32;
+
+    unsafe {
+      if GetExitCodeProcess(process_handle, &mut exit_code) == 0 || exit_code == STILL_ACTIVE {
+        return None
+      }
+    }
+
+    Some(exit_code)
   }
 
   pub fn get_duration_in_seconds(&self) -> Option<f64> {
@@ -398,6 +1414,26 @@ impl StatefulProcess {
     }
   }
 
+  /// Kernel and user time the process has used, in 100 nanosecond intervals.
+  fn get_cpu_time(&self) -> Option<u64> {
+    let process_handle = self.process_handle?;
+
+    unsafe {
+      let mut creation_time: FILETIME = std::mem::zeroed::<FILETIME>();
+      let mut exit_time: FILETIME = std::mem::zeroed::<FILETIME>();
+      let mut kernel_time: FILETIME = std::mem::zeroed::<FILETIME>();
+      let mut user_time: FILETIME = std::mem::zeroed::<FILETIME>();
+
+      if GetProcessTimes(process_handle, &mut creation_time, &mut exit_time, &mut kernel_time, &mut user_time) == 0 {
+        return None;
+      }
+
+      let to_u64 = |file_time: FILETIME| ((file_time.dwHighDateTime as u64) << 32) | file_time.dwLowDateTime as u64;
+
+      Some(to_u64(kernel_time) + to_u64(user_time))
+    }
+  }
+
   pub fn get_memory_usage(&self) -> Option<f64> {
     if self.process_handle.is_none() {
       return None;
@@ -431,6 +1467,112 @@ impl StatefulProcess {
   }
 }
 
+/// Replaces each `{env:NAME}` with the value of the variable, whatever the case of its name, leaving
+/// those of variables that aren't set as they are.
+fn apply_environment_template(value: &str, environment_variables: &HashMap<String, String>) -> String {
+  let mut result = String::new();
+  let mut rest = value;
+
+  while let Some(start) = rest.find("{env:") {
+    let end = match rest[start..].find('}') {
+      Some(end) => start + end,
+      None => break,
+    };
+
+    let name = &rest[start + "{env:".len()..end];
+    let environment_value = environment_variables.iter().find(|(x, _)| x.eq_ignore_ascii_case(name)).map(|(_, x)| x);
+
+    result.push_str(&rest[..start]);
+    match environment_value {
+      Some(environment_value) => result.push_str(environment_value),
+      None => result.push_str(&rest[start..=end]),
+    }
+
+    rest = &rest[end + 1..];
+  }
+
+  result.push_str(rest);
+  result
+}
+
+/// Adds the variables to the environment, replacing those of the same name whatever their case, as
+/// Windows ignores it, e.g. a configured `PATH` replaces the inherited `Path`.
+fn merge_environment_variables(environment_variables: &mut HashMap<String, String>, overlay: HashMap<String, String>) {
+  for (name, value) in overlay {
+    environment_variables.retain(|x, _| !x.eq_ignore_ascii_case(&name));
+    environment_variables.insert(name, value);
+  }
+}
+
+/// An inheritable handle the process reads its standard input from.
+fn open_stdin_handle(stdin_source: &StdinSource) -> std::io::Result<HANDLE> {
+  let file = match stdin_source {
+    StdinSource::Null => File::open("NUL")?,
+    StdinSource::File(path) => File::open(path)?,
+    StdinSource::Closed => unsafe {
+      let mut read_handle: HANDLE = null_mut();
+      let mut write_handle: HANDLE = null_mut();
+
+      if CreatePipe(&mut read_handle, &mut write_handle, null_mut(), 0) == 0 {
+        return Err(std::io::Error::last_os_error());
+      }
+
+      CloseHandle(write_handle);
+      File::from_raw_handle(read_handle as RawHandle)
+    },
+  };
+
+  let stdin_handle = file.into_raw_handle() as HANDLE;
+
+  unsafe {
+    if SetHandleInformation(stdin_handle, HANDLE_FLAG_INHERIT, HANDLE_FLAG_INHERIT) == 0 {
+      let inherit_error = std::io::Error::last_os_error();
+      CloseHandle(stdin_handle);
+      return Err(inherit_error);
+    }
+  }
+
+  Ok(stdin_handle)
+}
+
+/// The variables as a Unicode environment block: `NAME=value` strings ending with a null character,
+/// sorted by name whatever their case as Windows expects, followed by another null character. An
+/// empty block is two null characters.
+fn get_environment_block(environment_variables: &HashMap<String, String>) -> Vec<u16> {
+  let mut names = environment_variables.keys().collect::<Vec<&String>>();
+  names.sort_by_key(|x| x.to_uppercase());
+
+  let mut environment_block = Vec::<u16>::new();
+  if names.is_empty() {
+    environment_block.push(0);
+  }
+
+  for name in names {
+    environment_block.extend(format!("{}={}", name, &environment_variables[name]).encode_utf16());
+    environment_block.push(0);
+  }
+
+  environment_block.push(0);
+  environment_block
+}
+
+fn to_wide(value: &str) -> Vec<u16> {
+  value.encode_utf16().chain(std::iter::once(0)).collect()
+}
+
+/// Alternate log file next to the log file, e.g. `worker.fallback-1.log`.
+fn get_fallback_log_file(log_file: &str, fallback_index: usize) -> String {
+  let log_file_path = Path::new(log_file);
+  let file_stem = log_file_path.file_stem().unwrap_or_default().to_string_lossy();
+
+  let fallback_file_name = match log_file_path.extension() {
+    Some(extension) => format!("{}.fallback-{}.{}", file_stem, fallback_index, extension.to_string_lossy()),
+    None => format!("{}.fallback-{}", file_stem, fallback_index),
+  };
+
+  log_file_path.with_file_name(fallback_file_name).to_string_lossy().to_string()
+}
+
 unsafe extern "system" fn wait_or_timer_callback(lp_parameter: PVOID, _timer_or_wait_fired: BOOLEAN) {
   // Get an owned mutable reference here from the pointer passed.
   let mut os_handler_context = Box::from_raw(lp_parameter as *mut StatefulProcessOsHandlerContext);
@@ -450,4 +1592,3 @@ unsafe extern "system" fn wait_or_timer_callback(lp_parameter: PVOID, _timer_or_
   // This is why we prevent de-allocation here.
   Box::leak(os_handler_context);
 }
-
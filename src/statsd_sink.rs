@@ -0,0 +1,66 @@
+use crate::orchestrator_config::StatsdConfig;
+use crate::stateful_process::StatefulProcess;
+
+use std::error::Error;
+use std::net::UdpSocket;
+use log::trace;
+
+const DEFAULT_STATSD_HOST: &str = "127.0.0.1";
+const DEFAULT_STATSD_PORT: u16 = 8125;
+const DEFAULT_STATSD_PREFIX: &str = "process_orchestrator";
+
+/// Pushes per-process gauges over UDP in StatsD format, with DogStatsD tags naming the process.
+/// Sending is fire and forget, so a missing agent never slows the event pump down.
+pub struct StatsdSink {
+  socket: UdpSocket,
+  address: String,
+  prefix: String,
+  tags: Vec<String>,
+}
+
+impl StatsdSink {
+  pub fn new(config: &StatsdConfig) -> Result<Self, Box<dyn Error>> {
+    let socket = UdpSocket::bind("0.0.0.0:0")?;
+    socket.set_nonblocking(true)?;
+
+    let address = format!(
+      "{}:{}",
+      config.host.as_deref().unwrap_or(DEFAULT_STATSD_HOST),
+      config.port.unwrap_or(DEFAULT_STATSD_PORT));
+
+    Ok(Self {
+      socket,
+      address,
+      prefix: config.prefix.clone().unwrap_or_else(|| String::from(DEFAULT_STATSD_PREFIX)),
+      tags: config.tags.clone().unwrap_or_default(),
+    })
+  }
+
+  pub fn send_process_metrics(&self, process: &StatefulProcess) {
+    let mut tags = self.tags.clone();
+    tags.push(format!("process:{}", &process.config.name));
+    tags.push(format!("instance:{}", process.instance_index));
+
+    let mut lines = Vec::<String>::new();
+
+    if let Some(memory_usage_mbs) = process.memory_usage_mbs {
+      lines.push(self.format_gauge("memory_mbs", memory_usage_mbs, &tags));
+    }
+
+    if let Some(duration_secs) = process.duration_secs {
+      lines.push(self.format_gauge("uptime_secs", duration_secs, &tags));
+    }
+
+    if lines.is_empty() {
+      return;
+    }
+
+    if let Err(send_error) = self.socket.send_to(lines.join("\n").as_bytes(), &self.address) {
+      trace!("StatsdSink: Could not send to [{}]: {}", &self.address, send_error);
+    }
+  }
+
+  fn format_gauge(&self, name: &str, value: f64, tags: &[String]) -> String {
+    format!("{}.{}:{}|g|#{}", &self.prefix, name, value, tags.join(","))
+  }
+}
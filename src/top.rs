@@ -0,0 +1,272 @@
+use crate::commands::format_duration;
+use crate::control_server::{ControlRequest, ProcessStatus, send_control_request};
+
+use std::cmp::Ordering;
+use std::error::Error;
+use std::io::Write;
+use std::time::{Duration, Instant};
+use crossterm::cursor::{Hide, MoveTo, Show};
+use crossterm::event::{Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers};
+use crossterm::style::{Attribute, Print, SetAttribute};
+use crossterm::terminal::{Clear, ClearType, EnterAlternateScreen, LeaveAlternateScreen};
+use crossterm::{execute, queue};
+
+/// How often the processes are fetched from the running orchestrator.
+const TOP_REFRESH_INTERVAL: Duration = Duration::from_secs(1);
+
+const TOP_HEADERS: [&str; 8] = ["NAME", "INSTANCE", "PID", "STATE", "CPU", "MEMORY", "UPTIME", "RESTARTS"];
+const TOP_KEYS: &str = "Up/Down select  </> sort  s start  x stop  r restart  q quit";
+
+/// Columns the processes can be sorted by, the numeric ones largest first.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum SortColumn {
+  Name,
+  Cpu,
+  Memory,
+  Uptime,
+  Restarts,
+}
+
+const SORT_COLUMNS: [SortColumn; 5] = [SortColumn::Name, SortColumn::Cpu, SortColumn::Memory, SortColumn::Uptime, SortColumn::Restarts];
+
+struct TopView {
+  process_statuses: Vec<ProcessStatus>,
+  sort_column: SortColumn,
+  selected_index: usize,
+  message: Option<String>,
+}
+
+/// Puts the console in raw mode on the alternate screen, restoring it when dropped.
+struct RawConsole;
+
+impl RawConsole {
+  fn enter() -> Result<Self, Box<dyn Error>> {
+    // In raw mode, Ctrl+C arrives as a key rather than ending the process with the console left
+    // in raw mode.
+    crossterm::terminal::enable_raw_mode().map_err(|x| format!("top needs to run in a console: {}", x))?;
+
+    if let Err(enter_error) = execute!(std::io::stdout(), EnterAlternateScreen, Hide) {
+      let _ = crossterm::terminal::disable_raw_mode();
+      return Err(Box::new(enter_error));
+    }
+
+    Ok(Self)
+  }
+
+  /// Columns and rows of the visible part of the console.
+  fn get_size(&self) -> (usize, usize) {
+    crossterm::terminal::size().map(|(columns, rows)| (columns as usize, rows as usize)).unwrap_or((80, 25))
+  }
+
+  /// Waits up to the timeout for input, returning the keys pressed.
+  fn read_keys(&self, timeout: Duration) -> std::io::Result<Vec<KeyEvent>> {
+    let mut keys = Vec::<KeyEvent>::new();
+
+    if !crossterm::event::poll(timeout)? {
+      return Ok(keys);
+    }
+
+    // Read what else arrived with the first key, without waiting for more.
+    loop {
+      if let Event::Key(key) = crossterm::event::read()? {
+        if key.kind != KeyEventKind::Release {
+          keys.push(key);
+        }
+      }
+
+      if !crossterm::event::poll(Duration::ZERO)? {
+        return Ok(keys);
+      }
+    }
+  }
+}
+
+impl Drop for RawConsole {
+  fn drop(&mut self) {
+    let _ = execute!(std::io::stdout(), Show, LeaveAlternateScreen);
+    let _ = crossterm::terminal::disable_raw_mode();
+  }
+}
+
+/// Has the console interpret the escape sequences written to it, for output redrawn in place.
+pub fn enable_virtual_terminal_output() -> Result<(), Box<dyn Error>> {
+  if !crossterm::ansi_support::supports_ansi() {
+    return Err("Console does not support virtual terminal sequences".into());
+  }
+
+  Ok(())
+}
+
+/// Shows the processes of the orchestrator listening on the pipe, refreshed every second, until
+/// `q`, Escape or Ctrl+C is pressed.
+pub fn run_top(pipe_name: &str) -> Result<(), Box<dyn Error>> {
+  let mut view = TopView {
+    process_statuses: get_process_statuses(pipe_name)?,
+    sort_column: SortColumn::Name,
+    selected_index: 0,
+    message: None,
+  };
+
+  let console = RawConsole::enter()?;
+  let mut refreshed_at = Instant::now();
+
+  loop {
+    view.sort();
+    render(&console, &view)?;
+
+    for key in console.read_keys(TOP_REFRESH_INTERVAL.saturating_sub(refreshed_at.elapsed()))? {
+      let is_ctrl_pressed = key.modifiers.contains(KeyModifiers::CONTROL);
+
+      match key.code {
+        KeyCode::Char('q') | KeyCode::Esc => return Ok(()),
+        KeyCode::Char('c') | KeyCode::Char('C') if is_ctrl_pressed => return Ok(()),
+        _ => {}
+      }
+
+      let last_index = view.process_statuses.len().saturating_sub(1);
+
+      match key.code {
+        KeyCode::Up | KeyCode::Char('k') => view.selected_index = view.selected_index.saturating_sub(1),
+        KeyCode::Down | KeyCode::Char('j') => view.selected_index = (view.selected_index + 1).min(last_index),
+        KeyCode::PageUp => view.selected_index = view.selected_index.saturating_sub(10),
+        KeyCode::PageDown => view.selected_index = (view.selected_index + 10).min(last_index),
+        KeyCode::Home => view.selected_index = 0,
+        KeyCode::End => view.selected_index = last_index,
+        KeyCode::Char(character @ ('<' | '>')) => view.cycle_sort_column(character == '>'),
+        KeyCode::Char('s') => view.control(pipe_name, |name| ControlRequest::Start { name }, "starting"),
+        KeyCode::Char('x') => view.control(pipe_name, |name| ControlRequest::Stop { name }, "stopping"),
+        KeyCode::Char('r') => view.control(pipe_name, |name| ControlRequest::Restart { name }, "restarting"),
+        _ => continue,
+      }
+    }
+
+    if refreshed_at.elapsed() >= TOP_REFRESH_INTERVAL {
+      refreshed_at = Instant::now();
+
+      match get_process_statuses(pipe_name) {
+        Ok(process_statuses) => view.process_statuses = process_statuses,
+        Err(status_error) => view.message = Some(status_error.to_string()),
+      }
+
+      view.selected_index = view.selected_index.min(view.process_statuses.len().saturating_sub(1));
+    }
+  }
+}
+
+impl TopView {
+  fn sort(&mut self) {
+    // Keep the same process selected as the order changes.
+    let selected_key = self.process_statuses.get(self.selected_index).map(|x| (x.name.clone(), x.instance_index));
+
+    let by_name = |left: &ProcessStatus, right: &ProcessStatus| left.name.cmp(&right.name).then(left.instance_index.cmp(&right.instance_index));
+    let by_value_descending = |left: Option<f64>, right: Option<f64>| right.unwrap_or(-1f64).partial_cmp(&left.unwrap_or(-1f64)).unwrap_or(Ordering::Equal);
+
+    match self.sort_column {
+      SortColumn::Name => self.process_statuses.sort_by(by_name),
+      SortColumn::Cpu => self.process_statuses.sort_by(|left, right| by_value_descending(left.cpu_percent, right.cpu_percent).then(by_name(left, right))),
+      SortColumn::Memory => self.process_statuses.sort_by(|left, right| by_value_descending(left.memory_mbs, right.memory_mbs).then(by_name(left, right))),
+      SortColumn::Uptime => self.process_statuses.sort_by(|left, right| by_value_descending(left.uptime_secs, right.uptime_secs).then(by_name(left, right))),
+      SortColumn::Restarts => self.process_statuses.sort_by(|left, right| right.restarts.cmp(&left.restarts).then(by_name(left, right))),
+    }
+
+    if let Some((name, instance_index)) = selected_key {
+      if let Some(index) = self.process_statuses.iter().position(|x| x.name == name && x.instance_index == instance_index) {
+        self.selected_index = index;
+      }
+    }
+  }
+
+  fn cycle_sort_column(&mut self, is_forward: bool) {
+    let index = SORT_COLUMNS.iter().position(|x| *x == self.sort_column).unwrap_or_default();
+    let next_index = if is_forward { index + 1 } else { index + SORT_COLUMNS.len() - 1 };
+    self.sort_column = SORT_COLUMNS[next_index % SORT_COLUMNS.len()];
+  }
+
+  fn control(&mut self, pipe_name: &str, get_request: impl Fn(String) -> ControlRequest, action: &str) {
+    let name = match self.process_statuses.get(self.selected_index) {
+      Some(process_status) => process_status.name.clone(),
+      None => return,
+    };
+
+    self.message = Some(match send_control_request(pipe_name, &get_request(name.clone())) {
+      Ok(_) => format!("Process [{}] {}", name, action),
+      Err(control_error) => control_error.to_string(),
+    });
+  }
+
+  fn get_rows(&self) -> Vec<Vec<String>> {
+    self.process_statuses
+      .iter()
+      .map(|x| vec![
+        x.name.clone(),
+        x.instance_index.to_string(),
+        x.pid.map(|x| x.to_string()).unwrap_or_default(),
+        x.state.clone(),
+        x.cpu_percent.map(|x| format!("{:.1}%", x)).unwrap_or_default(),
+        x.memory_mbs.map(|x| format!("{:.1}MB", x)).unwrap_or_default(),
+        x.uptime_secs.map(format_duration).unwrap_or_default(),
+        x.restarts.to_string(),
+      ])
+      .collect()
+  }
+}
+
+fn get_process_statuses(pipe_name: &str) -> Result<Vec<ProcessStatus>, Box<dyn Error>> {
+  Ok(send_control_request(pipe_name, &ControlRequest::Status)?.processes.unwrap_or_default())
+}
+
+/// Redraws the whole screen: a title, the table with the selected process highlighted and
+/// scrolled into view, and the keys with the result of the last action.
+fn render(console: &RawConsole, view: &TopView) -> std::io::Result<()> {
+  let (columns, rows) = console.get_size();
+  let table_rows = view.get_rows();
+
+  let widths = TOP_HEADERS
+    .iter()
+    .enumerate()
+    .map(|(index, header)| table_rows.iter().map(|x| x[index].len()).chain(std::iter::once(header.len())).max().unwrap_or_default())
+    .collect::<Vec<usize>>();
+
+  let format_row = |values: &[String]| {
+    let row = values
+      .iter()
+      .zip(&widths)
+      .map(|(value, width)| format!("{:<width$}", value, width = width))
+      .collect::<Vec<String>>()
+      .join("  ");
+
+    format!("{:<width$.width$}", row, width = columns)
+  };
+
+  let visible_row_count = rows.saturating_sub(4).max(1);
+  let first_row_index = view.selected_index.saturating_sub(visible_row_count - 1);
+
+  let mut stdout = std::io::stdout();
+  queue!(stdout, MoveTo(0, 0))?;
+
+  let title = format!("Process Orchestrator - {} processes - sorted by {:?}", view.process_statuses.len(), view.sort_column);
+  queue!(stdout, Print(format!("{:<width$.width$}\r\n", title, width = columns)))?;
+
+  let headers = TOP_HEADERS.iter().map(|x| x.to_string()).collect::<Vec<String>>();
+  queue!(stdout, SetAttribute(Attribute::Bold), Print(format_row(&headers)), SetAttribute(Attribute::Reset), Print("\r\n"))?;
+
+  for (index, table_row) in table_rows.iter().enumerate().skip(first_row_index).take(visible_row_count) {
+    if index == view.selected_index {
+      queue!(stdout, SetAttribute(Attribute::Reverse), Print(format_row(table_row)), SetAttribute(Attribute::Reset), Print("\r\n"))?;
+    }
+    else {
+      queue!(stdout, Print(format_row(table_row)), Print("\r\n"))?;
+    }
+  }
+
+  queue!(stdout, Clear(ClearType::FromCursorDown))?;
+
+  let footer = match &view.message {
+    Some(message) => format!("{}  |  {}", TOP_KEYS, message),
+    None => TOP_KEYS.to_string(),
+  };
+  let footer_row = rows.saturating_sub(1) as u16;
+  queue!(stdout, MoveTo(0, footer_row), SetAttribute(Attribute::Reverse), Print(format!("{:<width$.width$}", footer, width = columns)), SetAttribute(Attribute::Reset))?;
+
+  stdout.flush()
+}
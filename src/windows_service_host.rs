@@ -1,23 +1,51 @@
+use crate::audit_log::AuditLog;
+use crate::cli_options::CliOptions;
 use crate::errors::OrchestratorError;
+use crate::instance_lock::InstanceLock;
+use crate::performance_counters::{install_performance_counters, uninstall_performance_counters};
+use crate::event_log::{EventLog, install_event_source, uninstall_event_source, SERVICE_STARTED_EVENT_ID, SERVICE_STOPPED_EVENT_ID};
+use crate::orchestrator_config::{OrchestratorConfig, ServiceRecoveryConfig, load_orchestrator_config};
 
-use std::sync::mpsc::channel;
+use std::sync::{Arc, Mutex};
+use std::sync::mpsc::{channel, Receiver, RecvTimeoutError};
 use std::error::Error;
-use std::ffi::OsString;
+use std::ffi::{OsStr, OsString};
 use std::time::Duration;
-use log::{error, info};
+use log::{error, info, warn};
 use windows_service::define_windows_service;
 use windows_service::service_dispatcher;
 use windows_service::service_control_handler;
-use windows_service::service_control_handler::ServiceControlHandlerResult;
-use windows_service::service::{ServiceExitCode, ServiceControlAccept, ServiceState, ServiceType, ServiceStatus, ServiceControl};
+use windows_service::service_control_handler::{ServiceControlHandlerResult, ServiceStatusHandle};
+use windows_service::service::{ServiceExitCode, ServiceControlAccept, ServiceState, ServiceType, ServiceStatus, ServiceControl, ServiceAccess, ServiceErrorControl, ServiceInfo, ServiceStartType, ServiceDependency, ServiceAction, ServiceActionType, ServiceFailureActions, ServiceFailureResetPeriod, PowerEventParam, SessionChangeReason};
+use windows_service::service_manager::{ServiceManager, ServiceManagerAccess};
+use structopt::StructOpt;
 use crate::event_pump::{EventPump, Event};
+use crate::stateful_process::SessionChangeEvent;
+
+/// How long the SCM is told to wait for the next progress report while the processes are stopping.
+const STOP_PENDING_WAIT_HINT: Duration = Duration::from_secs(10);
+
+/// Description of the service when none is configured.
+pub const DEFAULT_SERVICE_DESCRIPTION: &str = "Keeps processes up and running using desired-state-configuration";
+
+/// `sc control process-orchestrator 128` reloads the configs.
+pub const RELOAD_CONTROL_CODE: u32 = 128;
+
+/// `sc control process-orchestrator 129` restarts the processes one at a time.
+pub const ROLLING_RESTART_CONTROL_CODE: u32 = 129;
+
+/// `sc control process-orchestrator 130` writes the status of every process to the log.
+pub const STATUS_DUMP_CONTROL_CODE: u32 = 130;
+
+/// Caller of the service controls in the audit log, which can't tell who sent them to the SCM.
+const SERVICE_CONTROL_CALLER: &str = "Service Control Manager";
 
 #[cfg(windows)]
 define_windows_service!(ffi_service_main, service_main_outer);
 
 #[cfg(windows)]
-pub fn start_windows_service() -> Result<(), OrchestratorError> {
-  let service_name = get_service_name();
+pub fn start_windows_service(cli_options: &CliOptions, orchestrator_config: &OrchestratorConfig) -> Result<(), OrchestratorError> {
+  let service_name = get_service_name(cli_options.service_name.as_deref(), orchestrator_config);
   let start_result = service_dispatcher::start(service_name, ffi_service_main);
 
   if let Err(start_error) = start_result {
@@ -45,59 +73,357 @@ fn service_main_outer(arguments: Vec<OsString>) {
 }
 
 #[cfg(windows)]
-fn service_main_inner(_arguments: Vec<OsString>) -> Result<(), Box<dyn Error>> {
-  let mut event_pump = EventPump::new();
-  let (stopped_event_sender, stopped_event_receiver) = channel();
-  let request_stop_sender = event_pump.sender.clone();
+fn service_main_inner(arguments: Vec<OsString>) -> Result<(), Box<dyn Error>> {
+  let orchestrator_config = load_orchestrator_config()?;
+  let cli_options = get_service_cli_options(arguments, &orchestrator_config)?;
+  // The name the SCM started the service as, which the instance, and so its pipe, state file and
+  // config directory, are derived from whether or not start parameters are given.
+  let service_name = get_service_name(cli_options.service_name.as_deref(), &orchestrator_config);
+  let _instance_lock = InstanceLock::acquire(&cli_options.get_config_locations()?)?;
+  let audit_log = AuditLog::new(cli_options.service_name.as_deref());
+  let mut event_pump = EventPump::new(cli_options);
+  let (stopped_event_sender, stopped_event_receiver) = channel::<()>();
+  let mut stopped_event_receiver = Some(stopped_event_receiver);
+  let request_sender = event_pump.sender.clone();
+
+  // The handler reports the paused and stop pending states itself, so it needs the status handle
+  // registered with it. It is taken away once the service has stopped.
+  let status_handle = Arc::new(Mutex::new(None::<ServiceStatusHandle>));
+  let handler_status_handle = status_handle.clone();
 
   let event_handler = move |control_event| -> ServiceControlHandlerResult {
     match control_event {
-      ServiceControl::Stop => {
-        info!("Windows service: Stop received");
-        request_stop_sender.send(Event::OrchestratorRequestStop()).unwrap();
-        stopped_event_receiver.recv().unwrap();
+      // On OS shutdown, preshutdown gives the processes the configured preshutdown timeout to stop
+      // gracefully, rather than the short window of the shutdown control.
+      ServiceControl::Stop | ServiceControl::Preshutdown => {
+        info!("Windows service: {:?} received", control_event);
+        let operation_name = if matches!(control_event, ServiceControl::Stop) { "stop" } else { "preshutdown" };
+        audit_log.record("service_control", SERVICE_CONTROL_CALLER, operation_name, None, Ok(()));
+        request_sender.send(Event::OrchestratorRequestStop()).unwrap();
+        report_stop_pending(&handler_status_handle, 1);
+
+        // The handler returns right away, so the SCM can deliver other controls, while another
+        // thread keeps it from concluding the service hung as the processes drain.
+        if let Some(stopped_event_receiver) = stopped_event_receiver.take() {
+          let progress_status_handle = handler_status_handle.clone();
+          std::thread::spawn(move || report_stop_progress(&progress_status_handle, &stopped_event_receiver));
+        }
+
         ServiceControlHandlerResult::NoError
       }
       ServiceControl::Interrogate => ServiceControlHandlerResult::NoError,
+      ServiceControl::Pause | ServiceControl::Continue => {
+        let (done_sender, done_receiver) = channel::<()>();
+        let (event, pending_state, current_state, operation_name) = match control_event {
+          ServiceControl::Pause => (Event::OrchestratorRequestPause(done_sender), ServiceState::PausePending, ServiceState::Paused, "pause"),
+          _ => (Event::OrchestratorRequestContinue(done_sender), ServiceState::ContinuePending, ServiceState::Running, "continue"),
+        };
+
+        info!("Windows service: {:?} received", control_event);
+        audit_log.record("service_control", SERVICE_CONTROL_CALLER, operation_name, None, Ok(()));
+        request_sender.send(event).unwrap();
+        report_service_state(&handler_status_handle, pending_state);
+
+        // Paused, or running again, once the event pump has suspended, or resumed, every process.
+        let done_status_handle = handler_status_handle.clone();
+        std::thread::spawn(move || {
+          done_receiver.recv().ok();
+          report_service_state(&done_status_handle, current_state);
+        });
+
+        ServiceControlHandlerResult::NoError
+      }
+      ServiceControl::PowerEvent(PowerEventParam::Suspend) => {
+        info!("Windows service: Suspending");
+        request_sender.send(Event::OrchestratorSuspending()).unwrap();
+        ServiceControlHandlerResult::NoError
+      }
+      ServiceControl::PowerEvent(PowerEventParam::ResumeAutomatic) | ServiceControl::PowerEvent(PowerEventParam::ResumeCritical) => {
+        info!("Windows service: Resumed");
+        request_sender.send(Event::OrchestratorResumed()).unwrap();
+        ServiceControlHandlerResult::NoError
+      }
+      ServiceControl::PowerEvent(_) => ServiceControlHandlerResult::NoError,
+      ServiceControl::SessionChange(session_change_param) => {
+        let session_change_event = match session_change_param.reason {
+          SessionChangeReason::ConsoleConnect => SessionChangeEvent::ConsoleConnect,
+          SessionChangeReason::ConsoleDisconnect => SessionChangeEvent::ConsoleDisconnect,
+          SessionChangeReason::RemoteConnect => SessionChangeEvent::RemoteConnect,
+          SessionChangeReason::RemoteDisconnect => SessionChangeEvent::RemoteDisconnect,
+          SessionChangeReason::SessionLogon => SessionChangeEvent::Logon,
+          SessionChangeReason::SessionLogoff => SessionChangeEvent::Logoff,
+          SessionChangeReason::SessionLock => SessionChangeEvent::Lock,
+          SessionChangeReason::SessionUnlock => SessionChangeEvent::Unlock,
+          _ => return ServiceControlHandlerResult::NoError,
+        };
+
+        info!("Windows service: Session change {:?}", session_change_event);
+        request_sender.send(Event::OrchestratorSessionChanged(session_change_event)).unwrap();
+        ServiceControlHandlerResult::NoError
+      }
+      ServiceControl::UserEvent(user_event_code) => {
+        let (event, operation_name) = match user_event_code.to_raw() {
+          RELOAD_CONTROL_CODE => (Event::OrchestratorRequestReload(), Some("reload")),
+          ROLLING_RESTART_CONTROL_CODE => (Event::OrchestratorRequestRollingRestart(), Some("rolling_restart")),
+          STATUS_DUMP_CONTROL_CODE => (Event::OrchestratorRequestStatusDump(), None),
+          _ => return ServiceControlHandlerResult::NotImplemented,
+        };
+
+        info!("Windows service: Control code {} received", user_event_code.to_raw());
+        if let Some(operation_name) = operation_name {
+          audit_log.record("service_control", SERVICE_CONTROL_CALLER, operation_name, None, Ok(()));
+        }
+        request_sender.send(event).unwrap();
+        ServiceControlHandlerResult::NoError
+      }
       _ => ServiceControlHandlerResult::NotImplemented,
     }
   };
 
   info!("Windows service: Starting");
-  let status_sender = service_control_handler::register("process-orchestrator", event_handler)?;
+  let status_sender = service_control_handler::register(&service_name, event_handler)?;
+
+  *status_handle.lock().unwrap() = Some(status_sender);
 
   info!("Windows service: Started");
-  status_sender.set_service_status(ServiceStatus {
-    service_type: ServiceType::OWN_PROCESS,
-    current_state: ServiceState::Running,
-    controls_accepted: ServiceControlAccept::STOP,
-    exit_code: ServiceExitCode::Win32(0),
-    checkpoint: 0,
-    wait_hint: Duration::default(),
-    process_id: None,
-  })?;
+  status_sender.set_service_status(get_service_status(ServiceState::Running))?;
+
+  let event_log = EventLog::new(&service_name);
 
   info!("Windows service: Running");
+  event_log.info(SERVICE_STARTED_EVENT_ID, &format!("Service [{}] started", &service_name));
   event_pump.run();
 
   info!("Windows service: Stopped");
-  status_sender.set_service_status(ServiceStatus {
+  event_log.info(SERVICE_STOPPED_EVENT_ID, &format!("Service [{}] stopped", &service_name));
+  let mut status_handle_guard = status_handle.lock().unwrap();
+  *status_handle_guard = None;
+  status_sender.set_service_status(get_service_status(ServiceState::Stopped))?;
+  drop(status_handle_guard);
+
+  stopped_event_sender.send(())?;
+
+  Ok(())
+}
+
+/// Reports the state of the service, unless it has already stopped.
+#[cfg(windows)]
+fn report_service_state(status_handle: &Mutex<Option<ServiceStatusHandle>>, current_state: ServiceState) {
+  if let Some(status_handle) = *status_handle.lock().unwrap() {
+    if let Err(set_status_error) = status_handle.set_service_status(get_service_status(current_state)) {
+      error!("Windows service: Could not report state {:?}: {:?}", current_state, set_status_error);
+    }
+  }
+}
+
+/// Reports the stop as pending with the checkpoint, unless the service has already stopped.
+#[cfg(windows)]
+fn report_stop_pending(status_handle: &Mutex<Option<ServiceStatusHandle>>, checkpoint: u32) {
+  if let Some(status_handle) = *status_handle.lock().unwrap() {
+    let stop_pending_status = ServiceStatus {
+      checkpoint,
+      wait_hint: STOP_PENDING_WAIT_HINT,
+      ..get_service_status(ServiceState::StopPending)
+    };
+
+    if let Err(set_status_error) = status_handle.set_service_status(stop_pending_status) {
+      error!("Windows service: Could not report stop progress: {:?}", set_status_error);
+    }
+  }
+}
+
+/// Reports the progress of the stop within every wait hint, until the event pump has stopped.
+#[cfg(windows)]
+fn report_stop_progress(status_handle: &Mutex<Option<ServiceStatusHandle>>, stopped_event_receiver: &Receiver<()>) {
+  let mut checkpoint = 1;
+
+  while let Err(RecvTimeoutError::Timeout) = stopped_event_receiver.recv_timeout(STOP_PENDING_WAIT_HINT / 2) {
+    checkpoint += 1;
+    report_stop_pending(status_handle, checkpoint);
+  }
+}
+
+/// Options of the service: its start parameters (the first argument is the service name) when any
+/// are given, e.g. `sc start my-service --config-directory D:\ops\stack-a`, otherwise the command
+/// line it was installed with. Either way, the instance is the one the SCM started the service as.
+#[cfg(windows)]
+fn get_service_cli_options(arguments: Vec<OsString>, orchestrator_config: &OrchestratorConfig) -> Result<CliOptions, Box<dyn Error>> {
+  let mut arguments = arguments.into_iter();
+  let scm_service_name = arguments.next();
+  let start_parameters = arguments.collect::<Vec<OsString>>();
+  let start_parameters_given = !start_parameters.is_empty();
+
+  let cli_options = match start_parameters_given {
+    false => CliOptions::from_args(),
+    true => {
+      let executable_path = std::env::current_exe()?.into_os_string();
+      CliOptions::from_iter_safe(std::iter::once(executable_path).chain(start_parameters))?
+    }
+  };
+
+  let cli_options = match scm_service_name {
+    Some(scm_service_name) => CliOptions { service_name: get_instance_name(&scm_service_name, orchestrator_config), ..cli_options },
+    None => cli_options,
+  };
+
+  if start_parameters_given {
+    info!("Windows service: Using start parameters {:?}", &cli_options);
+  }
+
+  Ok(cli_options)
+}
+
+/// Name of the instance running as the service, none for the default instance, as it is installed
+/// with `--service-name` only when it is not named after the default.
+#[cfg(windows)]
+fn get_instance_name(service_name: &OsStr, orchestrator_config: &OrchestratorConfig) -> Option<String> {
+  let service_name = service_name.to_string_lossy().to_string();
+  (service_name != get_service_name(None, orchestrator_config)).then_some(service_name)
+}
+
+#[cfg(windows)]
+fn get_service_status(current_state: ServiceState) -> ServiceStatus {
+  let controls_accepted = match current_state {
+    ServiceState::Stopped | ServiceState::StopPending => ServiceControlAccept::empty(),
+    _ => ServiceControlAccept::STOP | ServiceControlAccept::PAUSE_CONTINUE | ServiceControlAccept::PRESHUTDOWN | ServiceControlAccept::POWER_EVENT | ServiceControlAccept::SESSION_CHANGE,
+  };
+
+  ServiceStatus {
     service_type: ServiceType::OWN_PROCESS,
-    current_state: ServiceState::Stopped,
-    controls_accepted: ServiceControlAccept::empty(),
+    current_state,
+    controls_accepted,
     exit_code: ServiceExitCode::Win32(0),
     checkpoint: 0,
     wait_hint: Duration::default(),
     process_id: None,
-  })?;
-
-  stopped_event_sender.send(())?;
-
-  Ok(())
+  }
 }
 
-pub fn get_service_name() -> String {
+/// The `--service-name` of the instance, the service name from `orchestrator.yml`, or else the
+/// executable name.
+pub fn get_service_name(cli_service_name: Option<&str>, orchestrator_config: &OrchestratorConfig) -> String {
+  if let Some(service_name) = cli_service_name.or(orchestrator_config.service.name.as_deref()) {
+    return service_name.to_string();
+  }
+
   let executable_path = std::env::current_exe().unwrap();
   let executable_name = executable_path.file_stem().unwrap().to_str().unwrap();
   return String::from(executable_name);
-}
\ No newline at end of file
+}
+
+/// How the orchestrator is registered with the service control manager.
+pub struct ServiceInstallation {
+  pub name: String,
+  pub display_name: String,
+  pub description: String,
+  pub start_type: ServiceStartType,
+  pub delayed_auto_start: bool,
+  pub dependencies: Vec<String>,
+  pub account: Option<String>,
+  pub password: Option<String>,
+  pub launch_arguments: Vec<OsString>,
+  pub recovery: ServiceRecoveryConfig,
+  pub preshutdown_timeout_secs: Option<u64>,
+}
+
+/// Default period without a crash after which the service control manager resets the failure count.
+const DEFAULT_FAILURE_RESET_PERIOD_SECS: u64 = 86400;
+
+/// Creates the Windows service running this executable.
+#[cfg(windows)]
+pub fn install_service(service_installation: &ServiceInstallation) -> Result<(), Box<dyn Error>> {
+  let service_manager = ServiceManager::local_computer(None::<&str>, ServiceManagerAccess::CONNECT | ServiceManagerAccess::CREATE_SERVICE)?;
+
+  let service_info = ServiceInfo {
+    name: OsString::from(&service_installation.name),
+    display_name: OsString::from(&service_installation.display_name),
+    service_type: ServiceType::OWN_PROCESS,
+    start_type: service_installation.start_type,
+    error_control: ServiceErrorControl::Normal,
+    executable_path: std::env::current_exe()?,
+    launch_arguments: service_installation.launch_arguments.clone(),
+    dependencies: service_installation.dependencies.iter().map(|x| ServiceDependency::Service(OsString::from(x))).collect(),
+    account_name: service_installation.account.as_ref().map(OsString::from),
+    account_password: service_installation.password.as_ref().map(OsString::from),
+  };
+
+  let service = service_manager.create_service(&service_info, ServiceAccess::CHANGE_CONFIG | ServiceAccess::START)?;
+  service.set_description(&service_installation.description)?;
+  install_event_source(&service_installation.name)?;
+
+  if let Err(install_performance_counters_error) = install_performance_counters() {
+    warn!("Windows service: Could not register performance counters: {}", install_performance_counters_error);
+  }
+
+  if let Some(preshutdown_timeout_secs) = service_installation.preshutdown_timeout_secs {
+    service.set_preshutdown_timeout(Duration::from_secs(preshutdown_timeout_secs))?;
+  }
+
+  if let Some(failure_actions) = get_failure_actions(&service_installation.recovery) {
+    service.update_failure_actions(failure_actions)?;
+  }
+
+  if service_installation.delayed_auto_start && service_installation.start_type == ServiceStartType::AutoStart {
+    service.set_delayed_auto_start(true)?;
+  }
+
+  Ok(())
+}
+
+/// Recovery actions of the service: restarting it after the first two failures and running the
+/// command after the third, or doing only one of those on every failure.
+fn get_failure_actions(recovery: &ServiceRecoveryConfig) -> Option<ServiceFailureActions> {
+  let restart_action = recovery.restart_delay_secs.map(|x| ServiceAction {
+    action_type: ServiceActionType::Restart,
+    delay: Duration::from_secs(x),
+  });
+
+  let command_action = recovery.command.as_ref().map(|_| ServiceAction {
+    action_type: ServiceActionType::RunCommand,
+    delay: Duration::default(),
+  });
+
+  let actions = match (restart_action, command_action) {
+    (Some(restart_action), Some(command_action)) => vec![restart_action.clone(), restart_action, command_action],
+    (Some(restart_action), None) => vec![restart_action.clone(), restart_action.clone(), restart_action],
+    (None, Some(command_action)) => vec![command_action.clone(), command_action.clone(), command_action],
+    (None, None) => return None,
+  };
+
+  let reset_period_secs = recovery.reset_period_secs.unwrap_or(DEFAULT_FAILURE_RESET_PERIOD_SECS);
+
+  Some(ServiceFailureActions {
+    reset_period: ServiceFailureResetPeriod::After(Duration::from_secs(reset_period_secs)),
+    reboot_msg: None,
+    command: recovery.command.as_ref().map(OsString::from),
+    actions: Some(actions),
+  })
+}
+
+/// Stops the Windows service when it is running, then deletes it.
+#[cfg(windows)]
+pub fn uninstall_service(service_name: &str) -> Result<(), Box<dyn Error>> {
+  let service_manager = ServiceManager::local_computer(None::<&str>, ServiceManagerAccess::CONNECT)?;
+  let service = service_manager.open_service(service_name, ServiceAccess::QUERY_STATUS | ServiceAccess::STOP | ServiceAccess::DELETE)?;
+
+  if service.query_status()?.current_state != ServiceState::Stopped {
+    info!("Windows service: Stopping [{}]", service_name);
+    service.stop()?;
+
+    while service.query_status()?.current_state != ServiceState::Stopped {
+      std::thread::sleep(Duration::from_millis(500));
+    }
+  }
+
+  service.delete()?;
+
+  if let Err(uninstall_event_source_error) = uninstall_event_source(service_name) {
+    warn!("Windows service: Could not remove event source [{}]: {}", service_name, uninstall_event_source_error);
+  }
+
+  if let Err(uninstall_performance_counters_error) = uninstall_performance_counters() {
+    warn!("Windows service: Could not remove performance counters: {}", uninstall_performance_counters_error);
+  }
+
+  Ok(())
+}